@@ -0,0 +1,798 @@
+//! Apple device tree (ADT) parser
+//!
+//! The flattened tree iBoot passes is read in place, nothing is copied or allocated.
+//! Each node is a header with its property and child counts, followed by its properties
+//! (32 bytes name, 32 bits size, value padded to 4 bytes) and then its children.
+#![cfg_attr(not(test), no_std)]
+
+use core::convert::TryInto;
+use core::slice::ChunksExact;
+
+const NODE_HEADER_SIZE: usize = 8;
+const PROPERTY_NAME_SIZE: usize = 32;
+const PROPERTY_HEADER_SIZE: usize = PROPERTY_NAME_SIZE + 4;
+/// The top bit of a property size is a flag set by iBoot on some properties.
+const PROPERTY_SIZE_MASK: u32 = 0x7FFF_FFFF;
+const ALIGNMENT: usize = 4;
+
+/// Deepest nesting accepted, real trees are far from it.
+const MAX_DEPTH: usize = 64;
+
+/// Defaults used by m1n1 when a node doesn't define its cells.
+const DEFAULT_ADDRESS_CELLS: u32 = 2;
+const DEFAULT_SIZE_CELLS: u32 = 1;
+const CELL_SIZE: usize = 4;
+/// Cells are read as 64 bits values.
+const MAX_CELLS: u32 = 2;
+
+fn read_u32(data: &[u8]) -> Option<u32> {
+    Some(u32::from_le_bytes(data.get(..4)?.try_into().ok()?))
+}
+
+fn read_u64(data: &[u8]) -> Option<u64> {
+    Some(u64::from_le_bytes(data.get(..8)?.try_into().ok()?))
+}
+
+/// Read a value made of `cells` little endian 32 bits cells, the first being the lowest.
+fn read_cells(data: &[u8], cells: usize) -> u64 {
+    let mut value = [0x0u8; 8];
+
+    value[..cells * CELL_SIZE].copy_from_slice(&data[..cells * CELL_SIZE]);
+
+    u64::from_le_bytes(value)
+}
+
+/// String up to its terminator, empty if it isn't valid UTF-8.
+fn read_str(data: &[u8]) -> &str {
+    let size = data
+        .iter()
+        .position(|value| *value == 0)
+        .unwrap_or(data.len());
+
+    core::str::from_utf8(&data[..size]).unwrap_or("")
+}
+
+fn get_path_components(path: &str) -> impl Iterator<Item = &str> {
+    path.split('/').filter(|name| !name.is_empty())
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Property<'a> {
+    name: &'a str,
+    value: &'a [u8],
+}
+
+impl<'a> Property<'a> {
+    /// Parse a property, also returning its size including padding.
+    fn parse(data: &'a [u8]) -> Option<(Self, usize)> {
+        let name = read_str(data.get(..PROPERTY_NAME_SIZE)?);
+        let size = (read_u32(&data[PROPERTY_NAME_SIZE..])? & PROPERTY_SIZE_MASK) as usize;
+        let value = data.get(PROPERTY_HEADER_SIZE..PROPERTY_HEADER_SIZE + size)?;
+        let total_size = (PROPERTY_HEADER_SIZE + size + ALIGNMENT - 1) & !(ALIGNMENT - 1);
+
+        Some((Property { name, value }, total_size))
+    }
+
+    pub fn get_name(&self) -> &'a str {
+        self.name
+    }
+
+    pub fn get_value(&self) -> &'a [u8] {
+        self.value
+    }
+
+    pub fn as_u32(&self) -> Option<u32> {
+        read_u32(self.value)
+    }
+
+    pub fn as_u64(&self) -> Option<u64> {
+        read_u64(self.value)
+    }
+
+    pub fn as_str(&self) -> &'a str {
+        read_str(self.value)
+    }
+
+    /// Iterate over a list of zero terminated strings.
+    pub fn as_str_list(&self) -> StrList<'a> {
+        StrList { data: self.value }
+    }
+}
+
+/// Iterator over the zero terminated strings of a property.
+#[derive(Clone)]
+pub struct StrList<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Iterator for StrList<'a> {
+    type Item = &'a str;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.data.is_empty() {
+            let size = self
+                .data
+                .iter()
+                .position(|value| *value == 0)
+                .unwrap_or(self.data.len());
+            let value = &self.data[..size];
+
+            self.data = self.data.get(size + 1..).unwrap_or(&[]);
+
+            if !value.is_empty() {
+                return Some(core::str::from_utf8(value).unwrap_or(""));
+            }
+        }
+
+        None
+    }
+}
+
+pub struct Properties<'a> {
+    data: &'a [u8],
+    remaining: usize,
+}
+
+impl<'a> Iterator for Properties<'a> {
+    type Item = Property<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+
+        let (property, size) = Property::parse(self.data)?;
+
+        self.data = self.data.get(size..).unwrap_or(&[]);
+        self.remaining -= 1;
+
+        Some(property)
+    }
+}
+
+pub struct Children<'a> {
+    data: &'a [u8],
+    remaining: usize,
+}
+
+impl<'a> Iterator for Children<'a> {
+    type Item = Node<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+
+        let node = Node { data: self.data };
+
+        self.data = self.data.get(node.get_size(0)?..).unwrap_or(&[]);
+        self.remaining -= 1;
+
+        Some(node)
+    }
+}
+
+/// Entry of a `reg` property.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Reg {
+    pub address: u64,
+    pub size: u64,
+}
+
+pub struct RegIter<'a> {
+    entries: ChunksExact<'a, u8>,
+    address_cells: usize,
+}
+
+impl<'a> Iterator for RegIter<'a> {
+    type Item = Reg;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let entry = self.entries.next()?;
+        let (address, size) = entry.split_at(self.address_cells * CELL_SIZE);
+
+        Some(Reg {
+            address: read_cells(address, self.address_cells),
+            size: read_cells(size, size.len() / CELL_SIZE),
+        })
+    }
+}
+
+/// Entry of a `ranges` property, mapping a range of child addresses to the parent's address space.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Range {
+    pub child_address: u64,
+    pub parent_address: u64,
+    pub size: u64,
+}
+
+impl Range {
+    /// Translate a region contained in this range to the parent's address space.
+    pub fn translate(&self, reg: &Reg) -> Option<Reg> {
+        let offset = reg.address.checked_sub(self.child_address)?;
+
+        if offset.checked_add(reg.size)? > self.size {
+            return None;
+        }
+
+        Some(Reg {
+            address: self.parent_address.checked_add(offset)?,
+            size: reg.size,
+        })
+    }
+}
+
+pub struct RangeIter<'a> {
+    entries: ChunksExact<'a, u8>,
+    child_address_cells: usize,
+    parent_address_cells: usize,
+}
+
+impl<'a> Iterator for RangeIter<'a> {
+    type Item = Range;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let entry = self.entries.next()?;
+        let (child_address, entry) = entry.split_at(self.child_address_cells * CELL_SIZE);
+        let (parent_address, size) = entry.split_at(self.parent_address_cells * CELL_SIZE);
+
+        Some(Range {
+            child_address: read_cells(child_address, self.child_address_cells),
+            parent_address: read_cells(parent_address, self.parent_address_cells),
+            size: read_cells(size, size.len() / CELL_SIZE),
+        })
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct Node<'a> {
+    /// Data starting at the node header, possibly extending past the node.
+    data: &'a [u8],
+}
+
+impl<'a> Node<'a> {
+    fn get_property_count(&self) -> usize {
+        read_u32(self.data).unwrap_or(0) as usize
+    }
+
+    fn get_child_count(&self) -> usize {
+        read_u32(&self.data[4..]).unwrap_or(0) as usize
+    }
+
+    /// Size of the properties, None if they are truncated.
+    fn get_properties_size(&self) -> Option<usize> {
+        let mut offset = NODE_HEADER_SIZE;
+
+        for _ in 0..self.get_property_count() {
+            let (_, size) = Property::parse(self.data.get(offset..)?)?;
+
+            offset += size;
+        }
+
+        Some(offset)
+    }
+
+    /// Size of the node including its children, None if any of them is truncated.
+    fn get_size(&self, depth: usize) -> Option<usize> {
+        if depth > MAX_DEPTH || self.data.len() < NODE_HEADER_SIZE {
+            return None;
+        }
+
+        let mut offset = self.get_properties_size()?;
+
+        for _ in 0..self.get_child_count() {
+            let child = Node {
+                data: self.data.get(offset..)?,
+            };
+
+            offset += child.get_size(depth + 1)?;
+        }
+
+        Some(offset)
+    }
+
+    pub fn properties(&self) -> Properties<'a> {
+        Properties {
+            data: &self.data[NODE_HEADER_SIZE..],
+            remaining: self.get_property_count(),
+        }
+    }
+
+    pub fn children(&self) -> Children<'a> {
+        let offset = self.get_properties_size().unwrap_or(self.data.len());
+
+        Children {
+            data: &self.data[offset..],
+            remaining: self.get_child_count(),
+        }
+    }
+
+    pub fn get_property(&self, name: &str) -> Option<Property<'a>> {
+        self.properties().find(|property| property.name == name)
+    }
+
+    /// Value of the `name` property, empty if there is none.
+    pub fn get_name(&self) -> &'a str {
+        self.get_property("name")
+            .map(|property| property.as_str())
+            .unwrap_or("")
+    }
+
+    pub fn get_child(&self, name: &str) -> Option<Node<'a>> {
+        self.children().find(|child| child.get_name() == name)
+    }
+
+    /// Find a node from a path relative to this one, like `arm-io/uart0`.
+    pub fn find_node(&self, path: &str) -> Option<Node<'a>> {
+        get_path_components(path).try_fold(*self, |node, name| node.get_child(name))
+    }
+
+    pub fn compatible(&self) -> StrList<'a> {
+        match self.get_property("compatible") {
+            Some(property) => property.as_str_list(),
+            None => StrList { data: &[] },
+        }
+    }
+
+    pub fn is_compatible(&self, compatible: &str) -> bool {
+        self.compatible().any(|value| value == compatible)
+    }
+
+    /// Number of cells used by the addresses of the children.
+    pub fn get_address_cells(&self) -> u32 {
+        self.get_property("#address-cells")
+            .and_then(|property| property.as_u32())
+            .unwrap_or(DEFAULT_ADDRESS_CELLS)
+    }
+
+    /// Number of cells used by the sizes of the children.
+    pub fn get_size_cells(&self) -> u32 {
+        self.get_property("#size-cells")
+            .and_then(|property| property.as_u32())
+            .unwrap_or(DEFAULT_SIZE_CELLS)
+    }
+
+    /// Entries of the `reg` property, in the address space of `parent`.
+    ///
+    /// None if there is no such property or its cells cannot be read.
+    pub fn reg(&self, parent: &Node<'a>) -> Option<RegIter<'a>> {
+        let address_cells = parent.get_address_cells();
+        let size_cells = parent.get_size_cells();
+
+        if address_cells > MAX_CELLS || size_cells > MAX_CELLS {
+            return None;
+        }
+
+        let entry_size = (address_cells + size_cells) as usize * CELL_SIZE;
+
+        if entry_size == 0 {
+            return None;
+        }
+
+        Some(RegIter {
+            entries: self.get_property("reg")?.value.chunks_exact(entry_size),
+            address_cells: address_cells as usize,
+        })
+    }
+
+    /// Entries of the `ranges` property, mapping the address space of this node to the one of `parent`.
+    ///
+    /// None if there is no such property or its cells cannot be read.
+    pub fn ranges(&self, parent: &Node<'a>) -> Option<RangeIter<'a>> {
+        let child_address_cells = self.get_address_cells();
+        let parent_address_cells = parent.get_address_cells();
+        let size_cells = self.get_size_cells();
+
+        if child_address_cells > MAX_CELLS
+            || parent_address_cells > MAX_CELLS
+            || size_cells > MAX_CELLS
+        {
+            return None;
+        }
+
+        let entry_size =
+            (child_address_cells + parent_address_cells + size_cells) as usize * CELL_SIZE;
+
+        if entry_size == 0 {
+            return None;
+        }
+
+        Some(RangeIter {
+            entries: self.get_property("ranges")?.value.chunks_exact(entry_size),
+            child_address_cells: child_address_cells as usize,
+            parent_address_cells: parent_address_cells as usize,
+        })
+    }
+}
+
+/// Apple device tree read from a flattened blob.
+#[derive(Clone, Copy)]
+pub struct Adt<'a> {
+    root: Node<'a>,
+}
+
+impl<'a> Adt<'a> {
+    /// Check the whole tree is contained in `data`, returning None if it's truncated or malformed.
+    pub fn new(data: &'a [u8]) -> Option<Self> {
+        let root = Node { data };
+
+        root.get_size(0)?;
+
+        Some(Adt { root })
+    }
+
+    pub fn get_root(&self) -> Node<'a> {
+        self.root
+    }
+
+    /// Find a node from its path, like `/arm-io/uart0`.
+    pub fn find_node(&self, path: &str) -> Option<Node<'a>> {
+        self.root.find_node(path)
+    }
+
+    /// Entry `index` of the `reg` property of a node, translated to a physical address.
+    ///
+    /// The entry goes through the `ranges` of every ancestor below the root, translation stops
+    /// at the first one without any, like m1n1's `adt_get_reg`. An empty `ranges` is an identity
+    /// mapping. None if there is no such entry or it falls outside of an ancestor's ranges.
+    pub fn get_reg(&self, path: &str, index: usize) -> Option<Reg> {
+        let mut nodes = [self.root; MAX_DEPTH + 1];
+        let mut depth = 0;
+
+        for name in get_path_components(path) {
+            let child = nodes[depth].get_child(name)?;
+
+            depth += 1;
+            *nodes.get_mut(depth)? = child;
+        }
+
+        if depth == 0 {
+            return None;
+        }
+
+        let mut reg = nodes[depth].reg(&nodes[depth - 1])?.nth(index)?;
+
+        for depth in (1..depth).rev() {
+            let node = &nodes[depth];
+
+            match node.get_property("ranges") {
+                None => break,
+                Some(ranges) if ranges.value.is_empty() => continue,
+                Some(_) => {}
+            }
+
+            reg = node
+                .ranges(&nodes[depth - 1])?
+                .find_map(|range| range.translate(&reg))?;
+        }
+
+        Some(reg)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Synthetic tree following the layout of a Mac mini (J274), see testdata/make_adt.py.
+    const J274: &[u8] = include_bytes!("../testdata/j274.adt");
+
+    fn get_adt() -> Adt<'static> {
+        Adt::new(J274).unwrap()
+    }
+
+    /// Tree of a real Mac mini, saved with `proxyclient dump-adt adt/testdata/j274-captured.adt`.
+    fn read_captured() -> Vec<u8> {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/j274-captured.adt");
+
+        std::fs::read(path).unwrap_or_else(|error| panic!("Cannot read {}: {}", path, error))
+    }
+
+    #[test]
+    fn root_properties() {
+        let root = get_adt().get_root();
+
+        assert_eq!(root.get_name(), "device-tree");
+        assert_eq!(root.get_property("model").unwrap().as_str(), "Macmini9,1");
+        assert_eq!(
+            root.get_property("#address-cells").unwrap().as_u32(),
+            Some(2)
+        );
+        assert!(root.get_property("missing").is_none());
+
+        let names: Vec<&str> = root
+            .properties()
+            .map(|property| property.get_name())
+            .collect();
+
+        assert_eq!(
+            names,
+            [
+                "name",
+                "compatible",
+                "model",
+                "target-type",
+                "#address-cells",
+                "#size-cells"
+            ]
+        );
+    }
+
+    #[test]
+    fn children() {
+        let adt = get_adt();
+        let names: Vec<&str> = adt
+            .get_root()
+            .children()
+            .map(|node| node.get_name())
+            .collect();
+
+        assert_eq!(names, ["chosen", "arm-io", "cpus"]);
+
+        let names: Vec<&str> = adt
+            .find_node("/arm-io")
+            .unwrap()
+            .children()
+            .map(|node| node.get_name())
+            .collect();
+
+        assert_eq!(names, ["uart0", "uart2", "aic", "pmgr", "wdt", "apcie"]);
+    }
+
+    #[test]
+    fn path_lookup() {
+        let adt = get_adt();
+
+        assert_eq!(adt.find_node("/").unwrap().get_name(), "device-tree");
+        assert_eq!(adt.find_node("/arm-io/uart2").unwrap().get_name(), "uart2");
+        assert_eq!(adt.find_node("arm-io/wdt").unwrap().get_name(), "wdt");
+        assert_eq!(
+            adt.find_node("/chosen")
+                .unwrap()
+                .get_property("firmware-version")
+                .unwrap()
+                .as_str(),
+            "iBoot-6723.61.3"
+        );
+        assert!(adt.find_node("/arm-io/uart1").is_none());
+        assert!(adt.find_node("/arm-io/uart0/child").is_none());
+    }
+
+    #[test]
+    fn compatible() {
+        let adt = get_adt();
+        let root = adt.get_root();
+
+        assert_eq!(
+            root.compatible().collect::<Vec<_>>(),
+            ["J274AP", "AppleARM"]
+        );
+        assert!(root.is_compatible("AppleARM"));
+        assert!(!root.is_compatible("Apple"));
+
+        let wdt = adt.find_node("/arm-io/wdt").unwrap();
+
+        assert!(wdt.is_compatible("wdt,s5l8960x"));
+        assert_eq!(adt.find_node("/chosen").unwrap().compatible().count(), 0);
+    }
+
+    #[test]
+    fn reg() {
+        let adt = get_adt();
+        let arm_io = adt.find_node("/arm-io").unwrap();
+        let uart0 = arm_io.get_child("uart0").unwrap();
+
+        assert_eq!(
+            uart0.reg(&arm_io).unwrap().collect::<Vec<_>>(),
+            [Reg {
+                address: 0x3520_0000,
+                size: 0x4000
+            }]
+        );
+
+        let pmgr = arm_io.get_child("pmgr").unwrap();
+
+        assert_eq!(
+            pmgr.reg(&arm_io).unwrap().collect::<Vec<_>>(),
+            [
+                Reg {
+                    address: 0x3B70_0000,
+                    size: 0x14000
+                },
+                Reg {
+                    address: 0x3D28_0000,
+                    size: 0xC000
+                }
+            ]
+        );
+
+        // Nodes not defining their cells use the defaults.
+        let chosen = adt.find_node("/chosen").unwrap();
+
+        assert_eq!(chosen.get_address_cells(), 2);
+        assert_eq!(chosen.get_size_cells(), 1);
+        assert!(chosen.reg(&adt.get_root()).is_none());
+    }
+
+    #[test]
+    fn ranges() {
+        let adt = get_adt();
+        let arm_io = adt.find_node("/arm-io").unwrap();
+
+        assert_eq!(
+            arm_io.ranges(&adt.get_root()).unwrap().collect::<Vec<_>>(),
+            [Range {
+                child_address: 0x0,
+                parent_address: 0x2_0000_0000,
+                size: 0x1_0000_0000
+            }]
+        );
+        assert!(adt.get_root().ranges(&adt.get_root()).is_none());
+    }
+
+    #[test]
+    fn truncated_tree() {
+        assert!(Adt::new(&J274[..J274.len() - 1]).is_none());
+        assert!(Adt::new(&J274[..4]).is_none());
+        assert!(Adt::new(&[]).is_none());
+    }
+
+    #[test]
+    fn oversized_property() {
+        let mut data = J274.to_vec();
+
+        // Size of the root name property.
+        data[NODE_HEADER_SIZE + PROPERTY_NAME_SIZE + 3] = 0x7F;
+
+        assert!(Adt::new(&data).is_none());
+    }
+
+    #[test]
+    fn translated_reg() {
+        let adt = get_adt();
+
+        assert_eq!(
+            adt.get_reg("/arm-io/uart0", 0),
+            Some(Reg {
+                address: 0x2_3520_0000,
+                size: 0x4000
+            })
+        );
+        assert_eq!(
+            adt.get_reg("/arm-io/pmgr", 1),
+            Some(Reg {
+                address: 0x2_3D28_0000,
+                size: 0xC000
+            })
+        );
+        assert_eq!(adt.get_reg("/arm-io/pmgr", 2), None);
+        assert_eq!(adt.get_reg("/arm-io/uart1", 0), None);
+        assert_eq!(adt.get_reg("/", 0), None);
+    }
+
+    #[test]
+    #[ignore = "needs a device tree captured from a Mac mini"]
+    fn captured_reg() {
+        let data = read_captured();
+        let adt = Adt::new(&data).unwrap();
+        let get_address = |path| adt.get_reg(path, 0).map(|reg| reg.address);
+
+        // Where m1n1 and Linux find these blocks on the M1.
+        assert_eq!(get_address("/arm-io/uart0"), Some(0x2_3520_0000));
+        assert_eq!(get_address("/arm-io/aic"), Some(0x2_3B10_0000));
+        assert_eq!(get_address("/arm-io/wdt"), Some(0x2_3D2B_0000));
+    }
+
+    #[test]
+    fn nested_ranges() {
+        let adt = get_adt();
+
+        // Through the second range of apcie, then the one of arm-io.
+        assert_eq!(
+            adt.get_reg("/arm-io/apcie/pcie-bridge0", 0),
+            Some(Reg {
+                address: 0x2_A000_4000,
+                size: 0x4000
+            })
+        );
+        assert_eq!(
+            adt.get_reg("/arm-io/apcie/pcie-bridge0", 1),
+            Some(Reg {
+                address: 0x2_9800_0000,
+                size: 0x1000
+            })
+        );
+        assert_eq!(adt.get_reg("/arm-io/apcie/pcie-bridge1", 0), None);
+    }
+
+    #[test]
+    #[ignore = "needs a device tree captured from a Mac mini"]
+    fn captured_nested_ranges() {
+        let data = read_captured();
+        let adt = Adt::new(&data).unwrap();
+        let root = adt.get_root();
+        let arm_io = adt.find_node("/arm-io").unwrap();
+        let windows: Vec<_> = arm_io
+            .ranges(&root)
+            .unwrap()
+            .map(|range| range.parent_address..range.parent_address.saturating_add(range.size))
+            .collect();
+        let mut count = 0;
+
+        // Devices behind a bus of their own, such as the PCIe bridges under apcie, go through both
+        // its ranges and the ones of arm-io.
+        for bus in arm_io.children() {
+            match bus.get_property("ranges") {
+                Some(ranges) if !ranges.get_value().is_empty() => {}
+                _ => continue,
+            }
+
+            for device in bus
+                .children()
+                .filter(|device| device.get_property("reg").is_some())
+            {
+                let path = format!("/arm-io/{}/{}", bus.get_name(), device.get_name());
+                let reg = adt
+                    .get_reg(&path, 0)
+                    .unwrap_or_else(|| panic!("{} isn't translated", path));
+
+                assert!(
+                    windows.iter().any(|window| window.contains(&reg.address)),
+                    "{} at 0x{:x}",
+                    path,
+                    reg.address
+                );
+                count += 1;
+            }
+        }
+
+        assert_ne!(count, 0);
+    }
+
+    #[test]
+    fn missing_ranges() {
+        let adt = get_adt();
+
+        assert_eq!(
+            adt.get_reg("/cpus/cpu1", 0),
+            Some(Reg {
+                address: 1,
+                size: 0
+            })
+        );
+    }
+
+    #[test]
+    fn range_translation() {
+        let range = Range {
+            child_address: 0x1000,
+            parent_address: 0x8000,
+            size: 0x1000,
+        };
+
+        assert_eq!(
+            range.translate(&Reg {
+                address: 0x1800,
+                size: 0x800
+            }),
+            Some(Reg {
+                address: 0x8800,
+                size: 0x800
+            })
+        );
+        assert_eq!(
+            range.translate(&Reg {
+                address: 0x1800,
+                size: 0x801
+            }),
+            None
+        );
+        assert_eq!(
+            range.translate(&Reg {
+                address: 0xFFF,
+                size: 0x10
+            }),
+            None
+        );
+    }
+}
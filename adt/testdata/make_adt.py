@@ -0,0 +1,122 @@
+#!/usr/bin/env python3
+# Generate j274.adt, a synthetic Apple device tree following the layout and
+# addresses of a Mac mini (J274) tree, used by the adt unit tests.
+
+import struct
+import sys
+
+
+def prop(name, value):
+    if isinstance(value, str):
+        value = value.encode() + b"\0"
+
+    data = name.encode().ljust(32, b"\0") + struct.pack("<I", len(value)) + value
+
+    return data + b"\0" * (-len(data) % 4)
+
+
+def u32(*values):
+    return struct.pack("<%dI" % len(values), *values)
+
+
+def u64(*values):
+    return struct.pack("<%dQ" % len(values), *values)
+
+
+def node(properties, children=()):
+    data = struct.pack("<II", len(properties), len(children))
+    data += b"".join(prop(name, value) for name, value in properties)
+
+    return data + b"".join(children)
+
+
+uart = lambda index, address: node(
+    [
+        ("name", "uart%d" % index),
+        ("compatible", "uart-1,samsung"),
+        ("device_type", "uart"),
+        ("reg", u64(address, 0x4000)),
+    ]
+)
+
+arm_io = node(
+    [
+        ("name", "arm-io"),
+        ("compatible", "arm-io,t8103"),
+        ("#address-cells", u32(2)),
+        ("#size-cells", u32(2)),
+        ("ranges", u64(0x0, 0x2_0000_0000, 0x1_0000_0000)),
+    ],
+    [
+        uart(0, 0x3520_0000),
+        uart(2, 0x3520_8000),
+        node(
+            [
+                ("name", "aic"),
+                ("compatible", "aic,1"),
+                ("reg", u64(0x3B10_0000, 0x10000)),
+            ]
+        ),
+        node(
+            [
+                ("name", "pmgr"),
+                ("compatible", "pmgr1,t8103"),
+                ("reg", u64(0x3B70_0000, 0x14000, 0x3D28_0000, 0xC000)),
+            ]
+        ),
+        node(
+            [
+                ("name", "wdt"),
+                ("compatible", "wdt,t8101\0wdt,s5l8960x"),
+                ("reg", u64(0x3D2B_0000, 0x4000)),
+            ]
+        ),
+        # Nested bus with its own ranges, the second one isn't identity mapped.
+        node(
+            [
+                ("name", "apcie"),
+                ("compatible", "apcie,t8103"),
+                ("#address-cells", u32(2)),
+                ("#size-cells", u32(2)),
+                (
+                    "ranges",
+                    u64(0x0, 0x9000_0000, 0x1000_0000, 0x1000_0000, 0xA000_0000, 0x100_0000),
+                ),
+            ],
+            [
+                node(
+                    [
+                        ("name", "pcie-bridge0"),
+                        ("reg", u64(0x1000_4000, 0x4000, 0x0800_0000, 0x1000)),
+                    ]
+                ),
+                # Outside of the bus ranges.
+                node([("name", "pcie-bridge1"), ("reg", u64(0x2000_0000, 0x4000))]),
+            ],
+        ),
+    ],
+)
+
+# Without ranges, cpu registers are identifiers rather than addresses.
+cpus = node(
+    [("name", "cpus"), ("#address-cells", u32(1)), ("#size-cells", u32(0))],
+    [node([("name", "cpu%d" % index), ("reg", u32(index))]) for index in range(2)],
+)
+
+root = node(
+    [
+        ("name", "device-tree"),
+        ("compatible", "J274AP\0AppleARM"),
+        ("model", "Macmini9,1"),
+        ("target-type", "J274"),
+        ("#address-cells", u32(2)),
+        ("#size-cells", u32(2)),
+    ],
+    [
+        node([("name", "chosen"), ("firmware-version", "iBoot-6723.61.3")]),
+        arm_io,
+        cpus,
+    ],
+)
+
+sys.stdout.buffer.write(root)
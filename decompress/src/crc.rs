@@ -0,0 +1,94 @@
+//! CRC32 and CRC64 as used by gzip and xz
+
+const CRC32_POLYNOMIAL: u32 = 0xEDB8_8320;
+const CRC64_POLYNOMIAL: u64 = 0xC96C_5795_D787_0F42;
+
+const fn make_crc32_table() -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut i = 0;
+
+    while i < table.len() {
+        let mut value = i as u32;
+        let mut bit = 0;
+
+        while bit < 8 {
+            value = if value & 1 != 0 {
+                CRC32_POLYNOMIAL ^ (value >> 1)
+            } else {
+                value >> 1
+            };
+            bit += 1;
+        }
+
+        table[i] = value;
+        i += 1;
+    }
+
+    table
+}
+
+const fn make_crc64_table() -> [u64; 256] {
+    let mut table = [0; 256];
+    let mut i = 0;
+
+    while i < table.len() {
+        let mut value = i as u64;
+        let mut bit = 0;
+
+        while bit < 8 {
+            value = if value & 1 != 0 {
+                CRC64_POLYNOMIAL ^ (value >> 1)
+            } else {
+                value >> 1
+            };
+            bit += 1;
+        }
+
+        table[i] = value;
+        i += 1;
+    }
+
+    table
+}
+
+const CRC32_TABLE: [u32; 256] = make_crc32_table();
+const CRC64_TABLE: [u64; 256] = make_crc64_table();
+
+/// Continue a CRC32 computation with more data.
+pub fn crc32_update(crc: u32, data: &[u8]) -> u32 {
+    let mut crc = !crc;
+
+    for value in data {
+        crc = CRC32_TABLE[((crc ^ u32::from(*value)) & 0xFF) as usize] ^ (crc >> 8);
+    }
+
+    !crc
+}
+
+pub fn crc32(data: &[u8]) -> u32 {
+    crc32_update(0, data)
+}
+
+pub fn crc64(data: &[u8]) -> u64 {
+    let mut crc = !0u64;
+
+    for value in data {
+        crc = CRC64_TABLE[((crc ^ u64::from(*value)) & 0xFF) as usize] ^ (crc >> 8);
+    }
+
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_values() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(crc32_update(crc32(b"12345"), b"6789"), 0xCBF4_3926);
+        assert_eq!(crc64(b""), 0);
+        assert_eq!(crc64(b"123456789"), 0x995D_C9BB_DF19_39FA);
+    }
+}
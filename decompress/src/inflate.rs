@@ -0,0 +1,564 @@
+//! Deflate (RFC 1951) and gzip (RFC 1952) decoder
+//!
+//! Huffman codes are decoded canonically one bit at a time, which keeps the tables small.
+
+use crate::crc::crc32;
+use crate::{DecompressError, Result};
+
+const MAX_BITS: usize = 15;
+const MAX_LITERAL_CODES: usize = 286;
+const MAX_DISTANCE_CODES: usize = 30;
+const FIXED_LITERAL_CODES: usize = 288;
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+
+/// Order in which code length code lengths are stored.
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+const GZIP_MAGIC: [u8; 2] = [0x1F, 0x8B];
+const GZIP_METHOD_DEFLATE: u8 = 8;
+const GZIP_FLAG_HCRC: u8 = 1 << 1;
+const GZIP_FLAG_EXTRA: u8 = 1 << 2;
+const GZIP_FLAG_NAME: u8 = 1 << 3;
+const GZIP_FLAG_COMMENT: u8 = 1 << 4;
+const GZIP_FLAG_RESERVED: u8 = 0xE0;
+
+struct BitReader<'a> {
+    input: &'a [u8],
+    position: usize,
+    bit_buffer: u32,
+    bit_count: u32,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(input: &'a [u8]) -> Self {
+        BitReader {
+            input,
+            position: 0,
+            bit_buffer: 0,
+            bit_count: 0,
+        }
+    }
+
+    fn bits(&mut self, count: u32) -> Result<u32> {
+        while self.bit_count < count {
+            let value = *self
+                .input
+                .get(self.position)
+                .ok_or(DecompressError::UnexpectedEndOfInput)?;
+
+            self.position += 1;
+            self.bit_buffer |= u32::from(value) << self.bit_count;
+            self.bit_count += 8;
+        }
+
+        let value = self.bit_buffer & ((1 << count) - 1);
+
+        self.bit_buffer >>= count;
+        self.bit_count -= count;
+
+        Ok(value)
+    }
+
+    /// Drop the remaining bits of the current byte.
+    fn align(&mut self) {
+        self.bit_buffer = 0;
+        self.bit_count = 0;
+    }
+
+    fn bytes(&mut self, count: usize) -> Result<&'a [u8]> {
+        let end = self.position + count;
+        let data = self
+            .input
+            .get(self.position..end)
+            .ok_or(DecompressError::UnexpectedEndOfInput)?;
+
+        self.position = end;
+
+        Ok(data)
+    }
+}
+
+struct Output<'a> {
+    buffer: &'a mut [u8],
+    position: usize,
+}
+
+impl Output<'_> {
+    fn push(&mut self, value: u8) -> Result<()> {
+        *self
+            .buffer
+            .get_mut(self.position)
+            .ok_or(DecompressError::OutputTooSmall)? = value;
+
+        self.position += 1;
+
+        Ok(())
+    }
+
+    fn copy_from_slice(&mut self, data: &[u8]) -> Result<()> {
+        let end = self.position + data.len();
+
+        self.buffer
+            .get_mut(self.position..end)
+            .ok_or(DecompressError::OutputTooSmall)?
+            .copy_from_slice(data);
+
+        self.position = end;
+
+        Ok(())
+    }
+
+    /// Copy `length` bytes from `distance` bytes back, the source can overlap the destination.
+    fn copy_match(&mut self, distance: usize, length: usize) -> Result<()> {
+        if distance > self.position {
+            return Err(DecompressError::InvalidData);
+        }
+
+        if self.position + length > self.buffer.len() {
+            return Err(DecompressError::OutputTooSmall);
+        }
+
+        for i in self.position..self.position + length {
+            self.buffer[i] = self.buffer[i - distance];
+        }
+
+        self.position += length;
+
+        Ok(())
+    }
+}
+
+/// Canonical Huffman code.
+struct Huffman {
+    /// Number of codes of each length.
+    counts: [u16; MAX_BITS + 1],
+    /// Symbols ordered by code.
+    symbols: [u16; FIXED_LITERAL_CODES],
+}
+
+impl Huffman {
+    /// Build a code from the length of each symbol's code.
+    ///
+    /// Also returns the number of unused codes, non zero for incomplete codes.
+    fn new(lengths: &[u8]) -> Result<(Self, i32)> {
+        let mut huffman = Huffman {
+            counts: [0; MAX_BITS + 1],
+            symbols: [0; FIXED_LITERAL_CODES],
+        };
+
+        for length in lengths {
+            huffman.counts[*length as usize] += 1;
+        }
+
+        if huffman.counts[0] as usize == lengths.len() {
+            return Ok((huffman, 0));
+        }
+
+        let mut left = 1i32;
+
+        for count in huffman.counts.iter().skip(1) {
+            left <<= 1;
+            left -= i32::from(*count);
+
+            if left < 0 {
+                return Err(DecompressError::InvalidData);
+            }
+        }
+
+        let mut offsets = [0u16; MAX_BITS + 1];
+
+        for length in 1..MAX_BITS {
+            offsets[length + 1] = offsets[length] + huffman.counts[length];
+        }
+
+        for (symbol, length) in lengths.iter().enumerate() {
+            if *length != 0 {
+                let offset = &mut offsets[*length as usize];
+
+                huffman.symbols[*offset as usize] = symbol as u16;
+                *offset += 1;
+            }
+        }
+
+        Ok((huffman, left))
+    }
+
+    fn decode(&self, reader: &mut BitReader) -> Result<u16> {
+        let mut code = 0i32;
+        let mut first = 0i32;
+        let mut index = 0i32;
+
+        for count in self.counts.iter().skip(1) {
+            let count = i32::from(*count);
+
+            code |= reader.bits(1)? as i32;
+
+            if code - count < first {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+
+            index += count;
+            first += count;
+            first <<= 1;
+            code <<= 1;
+        }
+
+        Err(DecompressError::InvalidData)
+    }
+}
+
+fn inflate_codes(
+    reader: &mut BitReader,
+    output: &mut Output,
+    literal_code: &Huffman,
+    distance_code: &Huffman,
+) -> Result<()> {
+    loop {
+        let symbol = literal_code.decode(reader)? as usize;
+
+        if symbol < 256 {
+            output.push(symbol as u8)?;
+            continue;
+        }
+
+        if symbol == 256 {
+            return Ok(());
+        }
+
+        let symbol = symbol - 257;
+
+        if symbol >= LENGTH_BASE.len() {
+            return Err(DecompressError::InvalidData);
+        }
+
+        let length = usize::from(LENGTH_BASE[symbol])
+            + reader.bits(u32::from(LENGTH_EXTRA[symbol]))? as usize;
+
+        let symbol = distance_code.decode(reader)? as usize;
+
+        if symbol >= DISTANCE_BASE.len() {
+            return Err(DecompressError::InvalidData);
+        }
+
+        let distance = usize::from(DISTANCE_BASE[symbol])
+            + reader.bits(u32::from(DISTANCE_EXTRA[symbol]))? as usize;
+
+        output.copy_match(distance, length)?;
+    }
+}
+
+fn inflate_stored(reader: &mut BitReader, output: &mut Output) -> Result<()> {
+    reader.align();
+
+    let length = reader.bits(16)?;
+    let inverted_length = reader.bits(16)?;
+
+    if length != !inverted_length & 0xFFFF {
+        return Err(DecompressError::InvalidData);
+    }
+
+    output.copy_from_slice(reader.bytes(length as usize)?)
+}
+
+fn inflate_fixed(reader: &mut BitReader, output: &mut Output) -> Result<()> {
+    let mut lengths = [0u8; FIXED_LITERAL_CODES];
+
+    for (symbol, length) in lengths.iter_mut().enumerate() {
+        *length = match symbol {
+            0..=143 => 8,
+            144..=255 => 9,
+            256..=279 => 7,
+            _ => 8,
+        };
+    }
+
+    let (literal_code, _) = Huffman::new(&lengths)?;
+    let (distance_code, _) = Huffman::new(&[5; MAX_DISTANCE_CODES])?;
+
+    inflate_codes(reader, output, &literal_code, &distance_code)
+}
+
+fn inflate_dynamic(reader: &mut BitReader, output: &mut Output) -> Result<()> {
+    let literal_count = reader.bits(5)? as usize + 257;
+    let distance_count = reader.bits(5)? as usize + 1;
+    let code_length_count = reader.bits(4)? as usize + 4;
+
+    if literal_count > MAX_LITERAL_CODES || distance_count > MAX_DISTANCE_CODES {
+        return Err(DecompressError::InvalidData);
+    }
+
+    let mut lengths = [0u8; MAX_LITERAL_CODES + MAX_DISTANCE_CODES];
+
+    for symbol in CODE_LENGTH_ORDER.iter().take(code_length_count) {
+        lengths[*symbol] = reader.bits(3)? as u8;
+    }
+
+    let (code_length_code, left) = Huffman::new(&lengths[..CODE_LENGTH_ORDER.len()])?;
+
+    if left != 0 {
+        return Err(DecompressError::InvalidData);
+    }
+
+    let total_count = literal_count + distance_count;
+    let mut index = 0;
+
+    while index < total_count {
+        let symbol = code_length_code.decode(reader)?;
+
+        let (length, repeat) = match symbol {
+            0..=15 => (symbol as u8, 1),
+            16 => {
+                if index == 0 {
+                    return Err(DecompressError::InvalidData);
+                }
+
+                (lengths[index - 1], 3 + reader.bits(2)? as usize)
+            }
+            17 => (0, 3 + reader.bits(3)? as usize),
+            _ => (0, 11 + reader.bits(7)? as usize),
+        };
+
+        if index + repeat > total_count {
+            return Err(DecompressError::InvalidData);
+        }
+
+        for entry in lengths[index..index + repeat].iter_mut() {
+            *entry = length;
+        }
+
+        index += repeat;
+    }
+
+    // The end of block code is required.
+    if lengths[256] == 0 {
+        return Err(DecompressError::InvalidData);
+    }
+
+    let literal_lengths = &lengths[..literal_count];
+    let distance_lengths = &lengths[literal_count..total_count];
+
+    // Incomplete codes are only allowed when made of a single code.
+    let is_single_code = |code: &Huffman, count: usize| {
+        count == usize::from(code.counts[0]) + usize::from(code.counts[1])
+    };
+
+    let (literal_code, left) = Huffman::new(literal_lengths)?;
+
+    if left > 0 && !is_single_code(&literal_code, literal_count) {
+        return Err(DecompressError::InvalidData);
+    }
+
+    let (distance_code, left) = Huffman::new(distance_lengths)?;
+
+    if left > 0 && !is_single_code(&distance_code, distance_count) {
+        return Err(DecompressError::InvalidData);
+    }
+
+    inflate_codes(reader, output, &literal_code, &distance_code)
+}
+
+/// Decompress a raw deflate stream, returning the number of bytes consumed and produced.
+fn inflate_raw(input: &[u8], output: &mut [u8]) -> Result<(usize, usize)> {
+    let mut reader = BitReader::new(input);
+    let mut output = Output {
+        buffer: output,
+        position: 0,
+    };
+
+    loop {
+        let is_last = reader.bits(1)? != 0;
+
+        match reader.bits(2)? {
+            0 => inflate_stored(&mut reader, &mut output)?,
+            1 => inflate_fixed(&mut reader, &mut output)?,
+            2 => inflate_dynamic(&mut reader, &mut output)?,
+            _ => return Err(DecompressError::InvalidData),
+        }
+
+        if is_last {
+            return Ok((reader.position, output.position));
+        }
+    }
+}
+
+/// Decompress a raw deflate stream, returning the decompressed size.
+pub fn inflate(input: &[u8], output: &mut [u8]) -> Result<usize> {
+    inflate_raw(input, output).map(|(_, size)| size)
+}
+
+fn read_u32(data: &[u8]) -> u32 {
+    u32::from_le_bytes([data[0], data[1], data[2], data[3]])
+}
+
+/// Skip a zero terminated string, returning the position after it.
+fn skip_string(input: &[u8], position: usize) -> Result<usize> {
+    let length = input
+        .get(position..)
+        .and_then(|data| data.iter().position(|value| *value == 0))
+        .ok_or(DecompressError::UnexpectedEndOfInput)?;
+
+    Ok(position + length + 1)
+}
+
+/// Decompress a gzip member, returning the decompressed size.
+pub fn gzip_decompress(input: &[u8], output: &mut [u8]) -> Result<usize> {
+    if input.len() < 10 {
+        return Err(DecompressError::UnexpectedEndOfInput);
+    }
+
+    if input[..2] != GZIP_MAGIC || input[2] != GZIP_METHOD_DEFLATE {
+        return Err(DecompressError::InvalidHeader);
+    }
+
+    let flags = input[3];
+
+    if flags & GZIP_FLAG_RESERVED != 0 {
+        return Err(DecompressError::InvalidHeader);
+    }
+
+    let mut position = 10;
+
+    if flags & GZIP_FLAG_EXTRA != 0 {
+        let extra = input
+            .get(position..position + 2)
+            .ok_or(DecompressError::UnexpectedEndOfInput)?;
+
+        position += 2 + usize::from(u16::from_le_bytes([extra[0], extra[1]]));
+    }
+
+    if flags & GZIP_FLAG_NAME != 0 {
+        position = skip_string(input, position)?;
+    }
+
+    if flags & GZIP_FLAG_COMMENT != 0 {
+        position = skip_string(input, position)?;
+    }
+
+    if flags & GZIP_FLAG_HCRC != 0 {
+        position += 2;
+    }
+
+    let data = input
+        .get(position..)
+        .ok_or(DecompressError::UnexpectedEndOfInput)?;
+
+    let (consumed, size) = inflate_raw(data, output)?;
+
+    let trailer = data
+        .get(consumed..consumed + 8)
+        .ok_or(DecompressError::UnexpectedEndOfInput)?;
+
+    if read_u32(&trailer[..4]) != crc32(&output[..size]) || read_u32(&trailer[4..]) != size as u32 {
+        return Err(DecompressError::ChecksumMismatch);
+    }
+
+    Ok(size)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LICENSE: &[u8] = include_bytes!("../testdata/LICENSE-APACHE");
+
+    fn decompress_with<F>(decompress: F, input: &[u8], size: usize) -> Result<Vec<u8>>
+    where
+        F: Fn(&[u8], &mut [u8]) -> Result<usize>,
+    {
+        let mut output = vec![0x0u8; size];
+        let size = decompress(input, &mut output)?;
+
+        output.truncate(size);
+
+        Ok(output)
+    }
+
+    #[test]
+    fn inflate_block_types() {
+        let vectors: [(&[u8], &[u8]); 4] = [
+            (include_bytes!("../testdata/stored.deflate"), LICENSE),
+            (include_bytes!("../testdata/fixed.deflate"), LICENSE),
+            (include_bytes!("../testdata/dynamic.deflate"), LICENSE),
+            (&[0x03, 0x00], b""),
+        ];
+
+        for (input, expected) in vectors.iter() {
+            assert_eq!(
+                decompress_with(inflate, input, expected.len()).as_deref(),
+                Ok(*expected)
+            );
+        }
+    }
+
+    #[test]
+    fn gzip() {
+        let input = include_bytes!("../testdata/LICENSE-APACHE.gz");
+
+        assert_eq!(
+            decompress_with(gzip_decompress, input, 0x10000).as_deref(),
+            Ok(LICENSE)
+        );
+    }
+
+    #[test]
+    fn gzip_errors() {
+        let input = include_bytes!("../testdata/LICENSE-APACHE.gz");
+
+        assert_eq!(
+            decompress_with(gzip_decompress, input, LICENSE.len() - 1),
+            Err(DecompressError::OutputTooSmall)
+        );
+        assert_eq!(
+            decompress_with(gzip_decompress, &input[..input.len() - 1], 0x10000),
+            Err(DecompressError::UnexpectedEndOfInput)
+        );
+        assert_eq!(
+            decompress_with(gzip_decompress, &input[1..], 0x10000),
+            Err(DecompressError::InvalidHeader)
+        );
+
+        let mut corrupted = input.to_vec();
+        let crc_position = corrupted.len() - 8;
+        corrupted[crc_position] ^= 1;
+
+        assert_eq!(
+            decompress_with(gzip_decompress, &corrupted, 0x10000),
+            Err(DecompressError::ChecksumMismatch)
+        );
+    }
+
+    #[test]
+    fn inflate_invalid_data() {
+        // Reserved block type.
+        assert_eq!(
+            decompress_with(inflate, &[0x07], 0x10),
+            Err(DecompressError::InvalidData)
+        );
+        // Stored block with a corrupted inverted length.
+        assert_eq!(
+            decompress_with(inflate, &[0x01, 0x01, 0x00, 0x00, 0x00, 0x42], 0x10),
+            Err(DecompressError::InvalidData)
+        );
+        // Distance pointing before the start of the output.
+        let input = include_bytes!("../testdata/dynamic.deflate");
+
+        assert!(decompress_with(inflate, &input[..input.len() / 2], 0x10000).is_err());
+    }
+}
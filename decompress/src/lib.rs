@@ -0,0 +1,30 @@
+//! gzip and xz decompression for the m1n1 proxy
+//!
+//! Decoders work between two buffers without allocating, the output buffer doubles as the dictionary.
+#![cfg_attr(not(test), no_std)]
+
+mod crc;
+mod inflate;
+mod lzma;
+mod xz;
+
+pub use crc::{crc32, crc64};
+pub use inflate::{gzip_decompress, inflate};
+pub use xz::xz_decompress;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum DecompressError {
+    /// The input ended in the middle of the stream.
+    UnexpectedEndOfInput,
+    /// The output buffer cannot hold the decompressed data.
+    OutputTooSmall,
+    /// Invalid magic or container header.
+    InvalidHeader,
+    /// Corrupted compressed data.
+    InvalidData,
+    /// Valid stream using a feature that isn't supported (like xz filters other than LZMA2).
+    Unsupported,
+    ChecksumMismatch,
+}
+
+pub type Result<T> = core::result::Result<T, DecompressError>;
@@ -0,0 +1,534 @@
+//! LZMA2 decoder
+//!
+//! The dictionary is the already decompressed part of the output buffer, so no window is kept.
+
+use crate::{DecompressError, Result};
+
+const PROBABILITY_BITS: u32 = 11;
+const PROBABILITY_INIT: u16 = 1 << (PROBABILITY_BITS - 1);
+const PROBABILITY_MOVE_BITS: u32 = 5;
+const RANGE_TOP_VALUE: u32 = 1 << 24;
+const RANGE_INIT_SIZE: usize = 5;
+
+const STATES: usize = 12;
+/// States below this one were reached after a literal.
+const LITERAL_STATES: usize = 7;
+const POSITION_STATES_MAX: usize = 1 << 4;
+
+const MATCH_LENGTH_MIN: usize = 2;
+const LENGTH_LOW_BITS: u32 = 3;
+const LENGTH_MIDDLE_BITS: u32 = 3;
+const LENGTH_HIGH_BITS: u32 = 8;
+
+const LENGTH_TO_DISTANCE_STATES: usize = 4;
+const DISTANCE_SLOT_BITS: u32 = 6;
+const DISTANCE_MODEL_START: u32 = 4;
+const DISTANCE_MODEL_END: u32 = 14;
+const FULL_DISTANCES: usize = 1 << (DISTANCE_MODEL_END / 2);
+const ALIGN_BITS: u32 = 4;
+
+const LITERAL_CODER_SIZE: usize = 0x300;
+/// LZMA2 limits lc + lp to 4.
+const LITERAL_CODERS_MAX: usize = 1 << 4;
+const PROPERTIES_MAX: u8 = (4 * 5 + 4) * 9 + 8;
+
+const LZMA2_CONTROL_END: u8 = 0x00;
+const LZMA2_CONTROL_UNCOMPRESSED_RESET: u8 = 0x01;
+const LZMA2_CONTROL_UNCOMPRESSED: u8 = 0x02;
+const LZMA2_CONTROL_LZMA: u8 = 0x80;
+const LZMA2_CONTROL_STATE_RESET: u8 = 0xA0;
+const LZMA2_CONTROL_PROPERTIES_RESET: u8 = 0xC0;
+const LZMA2_CONTROL_DICTIONARY_RESET: u8 = 0xE0;
+
+struct RangeDecoder<'a> {
+    input: &'a [u8],
+    position: usize,
+    range: u32,
+    code: u32,
+}
+
+impl<'a> RangeDecoder<'a> {
+    fn new(input: &'a [u8]) -> Result<Self> {
+        if input.len() < RANGE_INIT_SIZE || input[0] != 0 {
+            return Err(DecompressError::InvalidData);
+        }
+
+        let code = u32::from_be_bytes([input[1], input[2], input[3], input[4]]);
+
+        if code == u32::MAX {
+            return Err(DecompressError::InvalidData);
+        }
+
+        Ok(RangeDecoder {
+            input,
+            position: RANGE_INIT_SIZE,
+            range: u32::MAX,
+            code,
+        })
+    }
+
+    fn normalize(&mut self) -> Result<()> {
+        if self.range < RANGE_TOP_VALUE {
+            let value = *self
+                .input
+                .get(self.position)
+                .ok_or(DecompressError::InvalidData)?;
+
+            self.position += 1;
+            self.range <<= 8;
+            self.code = (self.code << 8) | u32::from(value);
+        }
+
+        Ok(())
+    }
+
+    /// Whether all the input was consumed and the encoder flushed cleanly.
+    fn is_finished(&self) -> bool {
+        self.position == self.input.len() && self.code == 0
+    }
+
+    fn bit(&mut self, probability: &mut u16) -> Result<usize> {
+        self.normalize()?;
+
+        let bound = (self.range >> PROBABILITY_BITS) * u32::from(*probability);
+
+        if self.code < bound {
+            self.range = bound;
+            *probability += ((1 << PROBABILITY_BITS) - *probability) >> PROBABILITY_MOVE_BITS;
+
+            Ok(0)
+        } else {
+            self.range -= bound;
+            self.code -= bound;
+            *probability -= *probability >> PROBABILITY_MOVE_BITS;
+
+            Ok(1)
+        }
+    }
+
+    fn bit_tree(&mut self, probabilities: &mut [u16], bits: u32) -> Result<usize> {
+        let mut symbol = 1;
+
+        for _ in 0..bits {
+            symbol = (symbol << 1) | self.bit(&mut probabilities[symbol])?;
+        }
+
+        Ok(symbol - (1 << bits))
+    }
+
+    fn reverse_bit_tree(&mut self, probabilities: &mut [u16], bits: u32) -> Result<usize> {
+        let mut symbol = 1;
+        let mut result = 0;
+
+        for i in 0..bits {
+            let bit = self.bit(&mut probabilities[symbol])?;
+
+            symbol = (symbol << 1) | bit;
+            result |= bit << i;
+        }
+
+        Ok(result)
+    }
+
+    fn direct_bits(&mut self, bits: u32) -> Result<usize> {
+        let mut result = 0;
+
+        for _ in 0..bits {
+            self.normalize()?;
+            self.range >>= 1;
+
+            let bit = if self.code >= self.range {
+                self.code -= self.range;
+                1
+            } else {
+                0
+            };
+
+            result = (result << 1) | bit;
+        }
+
+        Ok(result)
+    }
+}
+
+struct LengthDecoder {
+    choice: u16,
+    choice2: u16,
+    low: [[u16; 1 << LENGTH_LOW_BITS]; POSITION_STATES_MAX],
+    middle: [[u16; 1 << LENGTH_MIDDLE_BITS]; POSITION_STATES_MAX],
+    high: [u16; 1 << LENGTH_HIGH_BITS],
+}
+
+impl LengthDecoder {
+    const fn new() -> Self {
+        LengthDecoder {
+            choice: PROBABILITY_INIT,
+            choice2: PROBABILITY_INIT,
+            low: [[PROBABILITY_INIT; 1 << LENGTH_LOW_BITS]; POSITION_STATES_MAX],
+            middle: [[PROBABILITY_INIT; 1 << LENGTH_MIDDLE_BITS]; POSITION_STATES_MAX],
+            high: [PROBABILITY_INIT; 1 << LENGTH_HIGH_BITS],
+        }
+    }
+
+    fn decode(&mut self, decoder: &mut RangeDecoder, position_state: usize) -> Result<usize> {
+        let length = if decoder.bit(&mut self.choice)? == 0 {
+            decoder.bit_tree(&mut self.low[position_state], LENGTH_LOW_BITS)?
+        } else if decoder.bit(&mut self.choice2)? == 0 {
+            (1 << LENGTH_LOW_BITS)
+                + decoder.bit_tree(&mut self.middle[position_state], LENGTH_MIDDLE_BITS)?
+        } else {
+            (1 << LENGTH_LOW_BITS)
+                + (1 << LENGTH_MIDDLE_BITS)
+                + decoder.bit_tree(&mut self.high, LENGTH_HIGH_BITS)?
+        };
+
+        Ok(MATCH_LENGTH_MIN + length)
+    }
+}
+
+/// LZMA decoder state, kept across LZMA2 chunks.
+pub struct LzmaDecoder {
+    literal_context_bits: u32,
+    literal_position_bits: u32,
+    position_bits: u32,
+    state: usize,
+    /// Last four match distances, minus one.
+    reps: [usize; 4],
+    is_match: [[u16; POSITION_STATES_MAX]; STATES],
+    is_rep: [u16; STATES],
+    is_rep0: [u16; STATES],
+    is_rep1: [u16; STATES],
+    is_rep2: [u16; STATES],
+    is_rep0_long: [[u16; POSITION_STATES_MAX]; STATES],
+    distance_slot: [[u16; 1 << DISTANCE_SLOT_BITS]; LENGTH_TO_DISTANCE_STATES],
+    distance_special: [u16; 1 + FULL_DISTANCES - DISTANCE_MODEL_END as usize],
+    distance_align: [u16; 1 << ALIGN_BITS],
+    match_length: LengthDecoder,
+    rep_length: LengthDecoder,
+    literal: [u16; LITERAL_CODER_SIZE * LITERAL_CODERS_MAX],
+}
+
+impl LzmaDecoder {
+    pub const fn new() -> Self {
+        LzmaDecoder {
+            literal_context_bits: 0,
+            literal_position_bits: 0,
+            position_bits: 0,
+            state: 0,
+            reps: [0; 4],
+            is_match: [[PROBABILITY_INIT; POSITION_STATES_MAX]; STATES],
+            is_rep: [PROBABILITY_INIT; STATES],
+            is_rep0: [PROBABILITY_INIT; STATES],
+            is_rep1: [PROBABILITY_INIT; STATES],
+            is_rep2: [PROBABILITY_INIT; STATES],
+            is_rep0_long: [[PROBABILITY_INIT; POSITION_STATES_MAX]; STATES],
+            distance_slot: [[PROBABILITY_INIT; 1 << DISTANCE_SLOT_BITS]; LENGTH_TO_DISTANCE_STATES],
+            distance_special: [PROBABILITY_INIT; 1 + FULL_DISTANCES - DISTANCE_MODEL_END as usize],
+            distance_align: [PROBABILITY_INIT; 1 << ALIGN_BITS],
+            match_length: LengthDecoder::new(),
+            rep_length: LengthDecoder::new(),
+            literal: [PROBABILITY_INIT; LITERAL_CODER_SIZE * LITERAL_CODERS_MAX],
+        }
+    }
+
+    /// Set lc, lp and pb from the properties byte.
+    fn set_properties(&mut self, properties: u8) -> Result<()> {
+        if properties > PROPERTIES_MAX {
+            return Err(DecompressError::InvalidData);
+        }
+
+        let literal_context_bits = u32::from(properties % 9);
+        let properties = properties / 9;
+        let literal_position_bits = u32::from(properties % 5);
+
+        if literal_context_bits + literal_position_bits > 4 {
+            return Err(DecompressError::InvalidData);
+        }
+
+        self.literal_context_bits = literal_context_bits;
+        self.literal_position_bits = literal_position_bits;
+        self.position_bits = u32::from(properties / 5);
+
+        Ok(())
+    }
+
+    fn reset_state(&mut self) {
+        self.state = 0;
+        self.reps = [0; 4];
+        self.is_match = [[PROBABILITY_INIT; POSITION_STATES_MAX]; STATES];
+        self.is_rep = [PROBABILITY_INIT; STATES];
+        self.is_rep0 = [PROBABILITY_INIT; STATES];
+        self.is_rep1 = [PROBABILITY_INIT; STATES];
+        self.is_rep2 = [PROBABILITY_INIT; STATES];
+        self.is_rep0_long = [[PROBABILITY_INIT; POSITION_STATES_MAX]; STATES];
+        self.distance_slot =
+            [[PROBABILITY_INIT; 1 << DISTANCE_SLOT_BITS]; LENGTH_TO_DISTANCE_STATES];
+        self.distance_special =
+            [PROBABILITY_INIT; 1 + FULL_DISTANCES - DISTANCE_MODEL_END as usize];
+        self.distance_align = [PROBABILITY_INIT; 1 << ALIGN_BITS];
+        self.match_length = LengthDecoder::new();
+        self.rep_length = LengthDecoder::new();
+
+        for probability in self.literal.iter_mut() {
+            *probability = PROBABILITY_INIT;
+        }
+    }
+
+    fn decode_literal(&mut self, decoder: &mut RangeDecoder, dictionary: &[u8]) -> Result<u8> {
+        let previous = dictionary.last().copied().unwrap_or(0);
+        let position_mask = (1 << self.literal_position_bits) - 1;
+        let coder = ((dictionary.len() & position_mask) << self.literal_context_bits)
+            + (usize::from(previous) >> (8 - self.literal_context_bits));
+        let probabilities = &mut self.literal[coder * LITERAL_CODER_SIZE..][..LITERAL_CODER_SIZE];
+        let mut symbol = 1;
+
+        if self.state >= LITERAL_STATES {
+            let mut match_byte = usize::from(dictionary[dictionary.len() - self.reps[0] - 1]);
+
+            while symbol < 0x100 {
+                let match_bit = (match_byte >> 7) & 1;
+                let bit = decoder.bit(&mut probabilities[((1 + match_bit) << 8) + symbol])?;
+
+                match_byte <<= 1;
+                symbol = (symbol << 1) | bit;
+
+                if match_bit != bit {
+                    break;
+                }
+            }
+        }
+
+        while symbol < 0x100 {
+            symbol = (symbol << 1) | decoder.bit(&mut probabilities[symbol])?;
+        }
+
+        Ok(symbol as u8)
+    }
+
+    fn decode_distance(&mut self, decoder: &mut RangeDecoder, length: usize) -> Result<usize> {
+        let length_state = (length - MATCH_LENGTH_MIN).min(LENGTH_TO_DISTANCE_STATES - 1);
+        let slot = decoder.bit_tree(&mut self.distance_slot[length_state], DISTANCE_SLOT_BITS)?;
+
+        if slot < DISTANCE_MODEL_START as usize {
+            return Ok(slot);
+        }
+
+        let direct_bits = (slot as u32 >> 1) - 1;
+        let mut distance = (2 | (slot & 1)) << direct_bits;
+
+        if slot < DISTANCE_MODEL_END as usize {
+            distance += decoder
+                .reverse_bit_tree(&mut self.distance_special[distance - slot..], direct_bits)?;
+        } else {
+            distance += decoder.direct_bits(direct_bits - ALIGN_BITS)? << ALIGN_BITS;
+            distance += decoder.reverse_bit_tree(&mut self.distance_align, ALIGN_BITS)?;
+        }
+
+        Ok(distance)
+    }
+
+    /// Decode a rep match, returning its length.
+    fn decode_rep_match(
+        &mut self,
+        decoder: &mut RangeDecoder,
+        position_state: usize,
+    ) -> Result<usize> {
+        if decoder.bit(&mut self.is_rep0[self.state])? == 0 {
+            if decoder.bit(&mut self.is_rep0_long[self.state][position_state])? == 0 {
+                self.state = if self.state < LITERAL_STATES { 9 } else { 11 };
+
+                return Ok(1);
+            }
+        } else {
+            let distance = if decoder.bit(&mut self.is_rep1[self.state])? == 0 {
+                self.reps[1]
+            } else {
+                let distance = if decoder.bit(&mut self.is_rep2[self.state])? == 0 {
+                    self.reps[2]
+                } else {
+                    let distance = self.reps[3];
+
+                    self.reps[3] = self.reps[2];
+
+                    distance
+                };
+
+                self.reps[2] = self.reps[1];
+
+                distance
+            };
+
+            self.reps[1] = self.reps[0];
+            self.reps[0] = distance;
+        }
+
+        self.state = if self.state < LITERAL_STATES { 8 } else { 11 };
+
+        self.rep_length.decode(decoder, position_state)
+    }
+
+    /// Decode a LZMA chunk until `output` is full, the dictionary being `output[dictionary_start..position]`.
+    fn decode_chunk(
+        &mut self,
+        input: &[u8],
+        output: &mut [u8],
+        dictionary_start: usize,
+        mut position: usize,
+    ) -> Result<()> {
+        let mut decoder = RangeDecoder::new(input)?;
+        let position_mask = (1 << self.position_bits) - 1;
+
+        while position < output.len() {
+            let dictionary_size = position - dictionary_start;
+            let position_state = dictionary_size & position_mask;
+
+            if decoder.bit(&mut self.is_match[self.state][position_state])? == 0 {
+                output[position] =
+                    self.decode_literal(&mut decoder, &output[dictionary_start..position])?;
+                position += 1;
+
+                self.state = match self.state {
+                    0..=3 => 0,
+                    4..=9 => self.state - 3,
+                    _ => self.state - 6,
+                };
+
+                continue;
+            }
+
+            let length = if decoder.bit(&mut self.is_rep[self.state])? == 0 {
+                let length = self.match_length.decode(&mut decoder, position_state)?;
+                let distance = self.decode_distance(&mut decoder, length)?;
+
+                self.state = if self.state < LITERAL_STATES { 7 } else { 10 };
+                self.reps = [distance, self.reps[0], self.reps[1], self.reps[2]];
+
+                length
+            } else {
+                if dictionary_size == 0 {
+                    return Err(DecompressError::InvalidData);
+                }
+
+                self.decode_rep_match(&mut decoder, position_state)?
+            };
+
+            // This also rejects the end of stream marker, which isn't allowed in LZMA2.
+            let distance = self.reps[0] + 1;
+
+            if distance > dictionary_size || length > output.len() - position {
+                return Err(DecompressError::InvalidData);
+            }
+
+            for i in position..position + length {
+                output[i] = output[i - distance];
+            }
+
+            position += length;
+        }
+
+        decoder.normalize()?;
+
+        if !decoder.is_finished() {
+            return Err(DecompressError::InvalidData);
+        }
+
+        Ok(())
+    }
+}
+
+fn read_u16_be(input: &[u8], position: usize) -> Result<usize> {
+    let data = input
+        .get(position..position + 2)
+        .ok_or(DecompressError::UnexpectedEndOfInput)?;
+
+    Ok(usize::from(u16::from_be_bytes([data[0], data[1]])))
+}
+
+/// Decompress a LZMA2 stream, returning the number of bytes consumed and produced.
+pub fn lzma2_decompress(
+    lzma: &mut LzmaDecoder,
+    input: &[u8],
+    output: &mut [u8],
+) -> Result<(usize, usize)> {
+    let mut input_position = 0;
+    let mut position = 0;
+    let mut dictionary_start = 0;
+    let mut need_dictionary_reset = true;
+    let mut need_properties = true;
+
+    loop {
+        let control = *input
+            .get(input_position)
+            .ok_or(DecompressError::UnexpectedEndOfInput)?;
+
+        input_position += 1;
+
+        if control == LZMA2_CONTROL_END {
+            return Ok((input_position, position));
+        }
+
+        if control >= LZMA2_CONTROL_DICTIONARY_RESET || control == LZMA2_CONTROL_UNCOMPRESSED_RESET
+        {
+            need_dictionary_reset = false;
+            need_properties = true;
+            dictionary_start = position;
+        } else if need_dictionary_reset {
+            return Err(DecompressError::InvalidData);
+        }
+
+        if control >= LZMA2_CONTROL_LZMA {
+            let unpacked_size =
+                (usize::from(control & 0x1F) << 16) + read_u16_be(input, input_position)? + 1;
+            let packed_size = read_u16_be(input, input_position + 2)? + 1;
+
+            input_position += 4;
+
+            if control >= LZMA2_CONTROL_PROPERTIES_RESET {
+                let properties = *input
+                    .get(input_position)
+                    .ok_or(DecompressError::UnexpectedEndOfInput)?;
+
+                input_position += 1;
+
+                lzma.set_properties(properties)?;
+                lzma.reset_state();
+                need_properties = false;
+            } else if need_properties {
+                return Err(DecompressError::InvalidData);
+            } else if control >= LZMA2_CONTROL_STATE_RESET {
+                lzma.reset_state();
+            }
+
+            let packed = input
+                .get(input_position..input_position + packed_size)
+                .ok_or(DecompressError::UnexpectedEndOfInput)?;
+            let end = position + unpacked_size;
+            let output = output
+                .get_mut(..end)
+                .ok_or(DecompressError::OutputTooSmall)?;
+
+            lzma.decode_chunk(packed, output, dictionary_start, position)?;
+
+            input_position += packed_size;
+            position = end;
+        } else if control <= LZMA2_CONTROL_UNCOMPRESSED {
+            let size = read_u16_be(input, input_position)? + 1;
+
+            input_position += 2;
+
+            let data = input
+                .get(input_position..input_position + size)
+                .ok_or(DecompressError::UnexpectedEndOfInput)?;
+
+            output
+                .get_mut(position..position + size)
+                .ok_or(DecompressError::OutputTooSmall)?
+                .copy_from_slice(data);
+
+            input_position += size;
+            position += size;
+        } else {
+            return Err(DecompressError::InvalidData);
+        }
+    }
+}
@@ -0,0 +1,399 @@
+//! xz container decoder, only supporting the LZMA2 filter
+//!
+//! See https://tukaani.org/xz/xz-file-format.txt for the format.
+
+use crate::crc::{crc32, crc32_update, crc64};
+use crate::lzma::{lzma2_decompress, LzmaDecoder};
+use crate::{DecompressError, Result};
+
+const STREAM_HEADER_MAGIC: [u8; 6] = [0xFD, b'7', b'z', b'X', b'Z', 0x00];
+const STREAM_FOOTER_MAGIC: [u8; 2] = *b"YZ";
+const STREAM_HEADER_SIZE: usize = 12;
+const STREAM_FOOTER_SIZE: usize = 12;
+
+const CHECK_NONE: u8 = 0x00;
+const CHECK_CRC32: u8 = 0x01;
+const CHECK_CRC64: u8 = 0x04;
+const CHECK_SIZES: [usize; 16] = [0, 4, 4, 4, 8, 8, 8, 16, 16, 16, 32, 32, 32, 64, 64, 64];
+
+const BLOCK_FLAG_FILTER_COUNT: u8 = 0x03;
+const BLOCK_FLAG_RESERVED: u8 = 0x3C;
+const BLOCK_FLAG_COMPRESSED_SIZE: u8 = 0x40;
+const BLOCK_FLAG_UNCOMPRESSED_SIZE: u8 = 0x80;
+
+const FILTER_LZMA2: u64 = 0x21;
+const LZMA2_DICTIONARY_SIZE_MAX: u8 = 40;
+
+const VLI_SIZE_MAX: usize = 9;
+
+fn read_u32(data: &[u8]) -> u32 {
+    u32::from_le_bytes([data[0], data[1], data[2], data[3]])
+}
+
+fn read_u64(data: &[u8]) -> u64 {
+    let mut value = [0x0u8; 8];
+
+    value.copy_from_slice(&data[..8]);
+
+    u64::from_le_bytes(value)
+}
+
+struct Reader<'a> {
+    input: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(input: &'a [u8]) -> Self {
+        Reader { input, position: 0 }
+    }
+
+    fn remaining(&self) -> &'a [u8] {
+        &self.input[self.position..]
+    }
+
+    fn bytes(&mut self, count: usize) -> Result<&'a [u8]> {
+        let end = self.position + count;
+        let data = self
+            .input
+            .get(self.position..end)
+            .ok_or(DecompressError::UnexpectedEndOfInput)?;
+
+        self.position = end;
+
+        Ok(data)
+    }
+
+    fn byte(&mut self) -> Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    /// Read a variable length integer.
+    fn vli(&mut self) -> Result<u64> {
+        let mut value = 0;
+
+        for i in 0..VLI_SIZE_MAX {
+            let byte = self.byte()?;
+
+            value |= u64::from(byte & 0x7F) << (i * 7);
+
+            if byte & 0x80 == 0 {
+                // Encodings must be the shortest possible.
+                if i != 0 && byte == 0 {
+                    return Err(DecompressError::InvalidData);
+                }
+
+                return Ok(value);
+            }
+        }
+
+        Err(DecompressError::InvalidData)
+    }
+
+    /// Skip the zero padding up to the next multiple of four bytes.
+    fn skip_padding(&mut self) -> Result<()> {
+        while self.position & 3 != 0 {
+            if self.byte()? != 0 {
+                return Err(DecompressError::InvalidData);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Summary of block sizes, used to compare the decoded blocks against the index.
+#[derive(Default, PartialEq, Eq)]
+struct IndexHash {
+    count: u64,
+    crc: u32,
+}
+
+impl IndexHash {
+    fn add(&mut self, unpadded_size: u64, uncompressed_size: u64) {
+        self.count += 1;
+        self.crc = crc32_update(self.crc, &unpadded_size.to_le_bytes());
+        self.crc = crc32_update(self.crc, &uncompressed_size.to_le_bytes());
+    }
+}
+
+/// Decode a block, returning its unpadded size and the decompressed size.
+fn decode_block(
+    reader: &mut Reader,
+    check: u8,
+    lzma: &mut LzmaDecoder,
+    output: &mut [u8],
+) -> Result<(u64, usize)> {
+    let header_size = (usize::from(reader.remaining()[0]) + 1) * 4;
+    let (header, crc) = reader.bytes(header_size)?.split_at(header_size - 4);
+
+    if read_u32(crc) != crc32(header) {
+        return Err(DecompressError::ChecksumMismatch);
+    }
+
+    let flags = header[1];
+
+    if flags & BLOCK_FLAG_RESERVED != 0 || flags & BLOCK_FLAG_FILTER_COUNT != 0 {
+        return Err(DecompressError::Unsupported);
+    }
+
+    let mut fields = Reader::new(&header[2..]);
+
+    let compressed_size = if flags & BLOCK_FLAG_COMPRESSED_SIZE != 0 {
+        Some(fields.vli()?)
+    } else {
+        None
+    };
+
+    let uncompressed_size = if flags & BLOCK_FLAG_UNCOMPRESSED_SIZE != 0 {
+        Some(fields.vli()?)
+    } else {
+        None
+    };
+
+    if fields.vli()? != FILTER_LZMA2 {
+        return Err(DecompressError::Unsupported);
+    }
+
+    if fields.vli()? != 1 || fields.byte()? > LZMA2_DICTIONARY_SIZE_MAX {
+        return Err(DecompressError::InvalidHeader);
+    }
+
+    if fields.remaining().iter().any(|value| *value != 0) {
+        return Err(DecompressError::InvalidHeader);
+    }
+
+    let (consumed, size) = lzma2_decompress(lzma, reader.remaining(), output)?;
+
+    reader.position += consumed;
+
+    if matches!(compressed_size, Some(expected) if expected != consumed as u64)
+        || matches!(uncompressed_size, Some(expected) if expected != size as u64)
+    {
+        return Err(DecompressError::InvalidData);
+    }
+
+    reader.skip_padding()?;
+
+    let check_size = CHECK_SIZES[usize::from(check)];
+    let expected = reader.bytes(check_size)?;
+    let output = &output[..size];
+
+    let is_valid = match check {
+        CHECK_NONE => true,
+        CHECK_CRC32 => read_u32(expected) == crc32(output),
+        CHECK_CRC64 => read_u64(expected) == crc64(output),
+        // Other checks are skipped.
+        _ => true,
+    };
+
+    if !is_valid {
+        return Err(DecompressError::ChecksumMismatch);
+    }
+
+    Ok(((header_size + consumed + check_size) as u64, size))
+}
+
+/// Decompress a xz stream, returning the decompressed size.
+pub fn xz_decompress(input: &[u8], output: &mut [u8]) -> Result<usize> {
+    let mut reader = Reader::new(input);
+
+    let header = reader.bytes(STREAM_HEADER_SIZE)?;
+    let stream_flags = &header[6..8];
+
+    if header[..6] != STREAM_HEADER_MAGIC {
+        return Err(DecompressError::InvalidHeader);
+    }
+
+    if read_u32(&header[8..]) != crc32(stream_flags) {
+        return Err(DecompressError::ChecksumMismatch);
+    }
+
+    if stream_flags[0] != 0 || stream_flags[1] & 0xF0 != 0 {
+        return Err(DecompressError::Unsupported);
+    }
+
+    let check = stream_flags[1];
+    let mut lzma = LzmaDecoder::new();
+    let mut blocks = IndexHash::default();
+    let mut size = 0;
+
+    // Blocks are followed by the index, starting with a zero byte.
+    while *reader
+        .remaining()
+        .first()
+        .ok_or(DecompressError::UnexpectedEndOfInput)?
+        != 0
+    {
+        let (unpadded_size, block_size) =
+            decode_block(&mut reader, check, &mut lzma, &mut output[size..])?;
+
+        blocks.add(unpadded_size, block_size as u64);
+        size += block_size;
+    }
+
+    let index_start = reader.position;
+    let mut records = IndexHash::default();
+
+    reader.byte()?;
+
+    for _ in 0..reader.vli()? {
+        let unpadded_size = reader.vli()?;
+        let uncompressed_size = reader.vli()?;
+
+        records.add(unpadded_size, uncompressed_size);
+    }
+
+    reader.skip_padding()?;
+
+    let index_crc = crc32(&input[index_start..reader.position]);
+    let index_size = reader.position + 4 - index_start;
+
+    if read_u32(reader.bytes(4)?) != index_crc {
+        return Err(DecompressError::ChecksumMismatch);
+    }
+
+    if records != blocks {
+        return Err(DecompressError::InvalidData);
+    }
+
+    let footer = reader.bytes(STREAM_FOOTER_SIZE)?;
+
+    if footer[10..] != STREAM_FOOTER_MAGIC {
+        return Err(DecompressError::InvalidHeader);
+    }
+
+    if read_u32(footer) != crc32(&footer[4..10]) {
+        return Err(DecompressError::ChecksumMismatch);
+    }
+
+    let backward_size = (read_u32(&footer[4..]) as usize + 1) * 4;
+
+    if footer[8..10] != *stream_flags || backward_size != index_size {
+        return Err(DecompressError::InvalidData);
+    }
+
+    Ok(size)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LICENSE: &[u8] = include_bytes!("../testdata/LICENSE-APACHE");
+
+    fn decompress(input: &[u8], size: usize) -> Result<Vec<u8>> {
+        let mut output = vec![0x0u8; size];
+        let size = xz_decompress(input, &mut output)?;
+
+        output.truncate(size);
+
+        Ok(output)
+    }
+
+    /// Same data as testdata/random.xz, stored as an uncompressed LZMA2 chunk.
+    fn random_data() -> Vec<u8> {
+        let mut state = 1u32;
+
+        (0..2048)
+            .map(|_| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                (state >> 24) as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn single_block() {
+        let input = include_bytes!("../testdata/LICENSE-APACHE.xz");
+
+        assert_eq!(decompress(input, 0x10000).as_deref(), Ok(LICENSE));
+    }
+
+    #[test]
+    fn multiple_blocks() {
+        let input = include_bytes!("../testdata/LICENSE-APACHE-blocks.xz");
+
+        assert_eq!(decompress(input, 0x10000).as_deref(), Ok(LICENSE));
+    }
+
+    #[test]
+    fn literal_properties() {
+        // lc=1, lp=2, pb=0 with no integrity check.
+        let input = include_bytes!("../testdata/LICENSE-APACHE-properties.xz");
+
+        assert_eq!(decompress(input, 0x10000).as_deref(), Ok(LICENSE));
+    }
+
+    #[test]
+    fn uncompressed_chunk() {
+        let input = include_bytes!("../testdata/random.xz");
+
+        assert_eq!(decompress(input, 0x10000), Ok(random_data()));
+    }
+
+    #[test]
+    fn multiple_chunks() {
+        let input = include_bytes!("../testdata/zeros.xz");
+        let output = decompress(input, 6 << 20).unwrap();
+
+        assert_eq!(output.len(), 5 << 20);
+        assert!(output.iter().all(|value| *value == 0));
+    }
+
+    #[test]
+    fn errors() {
+        let input = include_bytes!("../testdata/LICENSE-APACHE.xz");
+
+        assert_eq!(
+            decompress(input, LICENSE.len() - 1),
+            Err(DecompressError::OutputTooSmall)
+        );
+        assert_eq!(
+            decompress(&input[..input.len() - 1], 0x10000),
+            Err(DecompressError::UnexpectedEndOfInput)
+        );
+        assert_eq!(
+            decompress(&input[1..], 0x10000),
+            Err(DecompressError::InvalidHeader)
+        );
+
+        // Block check.
+        let mut corrupted = input.to_vec();
+        let check_position = corrupted.len() - 12 - 12 - 8;
+
+        corrupted[check_position] ^= 1;
+
+        assert_eq!(
+            decompress(&corrupted, 0x10000),
+            Err(DecompressError::ChecksumMismatch)
+        );
+
+        // Compressed data.
+        let mut corrupted = input.to_vec();
+
+        corrupted[input.len() / 2] ^= 0x10;
+
+        assert!(decompress(&corrupted, 0x10000).is_err());
+    }
+
+    #[test]
+    fn unsupported_filter() {
+        let mut input = include_bytes!("../testdata/LICENSE-APACHE.xz").to_vec();
+
+        // Replace the LZMA2 filter with x86 BCJ and fix the block header CRC.
+        let header_size = (usize::from(input[12]) + 1) * 4;
+        let filter_position = input[12..].iter().position(|value| *value == 0x21).unwrap() + 12;
+
+        input[filter_position] = 0x04;
+
+        let crc = crc32(&input[12..12 + header_size - 4]);
+
+        input[12 + header_size - 4..12 + header_size].copy_from_slice(&crc.to_le_bytes());
+
+        assert_eq!(
+            decompress(&input, 0x10000),
+            Err(DecompressError::Unsupported)
+        );
+    }
+}
@@ -0,0 +1,519 @@
+//! ELF64 loader for aarch64 images
+//!
+//! The `PT_LOAD` segments are copied to their addresses, or to a chosen base for position
+//! independent images which are then relocated.
+#![cfg_attr(not(test), no_std)]
+
+mod relocation;
+
+use core::ops::Range;
+
+use loader::{read_u16, read_u32, read_u64};
+use loader::{Image, Mapping, PlacementError, SegmentError};
+
+pub use relocation::{relocate, RelocationError};
+
+const ELF_MAGIC: [u8; 4] = *b"\x7fELF";
+const ELFCLASS64: u8 = 2;
+const ELFDATA2LSB: u8 = 1;
+const EV_CURRENT: u8 = 1;
+
+const ET_EXEC: u16 = 2;
+const ET_DYN: u16 = 3;
+const EM_AARCH64: u16 = 183;
+
+const PT_LOAD: u32 = 1;
+const PT_DYNAMIC: u32 = 2;
+
+pub const HEADER_SIZE: usize = 64;
+const PROGRAM_HEADER_SIZE: usize = 56;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ElfError {
+    InvalidHeader,
+    UnsupportedMachine,
+    UnsupportedType,
+    Truncated,
+    InvalidSegment,
+    /// The image doesn't fit in the memory it's loaded to.
+    DoesNotFit,
+    /// The base chosen for a position independent image doesn't match its alignment.
+    MisalignedBase,
+    Relocation(RelocationError),
+}
+
+impl From<RelocationError> for ElfError {
+    fn from(error: RelocationError) -> Self {
+        ElfError::Relocation(error)
+    }
+}
+
+impl From<SegmentError> for ElfError {
+    fn from(error: SegmentError) -> Self {
+        match error {
+            SegmentError::InvalidSegment => ElfError::InvalidSegment,
+            SegmentError::Truncated => ElfError::Truncated,
+            SegmentError::DoesNotFit => ElfError::DoesNotFit,
+        }
+    }
+}
+
+pub type Result<T> = core::result::Result<T, ElfError>;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct ProgramHeader {
+    pub segment_type: u32,
+    pub flags: u32,
+    pub offset: u64,
+    pub virtual_address: u64,
+    pub file_size: u64,
+    pub memory_size: u64,
+    pub alignment: u64,
+}
+
+impl ProgramHeader {
+    fn parse(data: &[u8]) -> Self {
+        ProgramHeader {
+            segment_type: read_u32(data, 0),
+            flags: read_u32(data, 4),
+            offset: read_u64(data, 8),
+            virtual_address: read_u64(data, 16),
+            file_size: read_u64(data, 32),
+            memory_size: read_u64(data, 40),
+            alignment: read_u64(data, 48),
+        }
+    }
+
+    fn get_mapping(&self) -> Mapping {
+        Mapping {
+            virtual_address: self.virtual_address,
+            memory_size: self.memory_size,
+            file_offset: self.offset,
+            file_size: self.file_size,
+        }
+    }
+}
+
+/// Check if an image starts with an ELF header.
+pub fn is_elf(data: &[u8]) -> bool {
+    data.get(..4) == Some(&ELF_MAGIC)
+}
+
+/// Size of the header and program headers, enough to know the size of the whole file.
+pub fn get_headers_size(header: &[u8]) -> Result<usize> {
+    let header = header.get(..HEADER_SIZE).ok_or(ElfError::Truncated)?;
+
+    if !is_elf(header) {
+        return Err(ElfError::InvalidHeader);
+    }
+
+    let program_headers_offset = read_u64(header, 32) as usize;
+    let program_header_count = usize::from(read_u16(header, 56));
+
+    program_header_count
+        .checked_mul(PROGRAM_HEADER_SIZE)
+        .and_then(|size| size.checked_add(program_headers_offset))
+        .ok_or(ElfError::InvalidHeader)
+}
+
+pub struct ElfFile<'a> {
+    data: &'a [u8],
+    file_type: u16,
+    entry: u64,
+    program_headers_offset: usize,
+    program_header_count: usize,
+}
+
+impl<'a> ElfFile<'a> {
+    /// Validate an aarch64 little endian ELF64 executable.
+    ///
+    /// `data` must at least hold the headers, the segments are checked when loading.
+    pub fn parse(data: &'a [u8]) -> Result<Self> {
+        let header = data.get(..HEADER_SIZE).ok_or(ElfError::Truncated)?;
+
+        if !is_elf(header)
+            || header[4] != ELFCLASS64
+            || header[5] != ELFDATA2LSB
+            || header[6] != EV_CURRENT
+        {
+            return Err(ElfError::InvalidHeader);
+        }
+
+        let file_type = read_u16(header, 16);
+
+        if read_u16(header, 18) != EM_AARCH64 {
+            return Err(ElfError::UnsupportedMachine);
+        }
+
+        if file_type != ET_EXEC && file_type != ET_DYN {
+            return Err(ElfError::UnsupportedType);
+        }
+
+        if usize::from(read_u16(header, 54)) != PROGRAM_HEADER_SIZE {
+            return Err(ElfError::InvalidHeader);
+        }
+
+        if data.len() < get_headers_size(header)? {
+            return Err(ElfError::Truncated);
+        }
+
+        Ok(ElfFile {
+            data,
+            file_type,
+            entry: read_u64(header, 24),
+            program_headers_offset: read_u64(header, 32) as usize,
+            program_header_count: usize::from(read_u16(header, 56)),
+        })
+    }
+
+    pub fn is_position_independent(&self) -> bool {
+        self.file_type == ET_DYN
+    }
+
+    pub fn get_entry(&self) -> u64 {
+        self.entry
+    }
+
+    pub fn program_headers(&self) -> impl Iterator<Item = ProgramHeader> + 'a {
+        let data = self.data;
+        let offset = self.program_headers_offset;
+
+        (0..self.program_header_count)
+            .map(move |index| ProgramHeader::parse(&data[offset + index * PROGRAM_HEADER_SIZE..]))
+    }
+
+    fn segments(&self) -> impl Iterator<Item = ProgramHeader> + 'a {
+        self.program_headers()
+            .filter(|header| header.segment_type == PT_LOAD)
+    }
+
+    /// Size of the file, up to the end of its last segment.
+    pub fn get_file_size(&self) -> u64 {
+        let headers_size =
+            self.program_headers_offset + self.program_header_count * PROGRAM_HEADER_SIZE;
+
+        loader::get_file_size(
+            headers_size as u64,
+            self.segments().map(|segment| segment.get_mapping()),
+        )
+    }
+
+    /// Virtual addresses covered by the segments.
+    pub fn get_load_range(&self) -> Result<Range<u64>> {
+        Ok(loader::get_load_range(
+            self.segments().map(|segment| segment.get_mapping()),
+        )?)
+    }
+
+    /// Largest segment alignment, a position independent image must be loaded on it.
+    pub fn get_alignment(&self) -> u64 {
+        self.segments()
+            .map(|segment| segment.alignment)
+            .max()
+            .unwrap_or(1)
+            .max(1)
+    }
+
+    /// Load the image in `memory`, placed at `address`, returning its entry point.
+    ///
+    /// Position independent images are loaded with their lowest address at `address` and
+    /// relocated, others must fit in `memory` at their own addresses.
+    pub fn load(&self, memory: &mut [u8], address: u64) -> Result<u64> {
+        let range = self.get_load_range()?;
+
+        let (load_bias, offset) = if self.is_position_independent() {
+            if address % self.get_alignment() != range.start % self.get_alignment() {
+                return Err(ElfError::MisalignedBase);
+            }
+
+            (address.wrapping_sub(range.start), 0)
+        } else {
+            let offset = range
+                .start
+                .checked_sub(address)
+                .ok_or(ElfError::DoesNotFit)?;
+
+            (0, offset as usize)
+        };
+
+        let image = loader::copy(
+            self.data,
+            self.segments().map(|segment| segment.get_mapping()),
+            &range,
+            memory.get_mut(offset..).ok_or(ElfError::DoesNotFit)?,
+        )?;
+
+        if self.is_position_independent() {
+            if let Some(dynamic) = self
+                .program_headers()
+                .find(|header| header.segment_type == PT_DYNAMIC)
+            {
+                relocate(image, range.start, load_bias, dynamic.virtual_address)?;
+            }
+        }
+
+        Ok(self.entry.wrapping_add(load_bias))
+    }
+}
+
+impl<'a> Image<'a> for ElfFile<'a> {
+    type Error = ElfError;
+
+    const HEADER_SIZE: usize = HEADER_SIZE;
+
+    fn get_headers_size(header: &[u8]) -> Result<usize> {
+        get_headers_size(header)
+    }
+
+    fn parse(data: &'a [u8]) -> Result<Self> {
+        ElfFile::parse(data)
+    }
+
+    fn get_file_size(&self) -> u64 {
+        ElfFile::get_file_size(self)
+    }
+
+    fn get_load_range(&self) -> Result<Range<u64>> {
+        ElfFile::get_load_range(self)
+    }
+
+    /// Position independent images go after their file, others run at their own addresses.
+    fn get_load_address(
+        &self,
+        file: &Range<u64>,
+        range: &Range<u64>,
+    ) -> core::result::Result<u64, PlacementError> {
+        if self.is_position_independent() {
+            let alignment = self.get_alignment().max(loader::LOAD_ALIGNMENT);
+
+            loader::get_address_after(file, alignment, range.start)
+        } else {
+            Ok(range.start)
+        }
+    }
+
+    fn load(&self, memory: &mut [u8], address: u64) -> Result<u64> {
+        ElfFile::load(self, memory, address)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Built from testdata/payload.S by testdata/build.sh.
+    const PIE: &[u8] = include_bytes!("../testdata/pie.elf");
+    const STATIC: &[u8] = include_bytes!("../testdata/static.elf");
+    const SHARED: &[u8] = include_bytes!("../testdata/shared.elf");
+
+    const MESSAGE: &[u8] = b"m1saka\0";
+
+    fn read(memory: &[u8], offset: u64) -> u64 {
+        read_u64(memory, offset as usize)
+    }
+
+    #[test]
+    fn headers() {
+        let file = ElfFile::parse(PIE).unwrap();
+
+        assert!(file.is_position_independent());
+        assert_eq!(file.get_entry(), 0x1278);
+        assert_eq!(file.get_load_range(), Ok(0x0..0x3470));
+        assert_eq!(file.get_alignment(), 0x1000);
+        assert_eq!(file.get_file_size(), 0x36F);
+        assert_eq!(get_headers_size(PIE), Ok(0x200));
+
+        let file = ElfFile::parse(STATIC).unwrap();
+
+        assert!(!file.is_position_independent());
+        assert_eq!(file.get_load_range(), Ok(0x8_0100_0000..0x8_0100_2280));
+    }
+
+    #[test]
+    fn load_position_independent() {
+        let file = ElfFile::parse(PIE).unwrap();
+        let mut memory = vec![0xFFu8; 0x4000];
+        let base = 0x8_0380_0000;
+
+        assert_eq!(file.load(&mut memory, base), Ok(base + 0x1278));
+
+        // Table of pointers to the message and the entry point, then the message and bss.
+        assert_eq!(read(&memory, 0x3358), base + 0x3368);
+        assert_eq!(read(&memory, 0x3360), base + 0x1278);
+        assert_eq!(&memory[0x3368..0x336F], MESSAGE);
+        assert!(memory[0x3370..0x3470].iter().all(|value| *value == 0));
+        assert_eq!(&memory[0x1278..0x1288], &PIE[0x278..0x288]);
+    }
+
+    #[test]
+    fn load_static() {
+        let file = ElfFile::parse(STATIC).unwrap();
+        let mut memory = vec![0xFFu8; 0x3000];
+
+        assert_eq!(file.load(&mut memory, 0x8_0100_0000), Ok(0x8_0100_1158));
+        assert_eq!(read(&memory, 0x2168), 0x8_0100_2178);
+        assert_eq!(&memory[0x2178..0x217F], MESSAGE);
+
+        assert_eq!(
+            file.load(&mut memory, 0x8_0100_1000),
+            Err(ElfError::DoesNotFit)
+        );
+        assert_eq!(
+            file.load(&mut memory[..0x2000], 0x8_0100_0000),
+            Err(ElfError::DoesNotFit)
+        );
+    }
+
+    #[test]
+    fn load_errors() {
+        let file = ElfFile::parse(PIE).unwrap();
+        let mut memory = vec![0x0u8; 0x4000];
+
+        assert_eq!(
+            file.load(&mut memory[..0x3000], 0x8_0000_0000),
+            Err(ElfError::DoesNotFit)
+        );
+        assert_eq!(
+            file.load(&mut memory, 0x8_0000_0800),
+            Err(ElfError::MisalignedBase)
+        );
+
+        let file = ElfFile::parse(&PIE[..0x300]).unwrap();
+
+        assert_eq!(
+            file.load(&mut memory, 0x8_0000_0000),
+            Err(ElfError::Truncated)
+        );
+    }
+
+    #[test]
+    fn invalid_headers() {
+        assert_eq!(
+            ElfFile::parse(&PIE[..0x20]).err(),
+            Some(ElfError::Truncated)
+        );
+        assert_eq!(
+            ElfFile::parse(&PIE[..0x100]).err(),
+            Some(ElfError::Truncated)
+        );
+
+        let mut data = PIE.to_vec();
+
+        data[0] = 0;
+
+        assert_eq!(ElfFile::parse(&data).err(), Some(ElfError::InvalidHeader));
+        assert!(!is_elf(&data));
+
+        let mut data = PIE.to_vec();
+
+        // x86_64
+        data[18] = 62;
+
+        assert_eq!(
+            ElfFile::parse(&data).err(),
+            Some(ElfError::UnsupportedMachine)
+        );
+
+        let mut data = PIE.to_vec();
+
+        // ET_REL
+        data[16] = 1;
+
+        assert_eq!(ElfFile::parse(&data).err(), Some(ElfError::UnsupportedType));
+    }
+
+    #[test]
+    fn load_packed_relocations() {
+        let file = ElfFile::parse(SHARED).unwrap();
+        let mut memory = vec![0xFFu8; 0x4000];
+        let base = 0x8_0380_0000;
+
+        assert_eq!(file.load(&mut memory, base), Ok(base + 0x1290));
+
+        // The message pointer is in DT_RELR, the entry point an absolute relocation to _start.
+        assert_eq!(read(&memory, 0x3370), base + 0x3380);
+        assert_eq!(read(&memory, 0x3378), base + 0x1290);
+        assert_eq!(&memory[0x3380..0x3387], MESSAGE);
+    }
+
+    #[test]
+    fn relr_bitmap() {
+        const DYNAMIC: u64 = 0x100;
+        const TABLE: u64 = 0x200;
+
+        let mut memory = vec![0x0u8; 0x800];
+        let mut write = |offset: u64, value: u64| {
+            let offset = offset as usize;
+
+            memory[offset..offset + 8].copy_from_slice(&value.to_le_bytes());
+        };
+
+        write(DYNAMIC, 36);
+        write(DYNAMIC + 0x8, TABLE);
+        write(DYNAMIC + 0x10, 35);
+        write(DYNAMIC + 0x18, 0x18);
+        write(DYNAMIC + 0x20, 37);
+        write(DYNAMIC + 0x28, 0x8);
+
+        // 0x300, then 0x310 and 0x318 from the first bitmap, 0x308 + 63 * 8 from the second.
+        write(TABLE, 0x300);
+        write(TABLE + 0x8, (0b110 << 1) | 1);
+        write(TABLE + 0x10, (1 << 1) | 1);
+
+        for offset in (0x300..0x800).step_by(8) {
+            write(offset, offset);
+        }
+
+        assert_eq!(relocate(&mut memory, 0, 0x1000, DYNAMIC), Ok(()));
+
+        for offset in (0x300..0x800).step_by(8) {
+            let expected = match offset {
+                0x300 | 0x310 | 0x318 | 0x500 => offset + 0x1000,
+                _ => offset,
+            };
+
+            assert_eq!(read(&memory, offset), expected, "0x{:x}", offset);
+        }
+    }
+
+    #[test]
+    fn unsupported_relocation() {
+        let file_data = PIE.to_vec();
+        let file = ElfFile::parse(&file_data).unwrap();
+        let mut memory = vec![0x0u8; 0x4000];
+
+        file.load(&mut memory, 0).unwrap();
+
+        // Turn the first relocation at 0x248 into R_AARCH64_NONE, leaving its target untouched.
+        memory[0x250] = 0x00;
+        memory[0x251] = 0x00;
+
+        assert_eq!(relocate(&mut memory[..0x3470], 0, 0x1000, 0x2288), Ok(()));
+        assert_eq!(read(&memory, 0x3358), 0x3368);
+        assert_eq!(read(&memory, 0x3360), 0x2278);
+
+        // R_AARCH64_ABS32
+        memory[0x250] = 0x02;
+        memory[0x251] = 0x01;
+
+        assert_eq!(
+            relocate(&mut memory[..0x3470], 0, 0x1000, 0x2288),
+            Err(RelocationError::UnsupportedRelocation(0x102))
+        );
+
+        // R_AARCH64_ABS64 against a symbol while there's no symbol table.
+        memory[0x250] = 0x01;
+        memory[0x254] = 0x01;
+
+        assert_eq!(
+            relocate(&mut memory[..0x3470], 0, 0x1000, 0x2288),
+            Err(RelocationError::UndefinedSymbol(1))
+        );
+
+        // Relocation table outside of the image.
+        assert_eq!(
+            relocate(&mut memory[..0x300], 0, 0x1000, 0x2288),
+            Err(RelocationError::OutOfBounds)
+        );
+    }
+}
@@ -0,0 +1,290 @@
+//! Dynamic relocations, shared by the loader and the payload relocating itself
+//!
+//! This also runs before the payload is relocated: it must not reference any static data,
+//! which rules out panics and formatting.
+
+const DT_NULL: u64 = 0;
+const DT_SYMTAB: u64 = 6;
+const DT_RELA: u64 = 7;
+const DT_RELASZ: u64 = 8;
+const DT_RELAENT: u64 = 9;
+const DT_SYMENT: u64 = 11;
+const DT_REL: u64 = 17;
+const DT_RELSZ: u64 = 18;
+const DT_RELENT: u64 = 19;
+const DT_RELRSZ: u64 = 35;
+const DT_RELR: u64 = 36;
+const DT_RELRENT: u64 = 37;
+
+const R_AARCH64_NONE: u32 = 0;
+const R_AARCH64_ABS64: u32 = 0x101;
+const R_AARCH64_RELATIVE: u32 = 0x403;
+
+const SHN_UNDEF: u16 = 0;
+const SHN_ABS: u16 = 0xfff1;
+
+const DYN_SIZE: usize = 16;
+const REL_SIZE: u64 = 16;
+const RELA_SIZE: u64 = 24;
+const RELR_SIZE: u64 = 8;
+const SYM_SIZE: u64 = 24;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum RelocationError {
+    InvalidRelaEntrySize,
+    InvalidRelEntrySize,
+    InvalidRelrEntrySize,
+    InvalidSymbolEntrySize,
+    UnsupportedRelocation(u32),
+    /// An absolute relocation refers to a symbol that isn't defined in the image.
+    UndefinedSymbol(u32),
+    /// A table or a relocated address is outside of the image.
+    OutOfBounds,
+}
+
+/// Image being relocated, `data` holding it from its lowest virtual address `base`.
+struct Image<'a> {
+    data: &'a mut [u8],
+    base: u64,
+}
+
+impl<'a> Image<'a> {
+    fn get_offset(&self, address: u64, size: usize) -> Result<usize, RelocationError> {
+        let offset = address.wrapping_sub(self.base) as usize;
+
+        match offset.checked_add(size) {
+            Some(end) if address >= self.base && end <= self.data.len() => Ok(offset),
+            _ => Err(RelocationError::OutOfBounds),
+        }
+    }
+
+    fn read_u64(&self, address: u64) -> Result<u64, RelocationError> {
+        let offset = self.get_offset(address, 8)?;
+        let mut value = [0x0u8; 8];
+
+        value.copy_from_slice(&self.data[offset..offset + 8]);
+
+        Ok(u64::from_le_bytes(value))
+    }
+
+    fn read_u16(&self, address: u64) -> Result<u16, RelocationError> {
+        let offset = self.get_offset(address, 2)?;
+
+        Ok(u16::from_le_bytes([
+            self.data[offset],
+            self.data[offset + 1],
+        ]))
+    }
+
+    fn write_u64(&mut self, address: u64, value: u64) -> Result<(), RelocationError> {
+        let offset = self.get_offset(address, 8)?;
+
+        self.data[offset..offset + 8].copy_from_slice(&value.to_le_bytes());
+
+        Ok(())
+    }
+}
+
+/// Symbol table from the dynamic section, used by absolute relocations.
+struct SymbolTable {
+    address: Option<u64>,
+    entry_size: u64,
+}
+
+impl SymbolTable {
+    /// Value of the symbol `index` once loaded, only symbols defined in the image are resolved.
+    fn get_value(&self, image: &Image, index: u32, load_bias: u64) -> Result<u64, RelocationError> {
+        if index == 0 {
+            return Ok(0);
+        }
+
+        let address = self
+            .address
+            .ok_or(RelocationError::UndefinedSymbol(index))?;
+
+        if self.entry_size != SYM_SIZE {
+            return Err(RelocationError::InvalidSymbolEntrySize);
+        }
+
+        let symbol = address + u64::from(index) * SYM_SIZE;
+        let section_index = image.read_u16(symbol + 6)?;
+        let value = image.read_u64(symbol + 8)?;
+
+        match section_index {
+            SHN_UNDEF => Err(RelocationError::UndefinedSymbol(index)),
+            SHN_ABS => Ok(value),
+            _ => Ok(value.wrapping_add(load_bias)),
+        }
+    }
+}
+
+/// Apply a relocation of `info` at `offset`, `addend` being None for implicit addends.
+fn apply_relocation(
+    image: &mut Image,
+    symbols: &SymbolTable,
+    load_bias: u64,
+    offset: u64,
+    info: u64,
+    addend: Option<u64>,
+) -> Result<(), RelocationError> {
+    let relocation_type = info as u32;
+
+    match relocation_type {
+        R_AARCH64_NONE => Ok(()),
+        R_AARCH64_RELATIVE => {
+            let addend = match addend {
+                Some(addend) => addend,
+                None => image.read_u64(offset)?,
+            };
+
+            image.write_u64(offset, load_bias.wrapping_add(addend))
+        }
+        R_AARCH64_ABS64 => {
+            let value = symbols.get_value(image, (info >> 32) as u32, load_bias)?;
+            let addend = match addend {
+                Some(addend) => addend,
+                None => image.read_u64(offset)?,
+            };
+
+            image.write_u64(offset, value.wrapping_add(addend))
+        }
+        _ => Err(RelocationError::UnsupportedRelocation(relocation_type)),
+    }
+}
+
+/// Apply the packed relative relocations of a `DT_RELR` table.
+///
+/// Even entries are an address to relocate, odd ones a bitmap of the 63 words following the
+/// last address.
+fn apply_relr(
+    image: &mut Image,
+    load_bias: u64,
+    table: u64,
+    size: u64,
+) -> Result<(), RelocationError> {
+    let mut next = 0;
+
+    for i in 0..size / RELR_SIZE {
+        let entry = image.read_u64(table + i * RELR_SIZE)?;
+
+        if entry & 1 == 0 {
+            let value = image.read_u64(entry)?;
+
+            image.write_u64(entry, value.wrapping_add(load_bias))?;
+            next = entry + RELR_SIZE;
+        } else {
+            let mut bitmap = entry >> 1;
+            let mut address = next;
+
+            while bitmap != 0 {
+                if bitmap & 1 != 0 {
+                    let value = image.read_u64(address)?;
+
+                    image.write_u64(address, value.wrapping_add(load_bias))?;
+                }
+
+                bitmap >>= 1;
+                address += RELR_SIZE;
+            }
+
+            next += 63 * RELR_SIZE;
+        }
+    }
+
+    Ok(())
+}
+
+/// Apply the relocations described by the dynamic section at virtual address `dynamic`.
+///
+/// `image` holds the image from its lowest virtual address `base`, `load_bias` is added to
+/// the virtual addresses to get where it runs. Besides relative relocations, absolute ones
+/// are resolved against the symbols defined in the image.
+pub fn relocate(
+    image: &mut [u8],
+    base: u64,
+    load_bias: u64,
+    dynamic: u64,
+) -> Result<(), RelocationError> {
+    let mut image = Image { data: image, base };
+
+    let mut rela_address = None;
+    let mut rela_entry_size = 0;
+    let mut rela_size = 0;
+
+    let mut rel_address = None;
+    let mut rel_entry_size = 0;
+    let mut rel_size = 0;
+
+    let mut relr_address = None;
+    let mut relr_entry_size = 0;
+    let mut relr_size = 0;
+
+    let mut symbols = SymbolTable {
+        address: None,
+        entry_size: 0,
+    };
+
+    let mut entry = dynamic;
+
+    loop {
+        let tag = image.read_u64(entry)?;
+        let value = image.read_u64(entry + 8)?;
+
+        match tag {
+            DT_NULL => break,
+            DT_RELA => rela_address = Some(value),
+            DT_RELAENT => rela_entry_size = value,
+            DT_RELASZ => rela_size = value,
+            DT_REL => rel_address = Some(value),
+            DT_RELENT => rel_entry_size = value,
+            DT_RELSZ => rel_size = value,
+            DT_RELR => relr_address = Some(value),
+            DT_RELRENT => relr_entry_size = value,
+            DT_RELRSZ => relr_size = value,
+            DT_SYMTAB => symbols.address = Some(value),
+            DT_SYMENT => symbols.entry_size = value,
+            _ => {}
+        }
+
+        entry += DYN_SIZE as u64;
+    }
+
+    if let Some(rela_address) = rela_address {
+        if rela_entry_size != RELA_SIZE {
+            return Err(RelocationError::InvalidRelaEntrySize);
+        }
+
+        for i in 0..rela_size / RELA_SIZE {
+            let rela = rela_address + i * RELA_SIZE;
+            let offset = image.read_u64(rela)?;
+            let info = image.read_u64(rela + 8)?;
+            let addend = image.read_u64(rela + 16)?;
+
+            apply_relocation(&mut image, &symbols, load_bias, offset, info, Some(addend))?;
+        }
+    }
+
+    if let Some(rel_address) = rel_address {
+        if rel_entry_size != REL_SIZE {
+            return Err(RelocationError::InvalidRelEntrySize);
+        }
+
+        for i in 0..rel_size / REL_SIZE {
+            let rel = rel_address + i * REL_SIZE;
+            let offset = image.read_u64(rel)?;
+            let info = image.read_u64(rel + 8)?;
+
+            apply_relocation(&mut image, &symbols, load_bias, offset, info, None)?;
+        }
+    }
+
+    if let Some(relr_address) = relr_address {
+        if relr_entry_size != RELR_SIZE {
+            return Err(RelocationError::InvalidRelrEntrySize);
+        }
+
+        apply_relr(&mut image, load_bias, relr_address, relr_size)?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,19 @@
+#!/bin/sh
+# Build the ELF fixtures of the elf unit tests with llvm-mc and rust-lld.
+set -e
+
+cd "$(dirname "$0")"
+
+llvm-mc -triple=aarch64-none-elf -filetype=obj -o payload.o payload.S
+
+# Position independent, its relocations are applied by the loader.
+rust-lld -flavor gnu --pie --no-dynamic-linker -z max-page-size=0x1000 --strip-all -e _start -o pie.elf payload.o
+
+# Linked at a fixed address.
+rust-lld -flavor gnu --static -z max-page-size=0x1000 --strip-all --image-base=0x801000000 -e _start -o static.elf payload.o
+
+# Shared object with packed relative relocations, _start being preemptible its address is
+# left to an absolute relocation.
+rust-lld -flavor gnu -shared --pack-dyn-relocs=relr -z max-page-size=0x1000 --strip-all -e _start -o shared.elf payload.o
+
+rm payload.o
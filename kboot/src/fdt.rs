@@ -0,0 +1,735 @@
+//! Flattened device tree codec
+//!
+//! Blobs are parsed into a tree that can be edited, then written back with the layout `dtc` uses:
+//! header, memory reservation map, structure block and strings block, without any padding.
+//! See https://devicetree-specification.readthedocs.io/en/stable/flattened-format.html for the format.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::convert::TryInto;
+
+const MAGIC: u32 = 0xD00D_FEED;
+const VERSION: u32 = 17;
+const LAST_COMPATIBLE_VERSION: u32 = 16;
+const HEADER_SIZE: usize = 40;
+const RESERVE_ENTRY_SIZE: usize = 16;
+
+const TOKEN_BEGIN_NODE: u32 = 0x1;
+const TOKEN_END_NODE: u32 = 0x2;
+const TOKEN_PROP: u32 = 0x3;
+const TOKEN_NOP: u32 = 0x4;
+const TOKEN_END: u32 = 0x9;
+
+/// Nodes are parsed recursively, a template nested deeper is rejected before it exhausts the stack.
+const MAX_DEPTH: usize = 64;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum FdtError {
+    InvalidHeader,
+    UnsupportedVersion,
+    Truncated,
+    InvalidToken(u32),
+    InvalidString,
+    /// Nodes are nested deeper than `MAX_DEPTH`.
+    TooDeep,
+}
+
+pub type Result<T> = core::result::Result<T, FdtError>;
+
+fn read_u32(data: &[u8], offset: usize) -> Result<u32> {
+    let value = data.get(offset..offset + 4).ok_or(FdtError::Truncated)?;
+
+    Ok(u32::from_be_bytes(value.try_into().unwrap()))
+}
+
+fn read_u64(data: &[u8], offset: usize) -> Result<u64> {
+    let value = data.get(offset..offset + 8).ok_or(FdtError::Truncated)?;
+
+    Ok(u64::from_be_bytes(value.try_into().unwrap()))
+}
+
+fn align(value: usize) -> usize {
+    (value + 3) & !3
+}
+
+/// Read a zero terminated string, returning it with its size including the terminator.
+fn read_str(data: &[u8], offset: usize) -> Result<(&str, usize)> {
+    let data = data.get(offset..).ok_or(FdtError::Truncated)?;
+    let size = data
+        .iter()
+        .position(|value| *value == 0)
+        .ok_or(FdtError::Truncated)?;
+    let value = core::str::from_utf8(&data[..size]).map_err(|_| FdtError::InvalidString)?;
+
+    Ok((value, size + 1))
+}
+
+/// Size of a blob from its header, used to know how much to read from memory.
+pub fn get_total_size(header: &[u8]) -> Result<usize> {
+    if read_u32(header, 0)? != MAGIC {
+        return Err(FdtError::InvalidHeader);
+    }
+
+    Ok(read_u32(header, 4)? as usize)
+}
+
+/// Entry of the memory reservation map.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct ReserveEntry {
+    pub address: u64,
+    pub size: u64,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Property {
+    pub name: String,
+    pub value: Vec<u8>,
+}
+
+impl Property {
+    pub fn as_u32(&self) -> Option<u32> {
+        Some(u32::from_be_bytes(self.value.get(..4)?.try_into().ok()?))
+    }
+
+    pub fn as_u64(&self) -> Option<u64> {
+        Some(u64::from_be_bytes(self.value.get(..8)?.try_into().ok()?))
+    }
+
+    /// String up to its terminator, None if it isn't valid UTF-8.
+    pub fn as_str(&self) -> Option<&str> {
+        let size = self
+            .value
+            .iter()
+            .position(|value| *value == 0)
+            .unwrap_or(self.value.len());
+
+        core::str::from_utf8(&self.value[..size]).ok()
+    }
+
+    /// Entries of a stringlist like `compatible`, the ones that aren't valid UTF-8 are skipped.
+    pub fn as_str_list(&self) -> impl Iterator<Item = &str> {
+        self.value
+            .split(|value| *value == 0)
+            .filter(|value| !value.is_empty())
+            .filter_map(|value| core::str::from_utf8(value).ok())
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Node {
+    /// Name including the unit address, like `memory@800000000`.
+    pub name: String,
+    pub properties: Vec<Property>,
+    pub children: Vec<Node>,
+}
+
+impl Node {
+    pub fn new(name: &str) -> Self {
+        Node {
+            name: String::from(name),
+            properties: Vec::new(),
+            children: Vec::new(),
+        }
+    }
+
+    /// Name without the unit address.
+    pub fn get_base_name(&self) -> &str {
+        self.name.split('@').next().unwrap_or("")
+    }
+
+    /// Like libfdt, a name without unit address matches any node with the same base name.
+    fn has_name(&self, name: &str) -> bool {
+        self.name == name || (!name.contains('@') && self.get_base_name() == name)
+    }
+
+    pub fn get_property(&self, name: &str) -> Option<&Property> {
+        self.properties
+            .iter()
+            .find(|property| property.name == name)
+    }
+
+    /// Set a property, replacing its value if it already exists.
+    pub fn set_property(&mut self, name: &str, value: &[u8]) {
+        match self
+            .properties
+            .iter_mut()
+            .find(|property| property.name == name)
+        {
+            Some(property) => property.value = value.to_vec(),
+            None => self.properties.push(Property {
+                name: String::from(name),
+                value: value.to_vec(),
+            }),
+        }
+    }
+
+    pub fn set_property_u32(&mut self, name: &str, value: u32) {
+        self.set_property(name, &value.to_be_bytes());
+    }
+
+    pub fn set_property_u64(&mut self, name: &str, value: u64) {
+        self.set_property(name, &value.to_be_bytes());
+    }
+
+    pub fn set_property_str(&mut self, name: &str, value: &str) {
+        let mut data = Vec::with_capacity(value.len() + 1);
+
+        data.extend_from_slice(value.as_bytes());
+        data.push(0);
+
+        self.set_property(name, &data);
+    }
+
+    pub fn remove_property(&mut self, name: &str) -> Option<Property> {
+        let index = self
+            .properties
+            .iter()
+            .position(|property| property.name == name)?;
+
+        Some(self.properties.remove(index))
+    }
+
+    pub fn get_child(&self, name: &str) -> Option<&Node> {
+        self.children.iter().find(|child| child.has_name(name))
+    }
+
+    pub fn get_child_mut(&mut self, name: &str) -> Option<&mut Node> {
+        self.children.iter_mut().find(|child| child.has_name(name))
+    }
+
+    /// Get a child, adding an empty one if it doesn't exist.
+    pub fn get_or_insert_child(&mut self, name: &str) -> &mut Node {
+        match self.children.iter().position(|child| child.has_name(name)) {
+            Some(index) => &mut self.children[index],
+            None => {
+                self.children.push(Node::new(name));
+                self.children.last_mut().unwrap()
+            }
+        }
+    }
+
+    pub fn find_node(&self, path: &str) -> Option<&Node> {
+        path.split('/')
+            .filter(|name| !name.is_empty())
+            .try_fold(self, |node, name| node.get_child(name))
+    }
+
+    pub fn find_node_mut(&mut self, path: &str) -> Option<&mut Node> {
+        path.split('/')
+            .filter(|name| !name.is_empty())
+            .try_fold(self, |node, name| node.get_child_mut(name))
+    }
+
+    pub fn is_compatible(&self, compatible: &str) -> bool {
+        self.get_property("compatible")
+            .filter(|property| property.as_str_list().any(|value| value == compatible))
+            .is_some()
+    }
+
+    /// Number of cells used by the addresses of the children, 2 by default.
+    pub fn get_address_cells(&self) -> u32 {
+        self.get_property("#address-cells")
+            .and_then(Property::as_u32)
+            .unwrap_or(2)
+    }
+
+    /// Number of cells used by the sizes of the children, 1 by default.
+    pub fn get_size_cells(&self) -> u32 {
+        self.get_property("#size-cells")
+            .and_then(Property::as_u32)
+            .unwrap_or(1)
+    }
+}
+
+struct Parser<'a> {
+    data: &'a [u8],
+    strings: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn token(&mut self) -> Result<u32> {
+        loop {
+            let token = read_u32(self.data, self.position)?;
+
+            self.position += 4;
+
+            if token != TOKEN_NOP {
+                return Ok(token);
+            }
+        }
+    }
+
+    /// Parse the content of a node after its begin token.
+    fn node(&mut self, depth: usize) -> Result<Node> {
+        if depth > MAX_DEPTH {
+            return Err(FdtError::TooDeep);
+        }
+
+        let (name, size) = read_str(self.data, self.position)?;
+        let mut node = Node::new(name);
+
+        self.position = align(self.position + size);
+
+        loop {
+            match self.token()? {
+                TOKEN_PROP => {
+                    let size = read_u32(self.data, self.position)? as usize;
+                    let name_offset = read_u32(self.data, self.position + 4)? as usize;
+                    let (name, _) = read_str(self.strings, name_offset)?;
+                    let start = self.position + 8;
+                    let value = self
+                        .data
+                        .get(start..start + size)
+                        .ok_or(FdtError::Truncated)?;
+
+                    node.properties.push(Property {
+                        name: String::from(name),
+                        value: value.to_vec(),
+                    });
+
+                    self.position = align(start + size);
+                }
+                TOKEN_BEGIN_NODE => node.children.push(self.node(depth + 1)?),
+                TOKEN_END_NODE => return Ok(node),
+                token => return Err(FdtError::InvalidToken(token)),
+            }
+        }
+    }
+}
+
+/// Strings block, sharing names with the suffix of an existing one like `dtc`.
+struct StringTable {
+    data: Vec<u8>,
+}
+
+impl StringTable {
+    fn insert(&mut self, value: &str) -> u32 {
+        let value = value.as_bytes();
+
+        for offset in 0..self.data.len() {
+            let candidate = &self.data[offset..];
+
+            if candidate.len() > value.len()
+                && candidate[..value.len()] == *value
+                && candidate[value.len()] == 0
+            {
+                return offset as u32;
+            }
+        }
+
+        let offset = self.data.len();
+
+        self.data.extend_from_slice(value);
+        self.data.push(0);
+
+        offset as u32
+    }
+}
+
+fn write_u32(data: &mut Vec<u8>, value: u32) {
+    data.extend_from_slice(&value.to_be_bytes());
+}
+
+fn write_padding(data: &mut Vec<u8>) {
+    data.resize(align(data.len()), 0);
+}
+
+fn write_node(node: &Node, data: &mut Vec<u8>, strings: &mut StringTable) {
+    write_u32(data, TOKEN_BEGIN_NODE);
+    data.extend_from_slice(node.name.as_bytes());
+    data.push(0);
+    write_padding(data);
+
+    for property in node.properties.iter() {
+        write_u32(data, TOKEN_PROP);
+        write_u32(data, property.value.len() as u32);
+        write_u32(data, strings.insert(&property.name));
+        data.extend_from_slice(&property.value);
+        write_padding(data);
+    }
+
+    for child in node.children.iter() {
+        write_node(child, data, strings);
+    }
+
+    write_u32(data, TOKEN_END_NODE);
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct DeviceTree {
+    pub reserved_memory: Vec<ReserveEntry>,
+    pub boot_cpuid: u32,
+    pub root: Node,
+}
+
+impl DeviceTree {
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        let total_size = get_total_size(data)?;
+        let data = data.get(..total_size).ok_or(FdtError::Truncated)?;
+
+        let struct_offset = read_u32(data, 8)? as usize;
+        let strings_offset = read_u32(data, 12)? as usize;
+        let reserve_map_offset = read_u32(data, 16)? as usize;
+        let version = read_u32(data, 20)?;
+        let last_compatible_version = read_u32(data, 24)?;
+        let boot_cpuid = read_u32(data, 28)?;
+        let strings_size = read_u32(data, 32)? as usize;
+
+        if version < LAST_COMPATIBLE_VERSION || last_compatible_version > VERSION {
+            return Err(FdtError::UnsupportedVersion);
+        }
+
+        let strings = data
+            .get(strings_offset..strings_offset + strings_size)
+            .ok_or(FdtError::Truncated)?;
+
+        let mut reserved_memory = Vec::new();
+        let mut offset = reserve_map_offset;
+
+        loop {
+            let entry = ReserveEntry {
+                address: read_u64(data, offset)?,
+                size: read_u64(data, offset + 8)?,
+            };
+
+            offset += RESERVE_ENTRY_SIZE;
+
+            if entry.address == 0 && entry.size == 0 {
+                break;
+            }
+
+            reserved_memory.push(entry);
+        }
+
+        let mut parser = Parser {
+            data: data.get(struct_offset..).ok_or(FdtError::Truncated)?,
+            strings,
+            position: 0,
+        };
+
+        let root = match parser.token()? {
+            TOKEN_BEGIN_NODE => parser.node(0)?,
+            token => return Err(FdtError::InvalidToken(token)),
+        };
+
+        match parser.token()? {
+            TOKEN_END => Ok(DeviceTree {
+                reserved_memory,
+                boot_cpuid,
+                root,
+            }),
+            token => Err(FdtError::InvalidToken(token)),
+        }
+    }
+
+    pub fn find_node(&self, path: &str) -> Option<&Node> {
+        self.root.find_node(path)
+    }
+
+    pub fn find_node_mut(&mut self, path: &str) -> Option<&mut Node> {
+        self.root.find_node_mut(path)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut strings = StringTable { data: Vec::new() };
+        let mut structure = Vec::new();
+
+        write_node(&self.root, &mut structure, &mut strings);
+        write_u32(&mut structure, TOKEN_END);
+
+        let reserve_map_size = (self.reserved_memory.len() + 1) * RESERVE_ENTRY_SIZE;
+        let struct_offset = HEADER_SIZE + reserve_map_size;
+        let strings_offset = struct_offset + structure.len();
+        let total_size = strings_offset + strings.data.len();
+
+        let mut data = Vec::with_capacity(total_size);
+
+        for value in [
+            MAGIC,
+            total_size as u32,
+            struct_offset as u32,
+            strings_offset as u32,
+            HEADER_SIZE as u32,
+            VERSION,
+            LAST_COMPATIBLE_VERSION,
+            self.boot_cpuid,
+            strings.data.len() as u32,
+            structure.len() as u32,
+        ]
+        .iter()
+        {
+            write_u32(&mut data, *value);
+        }
+
+        for entry in self.reserved_memory.iter() {
+            data.extend_from_slice(&entry.address.to_be_bytes());
+            data.extend_from_slice(&entry.size.to_be_bytes());
+        }
+
+        data.extend_from_slice(&[0; RESERVE_ENTRY_SIZE]);
+        data.extend_from_slice(&structure);
+        data.extend_from_slice(&strings.data);
+
+        data
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    use std::io::Write;
+    use std::process::{Command, Stdio};
+
+    /// Built from testdata/template.dts by testdata/build.sh.
+    const TEMPLATE: &[u8] = include_bytes!("../testdata/template.dtb");
+
+    /// Run `dtc` with `input` on its standard input, returning what it wrote.
+    fn run_dtc(args: &[&str], input: &[u8]) -> Vec<u8> {
+        let mut dtc = Command::new("dtc")
+            .arg("-q")
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .expect("Cannot run dtc");
+
+        dtc.stdin.take().unwrap().write_all(input).unwrap();
+
+        let output = dtc.wait_with_output().unwrap();
+
+        assert!(output.status.success(), "dtc failed");
+
+        output.stdout
+    }
+
+    /// Whether `dtc` is in `PATH`, the tests going through it pass without checking anything
+    /// otherwise.
+    pub(crate) fn has_dtc() -> bool {
+        let found = Command::new("dtc")
+            .arg("--version")
+            .stdout(Stdio::null())
+            .status()
+            .is_ok();
+
+        if !found {
+            eprintln!("dtc isn't in PATH, skipping");
+        }
+
+        found
+    }
+
+    /// Have `dtc` decode a blob and encode it again, which gives back the same bytes if it was
+    /// written correctly with the same layout.
+    pub(crate) fn dtc_round_trip(data: &[u8]) -> Vec<u8> {
+        run_dtc(&["-I", "dtb", "-O", "dtb", "-o", "-", "-"], data)
+    }
+
+    #[test]
+    fn parse() {
+        let tree = DeviceTree::parse(TEMPLATE).unwrap();
+
+        assert_eq!(tree.boot_cpuid, 0);
+        assert_eq!(tree.root.name, "");
+        assert!(tree.root.is_compatible("apple,t8103"));
+        assert_eq!(tree.root.get_address_cells(), 2);
+
+        let memory = tree.find_node("/memory").unwrap();
+
+        assert_eq!(memory.name, "memory@800000000");
+        assert_eq!(
+            memory.get_property("device_type").unwrap().as_str(),
+            Some("memory")
+        );
+        assert!(tree.find_node("/memory@800000000").is_some());
+        assert!(tree.find_node("/memory@900000000").is_none());
+
+        let names: Vec<&str> = tree
+            .find_node("/cpus")
+            .unwrap()
+            .children
+            .iter()
+            .map(|node| node.name.as_str())
+            .collect();
+
+        assert_eq!(
+            names,
+            [
+                "cpu@0",
+                "cpu@1",
+                "cpu@2",
+                "cpu@3",
+                "cpu@10100",
+                "cpu@10101",
+                "cpu@10102",
+                "cpu@10103"
+            ]
+        );
+        assert_eq!(
+            tree.find_node("/cpus/cpu@10100")
+                .unwrap()
+                .get_property("reg")
+                .unwrap()
+                .as_u64(),
+            Some(0x10100)
+        );
+        assert_eq!(
+            tree.reserved_memory,
+            [ReserveEntry {
+                address: 0x8_0000_0000,
+                size: 0x4000
+            }]
+        );
+    }
+
+    #[test]
+    fn round_trip() {
+        let tree = DeviceTree::parse(TEMPLATE).unwrap();
+
+        assert_eq!(tree.to_bytes(), TEMPLATE);
+    }
+
+    #[test]
+    fn edit_round_trip() {
+        let mut tree = DeviceTree::parse(TEMPLATE).unwrap();
+        let chosen = tree.find_node_mut("/chosen").unwrap();
+
+        chosen.set_property_str("bootargs", "console=ttySAC0 earlycon");
+        chosen.set_property_u64("linux,initrd-start", 0x8_1000_0000);
+        chosen.set_property_u32("stdout-path", 1);
+        chosen.remove_property("stdout-path");
+        chosen
+            .get_or_insert_child("test@0")
+            .set_property("empty", &[]);
+        tree.find_node_mut("/cpus")
+            .unwrap()
+            .children
+            .retain(|node| node.name != "cpu@3");
+
+        let data = tree.to_bytes();
+        let parsed = DeviceTree::parse(&data).unwrap();
+
+        assert_eq!(parsed, tree);
+
+        let chosen = parsed.find_node("/chosen").unwrap();
+
+        assert_eq!(
+            chosen.get_property("bootargs").unwrap().as_str(),
+            Some("console=ttySAC0 earlycon")
+        );
+        assert_eq!(
+            chosen.get_property("linux,initrd-start").unwrap().as_u64(),
+            Some(0x8_1000_0000)
+        );
+        assert!(chosen.get_property("stdout-path").is_none());
+        assert!(parsed.find_node("/chosen/test").is_some());
+        assert!(parsed.find_node("/cpus/cpu@3").is_none());
+    }
+
+    #[test]
+    fn template_from_dtc() {
+        if !has_dtc() {
+            return;
+        }
+
+        let source = include_bytes!("../testdata/template.dts");
+
+        assert_eq!(
+            run_dtc(&["-I", "dts", "-O", "dtb", "-o", "-", "-"], source),
+            TEMPLATE
+        );
+    }
+
+    #[test]
+    fn edit_read_by_dtc() {
+        if !has_dtc() {
+            return;
+        }
+
+        let mut tree = DeviceTree::parse(TEMPLATE).unwrap();
+        let chosen = tree.find_node_mut("/chosen").unwrap();
+
+        chosen.set_property_str("bootargs", "console=ttySAC0 earlycon");
+        chosen.set_property_u64("linux,initrd-start", 0x8_1000_0000);
+        chosen
+            .get_or_insert_child("test@0")
+            .set_property("empty", &[]);
+        tree.find_node_mut("/cpus")
+            .unwrap()
+            .children
+            .retain(|node| node.name != "cpu@3");
+        tree.reserved_memory.push(ReserveEntry {
+            address: 0x9_0000_0000,
+            size: 0x10_0000,
+        });
+
+        let data = tree.to_bytes();
+
+        assert_eq!(dtc_round_trip(&data), data);
+
+        let source = run_dtc(&["-I", "dtb", "-O", "dts", "-o", "-", "-"], &data);
+        let source = String::from_utf8(source).unwrap();
+
+        assert!(source.contains("bootargs = \"console=ttySAC0 earlycon\";"));
+        assert!(source.contains("test@0 {"));
+        assert!(!source.contains("cpu@3 {"));
+    }
+
+    #[test]
+    fn shared_strings() {
+        let mut strings = StringTable { data: Vec::new() };
+
+        assert_eq!(strings.insert("#size-cells"), 0);
+        assert_eq!(strings.insert("size-cells"), 1);
+        assert_eq!(strings.insert("cells"), 6);
+        assert_eq!(strings.insert("size"), 12);
+        assert_eq!(strings.insert("#size-cells"), 0);
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(
+            DeviceTree::parse(&TEMPLATE[1..]),
+            Err(FdtError::InvalidHeader)
+        );
+        assert_eq!(
+            DeviceTree::parse(&TEMPLATE[..TEMPLATE.len() - 1]),
+            Err(FdtError::Truncated)
+        );
+
+        let mut data = TEMPLATE.to_vec();
+
+        // First token of the structure block.
+        let struct_offset = read_u32(&data, 8).unwrap() as usize;
+
+        data[struct_offset + 3] = 0x7;
+
+        assert_eq!(DeviceTree::parse(&data), Err(FdtError::InvalidToken(0x7)));
+
+        let mut data = TEMPLATE.to_vec();
+
+        data[23] = 0x2;
+
+        assert_eq!(DeviceTree::parse(&data), Err(FdtError::UnsupportedVersion));
+    }
+
+    #[test]
+    fn depth_limit() {
+        let mut tree = DeviceTree::parse(TEMPLATE).unwrap();
+        let mut node = &mut tree.root;
+
+        for _ in 0..MAX_DEPTH {
+            node = node.get_or_insert_child("nested");
+        }
+
+        assert_eq!(DeviceTree::parse(&tree.to_bytes()), Ok(tree.clone()));
+
+        tree.root
+            .find_node_mut(&"/nested".repeat(MAX_DEPTH))
+            .unwrap()
+            .get_or_insert_child("nested");
+
+        assert_eq!(DeviceTree::parse(&tree.to_bytes()), Err(FdtError::TooDeep));
+    }
+}
@@ -0,0 +1,555 @@
+//! Linux kernel boot support
+//!
+//! A device tree template is filled the way m1n1's kboot does: `/chosen`, `/memory`, the CPUs
+//! present in the Apple device tree and the framebuffer iBoot set up.
+#![cfg_attr(not(test), no_std)]
+
+extern crate alloc;
+
+pub mod fdt;
+
+use adt::Adt;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use fdt::{DeviceTree, FdtError, Node};
+
+/// Magic of the arm64 Image header, at offset 0x38.
+pub const IMAGE_MAGIC: u32 = 0x644D_5241;
+pub const IMAGE_MAGIC_OFFSET: usize = 0x38;
+
+/// Affinity fields of MPIDR_EL1, which both device trees use as the `reg` of a CPU.
+const MPIDR_AFFINITY_MASK: u64 = 0xFF_00FF_FFFF;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum KbootError {
+    Fdt(FdtError),
+    /// The template doesn't have a node that has to be filled.
+    MissingNode(&'static str),
+    /// A value doesn't fit the cells of its parent.
+    UnsupportedCells,
+    UnsupportedFramebufferDepth(u32),
+    /// The kernel doesn't start with an arm64 Image header.
+    InvalidImage,
+    /// Booting was requested before the device tree was prepared.
+    DeviceTreeNotReady,
+    MissingBootArguments,
+    InvalidString,
+}
+
+impl From<FdtError> for KbootError {
+    fn from(error: FdtError) -> Self {
+        KbootError::Fdt(error)
+    }
+}
+
+pub type Result<T> = core::result::Result<T, KbootError>;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct MemoryRange {
+    pub address: u64,
+    pub size: u64,
+}
+
+/// Framebuffer set up by iBoot.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Framebuffer {
+    pub address: u64,
+    pub width: u32,
+    pub height: u32,
+    pub stride: u32,
+    /// Bits per pixel, iBoot keeps flags in the upper bits.
+    pub depth: u32,
+}
+
+impl Framebuffer {
+    fn get_format(&self) -> Result<&'static str> {
+        match self.depth & 0xFF {
+            30 => Ok("x2r10g10b10"),
+            32 => Ok("a8r8g8b8"),
+            depth => Err(KbootError::UnsupportedFramebufferDepth(depth)),
+        }
+    }
+}
+
+/// What the device tree is filled with.
+pub struct BootInfo<'a> {
+    pub memory: MemoryRange,
+    pub framebuffer: Option<Framebuffer>,
+    pub initrd: Option<MemoryRange>,
+    /// String properties of `/chosen`, like `bootargs`.
+    pub chosen: &'a [(String, String)],
+    pub adt: Option<Adt<'a>>,
+}
+
+/// Check a kernel Image header.
+pub fn is_valid_image(header: &[u8]) -> bool {
+    match header.get(IMAGE_MAGIC_OFFSET..IMAGE_MAGIC_OFFSET + 4) {
+        Some(magic) => u32::from_le_bytes([magic[0], magic[1], magic[2], magic[3]]) == IMAGE_MAGIC,
+        None => false,
+    }
+}
+
+fn write_cells(data: &mut Vec<u8>, value: u64, cells: u32) -> Result<()> {
+    match cells {
+        1 if value <= u64::from(u32::MAX) => data.extend_from_slice(&(value as u32).to_be_bytes()),
+        2 => data.extend_from_slice(&value.to_be_bytes()),
+        _ => return Err(KbootError::UnsupportedCells),
+    }
+
+    Ok(())
+}
+
+/// Encode a `reg` entry with the cells of `parent`.
+fn encode_reg(parent: &Node, range: &MemoryRange) -> Result<Vec<u8>> {
+    let mut data = Vec::new();
+
+    write_cells(&mut data, range.address, parent.get_address_cells())?;
+    write_cells(&mut data, range.size, parent.get_size_cells())?;
+
+    Ok(data)
+}
+
+fn set_chosen(tree: &mut DeviceTree, info: &BootInfo) {
+    let has_serial = tree
+        .find_node("/aliases")
+        .and_then(|aliases| aliases.get_property("serial0"))
+        .is_some();
+    let chosen = tree.root.get_or_insert_child("chosen");
+
+    for (name, value) in info.chosen.iter() {
+        chosen.set_property_str(name, value);
+    }
+
+    if let Some(initrd) = info.initrd {
+        chosen.set_property_u64("linux,initrd-start", initrd.address);
+        chosen.set_property_u64("linux,initrd-end", initrd.address + initrd.size);
+    }
+
+    if has_serial && chosen.get_property("stdout-path").is_none() {
+        chosen.set_property_str("stdout-path", "serial0");
+    }
+}
+
+fn set_memory(tree: &mut DeviceTree, memory: &MemoryRange) -> Result<()> {
+    let reg = encode_reg(&tree.root, memory)?;
+    let node = tree.root.get_or_insert_child("memory");
+
+    node.name = format!("memory@{:x}", memory.address);
+    node.set_property_str("device_type", "memory");
+    node.set_property("reg", &reg);
+
+    Ok(())
+}
+
+/// First address of a `reg` property written with `cells` address cells.
+fn get_reg_address(node: &Node, cells: u32) -> Option<u64> {
+    let reg = node.get_property("reg")?;
+
+    match cells {
+        1 => reg.as_u32().map(u64::from),
+        2 => reg.as_u64(),
+        _ => None,
+    }
+}
+
+/// Remove the CPUs the Apple device tree doesn't have, matching them by `reg` like m1n1.
+fn set_cpus(tree: &mut DeviceTree, adt: &Adt) -> Result<()> {
+    let adt_cpus = match adt.find_node("/cpus") {
+        Some(cpus) => cpus,
+        None => return Ok(()),
+    };
+
+    let cpus = tree
+        .find_node_mut("/cpus")
+        .ok_or(KbootError::MissingNode("/cpus"))?;
+    let address_cells = cpus.get_address_cells();
+
+    cpus.children.retain(|node| {
+        let is_cpu = node
+            .get_property("device_type")
+            .and_then(|property| property.as_str())
+            == Some("cpu");
+
+        if !is_cpu {
+            return true;
+        }
+
+        let mpidr = match get_reg_address(node, address_cells) {
+            Some(reg) => reg & MPIDR_AFFINITY_MASK,
+            None => return false,
+        };
+
+        adt_cpus.children().any(|cpu| {
+            cpu.get_property("reg")
+                .and_then(|property| property.as_u32())
+                .map(u64::from)
+                == Some(mpidr)
+        })
+    });
+
+    Ok(())
+}
+
+fn set_framebuffer(tree: &mut DeviceTree, framebuffer: &Framebuffer) -> Result<()> {
+    let chosen = tree
+        .find_node_mut("/chosen")
+        .ok_or(KbootError::MissingNode("/chosen"))?;
+    let range = MemoryRange {
+        address: framebuffer.address,
+        size: u64::from(framebuffer.stride) * u64::from(framebuffer.height),
+    };
+    let reg = encode_reg(chosen, &range)?;
+
+    let node = match chosen
+        .children
+        .iter_mut()
+        .find(|node| node.is_compatible("simple-framebuffer"))
+    {
+        Some(node) => node,
+        None => return Ok(()),
+    };
+
+    node.name = format!("framebuffer@{:x}", framebuffer.address);
+    node.set_property("reg", &reg);
+    node.set_property_u32("width", framebuffer.width);
+    node.set_property_u32("height", framebuffer.height);
+    node.set_property_u32("stride", framebuffer.stride);
+    node.set_property_str("format", framebuffer.get_format()?);
+    node.set_property_str("status", "okay");
+
+    Ok(())
+}
+
+/// Fill a device tree template, returning the blob to pass to the kernel.
+pub fn prepare_dt(template: &[u8], info: &BootInfo) -> Result<Vec<u8>> {
+    let mut tree = DeviceTree::parse(template)?;
+
+    set_chosen(&mut tree, info);
+    set_memory(&mut tree, &info.memory)?;
+
+    if let Some(adt) = info.adt.as_ref() {
+        set_cpus(&mut tree, adt)?;
+    }
+
+    if let Some(framebuffer) = info.framebuffer.as_ref() {
+        set_framebuffer(&mut tree, framebuffer)?;
+    }
+
+    Ok(tree.to_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEMPLATE: &[u8] = include_bytes!("../testdata/template.dtb");
+    const J274: &[u8] = include_bytes!("../../adt/testdata/j274.adt");
+
+    const MEMORY: MemoryRange = MemoryRange {
+        address: 0x8_0000_0000,
+        size: 0x2_0000_0000,
+    };
+
+    const FRAMEBUFFER: Framebuffer = Framebuffer {
+        address: 0x9_E000_0000,
+        width: 1920,
+        height: 1080,
+        stride: 7680,
+        depth: 30 | 0x1_0000,
+    };
+
+    fn prepare(info: &BootInfo) -> Result<DeviceTree> {
+        let data = prepare_dt(TEMPLATE, info)?;
+
+        Ok(DeviceTree::parse(&data)?)
+    }
+
+    fn get_chosen() -> Vec<(String, String)> {
+        vec![(
+            String::from("bootargs"),
+            String::from("earlycon console=ttySAC0,1500000"),
+        )]
+    }
+
+    #[test]
+    fn chosen() {
+        let chosen = get_chosen();
+        let tree = prepare(&BootInfo {
+            memory: MEMORY,
+            framebuffer: None,
+            initrd: Some(MemoryRange {
+                address: 0x8_1000_0000,
+                size: 0x80_0000,
+            }),
+            chosen: &chosen,
+            adt: None,
+        })
+        .unwrap();
+
+        let chosen = tree.find_node("/chosen").unwrap();
+        let get_property = |name| chosen.get_property(name).unwrap();
+
+        assert_eq!(
+            get_property("bootargs").as_str(),
+            Some("earlycon console=ttySAC0,1500000")
+        );
+        assert_eq!(
+            get_property("linux,initrd-start").as_u64(),
+            Some(0x8_1000_0000)
+        );
+        assert_eq!(
+            get_property("linux,initrd-end").as_u64(),
+            Some(0x8_1080_0000)
+        );
+        assert_eq!(get_property("stdout-path").as_str(), Some("serial0"));
+    }
+
+    #[test]
+    fn chosen_override() {
+        let chosen = vec![(String::from("stdout-path"), String::from("/soc/serial@0"))];
+        let tree = prepare(&BootInfo {
+            memory: MEMORY,
+            framebuffer: None,
+            initrd: None,
+            chosen: &chosen,
+            adt: None,
+        })
+        .unwrap();
+
+        let chosen = tree.find_node("/chosen").unwrap();
+
+        assert_eq!(
+            chosen.get_property("stdout-path").unwrap().as_str(),
+            Some("/soc/serial@0")
+        );
+        assert!(chosen.get_property("linux,initrd-start").is_none());
+    }
+
+    #[test]
+    fn memory() {
+        let tree = prepare(&BootInfo {
+            memory: MemoryRange {
+                address: 0x8_0400_0000,
+                size: 0x1_FC00_0000,
+            },
+            framebuffer: None,
+            initrd: None,
+            chosen: &[],
+            adt: None,
+        })
+        .unwrap();
+
+        let memory = tree.find_node("/memory").unwrap();
+
+        assert_eq!(memory.name, "memory@804000000");
+        assert_eq!(
+            memory.get_property("reg").unwrap().value,
+            [0, 0, 0, 0x8, 0x04, 0, 0, 0, 0, 0, 0, 0x1, 0xFC, 0, 0, 0]
+        );
+        assert_eq!(
+            tree.root
+                .children
+                .iter()
+                .filter(|node| node.get_base_name() == "memory")
+                .count(),
+            1
+        );
+    }
+
+    #[test]
+    fn cpus() {
+        let tree = prepare(&BootInfo {
+            memory: MEMORY,
+            framebuffer: None,
+            initrd: None,
+            chosen: &[],
+            adt: Adt::new(J274),
+        })
+        .unwrap();
+
+        let names: Vec<&str> = tree
+            .find_node("/cpus")
+            .unwrap()
+            .children
+            .iter()
+            .map(|node| node.name.as_str())
+            .collect();
+
+        assert_eq!(names, ["cpu@0", "cpu@1"]);
+    }
+
+    /// Apple device tree with a CPU node for each of `regs`.
+    fn make_adt_cpus(regs: &[u32]) -> Vec<u8> {
+        fn node(properties: &[(&str, &[u8])], child_count: u32) -> Vec<u8> {
+            let mut data = Vec::new();
+
+            data.extend_from_slice(&(properties.len() as u32).to_le_bytes());
+            data.extend_from_slice(&child_count.to_le_bytes());
+
+            for (name, value) in properties.iter() {
+                let mut name = name.as_bytes().to_vec();
+
+                name.resize(32, 0);
+                data.extend_from_slice(&name);
+                data.extend_from_slice(&(value.len() as u32).to_le_bytes());
+                data.extend_from_slice(value);
+                data.resize((data.len() + 3) & !3, 0);
+            }
+
+            data
+        }
+
+        let mut data = node(&[("name", b"device-tree\0")], 1);
+
+        data.extend(node(&[("name", b"cpus\0")], regs.len() as u32));
+
+        for (index, reg) in regs.iter().enumerate() {
+            data.extend(node(
+                &[
+                    ("name", format!("cpu{}\0", index).as_bytes()),
+                    ("cpu-id", &(index as u32).to_le_bytes()),
+                    ("reg", &reg.to_le_bytes()),
+                ],
+                0,
+            ));
+        }
+
+        data
+    }
+
+    #[test]
+    fn cpus_matched_by_reg() {
+        // Out of order, without cpu@1 and cpu@10102, with one the template doesn't have.
+        let adt = make_adt_cpus(&[0x10103, 0x0, 0x10101, 0x2, 0x10100, 0x3, 0x10200]);
+        let tree = prepare(&BootInfo {
+            memory: MEMORY,
+            framebuffer: None,
+            initrd: None,
+            chosen: &[],
+            adt: Adt::new(&adt),
+        })
+        .unwrap();
+
+        let names: Vec<&str> = tree
+            .find_node("/cpus")
+            .unwrap()
+            .children
+            .iter()
+            .map(|node| node.name.as_str())
+            .collect();
+
+        assert_eq!(
+            names,
+            [
+                "cpu@0",
+                "cpu@2",
+                "cpu@3",
+                "cpu@10100",
+                "cpu@10101",
+                "cpu@10103"
+            ]
+        );
+    }
+
+    #[test]
+    fn framebuffer() {
+        let tree = prepare(&BootInfo {
+            memory: MEMORY,
+            framebuffer: Some(FRAMEBUFFER),
+            initrd: None,
+            chosen: &[],
+            adt: None,
+        })
+        .unwrap();
+
+        let node = tree.find_node("/chosen/framebuffer").unwrap();
+        let get_property = |name| node.get_property(name).unwrap();
+
+        assert_eq!(node.name, "framebuffer@9e0000000");
+        assert_eq!(
+            get_property("reg").value,
+            [0, 0, 0, 0x9, 0xE0, 0, 0, 0, 0, 0, 0, 0, 0x00, 0x7E, 0x90, 0x00]
+        );
+        assert_eq!(get_property("width").as_u32(), Some(1920));
+        assert_eq!(get_property("stride").as_u32(), Some(7680));
+        assert_eq!(get_property("format").as_str(), Some("x2r10g10b10"));
+        assert_eq!(get_property("status").as_str(), Some("okay"));
+
+        assert_eq!(
+            prepare(&BootInfo {
+                memory: MEMORY,
+                framebuffer: Some(Framebuffer {
+                    depth: 16,
+                    ..FRAMEBUFFER
+                }),
+                initrd: None,
+                chosen: &[],
+                adt: None,
+            }),
+            Err(KbootError::UnsupportedFramebufferDepth(16))
+        );
+    }
+
+    #[test]
+    fn prepared_read_by_dtc() {
+        if !fdt::tests::has_dtc() {
+            return;
+        }
+
+        let chosen = get_chosen();
+        let adt = make_adt_cpus(&[0x0, 0x2, 0x10100]);
+        let data = prepare_dt(
+            TEMPLATE,
+            &BootInfo {
+                memory: MEMORY,
+                framebuffer: Some(FRAMEBUFFER),
+                initrd: Some(MemoryRange {
+                    address: 0x8_1000_0000,
+                    size: 0x80_0000,
+                }),
+                chosen: &chosen,
+                adt: Adt::new(&adt),
+            },
+        )
+        .unwrap();
+
+        assert_eq!(fdt::tests::dtc_round_trip(&data), data);
+    }
+
+    #[test]
+    fn unsupported_cells() {
+        let mut tree = DeviceTree::parse(TEMPLATE).unwrap();
+
+        tree.root.set_property_u32("#size-cells", 1);
+
+        let template = tree.to_bytes();
+
+        assert_eq!(
+            prepare_dt(
+                &template,
+                &BootInfo {
+                    memory: MEMORY,
+                    framebuffer: None,
+                    initrd: None,
+                    chosen: &[],
+                    adt: None,
+                }
+            ),
+            Err(KbootError::UnsupportedCells)
+        );
+    }
+
+    #[test]
+    fn image_header() {
+        let mut header = [0x0u8; 0x40];
+
+        assert!(!is_valid_image(&header));
+
+        header[IMAGE_MAGIC_OFFSET..IMAGE_MAGIC_OFFSET + 4].copy_from_slice(b"ARM\x64");
+
+        assert!(is_valid_image(&header));
+        assert!(!is_valid_image(&header[..0x3B]));
+    }
+}
@@ -0,0 +1,7 @@
+#!/bin/sh
+# Build the device tree template of the kboot unit tests with dtc.
+set -e
+
+cd "$(dirname "$0")"
+
+dtc -I dts -O dtb -o template.dtb template.dts
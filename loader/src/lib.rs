@@ -0,0 +1,55 @@
+//! Loading of the images booted from the proxy, shared by the ELF and Mach-O loaders
+//!
+//! An image uploaded to the target is parsed in place, copied to the address it runs at and
+//! started. The formats only differ in how they describe their segments and entry point.
+#![cfg_attr(not(test), no_std)]
+
+mod placement;
+mod segment;
+
+use core::convert::TryInto;
+use core::ops::Range;
+
+pub use placement::{get_address_after, get_range, place, PlacementError};
+pub use segment::{copy, get_file_size, get_load_range, Mapping, SegmentError};
+
+/// Minimal alignment of the images loaded right after their file.
+pub const LOAD_ALIGNMENT: u64 = 0x10000;
+
+pub fn read_u16(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes(data[offset..offset + 2].try_into().unwrap())
+}
+
+pub fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+pub fn read_u64(data: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap())
+}
+
+/// Executable format that can be booted from a file uploaded to memory.
+pub trait Image<'a>: Sized {
+    type Error;
+
+    /// Bytes to read before the size of all the headers is known.
+    const HEADER_SIZE: usize;
+
+    /// Size of the headers given the first `HEADER_SIZE` bytes of the file.
+    fn get_headers_size(header: &[u8]) -> Result<usize, Self::Error>;
+
+    /// Validate the image, `data` holding at least its headers.
+    fn parse(data: &'a [u8]) -> Result<Self, Self::Error>;
+
+    /// Bytes of the file the image needs, headers and segments.
+    fn get_file_size(&self) -> u64;
+
+    /// Virtual addresses the image spans.
+    fn get_load_range(&self) -> Result<Range<u64>, Self::Error>;
+
+    /// Address the image is loaded at, when its file is at `file` and it spans `range`.
+    fn get_load_address(&self, file: &Range<u64>, range: &Range<u64>) -> placement::Result<u64>;
+
+    /// Load the image in `memory`, placed at `address`, returning its entry point.
+    fn load(&self, memory: &mut [u8], address: u64) -> Result<u64, Self::Error>;
+}
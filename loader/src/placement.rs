@@ -0,0 +1,174 @@
+//! Choosing where an image runs
+//!
+//! Until it starts, the copy of an image must leave both its uploaded file and the running payload
+//! untouched.
+
+use core::ops::Range;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum PlacementError {
+    /// The range ends past the end of the address space.
+    Overflow,
+    /// The image would overwrite its file while being copied from it.
+    OverlapsFile,
+    /// The image would overwrite memory in use until it starts, like the running payload.
+    OverlapsReserved,
+}
+
+pub type Result<T> = core::result::Result<T, PlacementError>;
+
+fn overlaps(left: &Range<u64>, right: &Range<u64>) -> bool {
+    left.start < right.end && right.start < left.end
+}
+
+/// Range of `size` bytes at `address`.
+pub fn get_range(address: u64, size: u64) -> Result<Range<u64>> {
+    let end = address.checked_add(size).ok_or(PlacementError::Overflow)?;
+
+    Ok(address..end)
+}
+
+/// First address after `file` aligned to `alignment`, plus the `offset` the image has within
+/// its alignment.
+///
+/// Relocatable images go there, past the file so it isn't overwritten.
+pub fn get_address_after(file: &Range<u64>, alignment: u64, offset: u64) -> Result<u64> {
+    let alignment = alignment.max(1);
+
+    file.end
+        .checked_add(alignment - 1)
+        .map(|end| end / alignment * alignment)
+        .and_then(|address| address.checked_add(offset % alignment))
+        .ok_or(PlacementError::Overflow)
+}
+
+/// Memory covered by an image of `size` bytes loaded at `address`, checking it overlaps neither
+/// its `file` nor the `reserved` ranges.
+pub fn place(
+    address: u64,
+    size: u64,
+    file: &Range<u64>,
+    reserved: &[Range<u64>],
+) -> Result<Range<u64>> {
+    let range = get_range(address, size)?;
+
+    if overlaps(&range, file) {
+        return Err(PlacementError::OverlapsFile);
+    }
+
+    if reserved.iter().any(|reserved| overlaps(&range, reserved)) {
+        return Err(PlacementError::OverlapsReserved);
+    }
+
+    Ok(range)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FILE: Range<u64> = 0x8_0000_0000..0x8_0001_2345;
+    const PAYLOAD: Range<u64> = 0x8_0400_0000..0x8_0423_0000;
+
+    #[test]
+    fn ranges() {
+        assert_eq!(get_range(0x1000, 0x2000), Ok(0x1000..0x3000));
+        assert_eq!(get_range(u64::MAX, 0), Ok(u64::MAX..u64::MAX));
+        assert_eq!(get_range(u64::MAX, 1), Err(PlacementError::Overflow));
+        assert_eq!(
+            get_range(0xFFFF_FFFF_FFFF_0000, 0x1_0000),
+            Err(PlacementError::Overflow)
+        );
+    }
+
+    #[test]
+    fn address_after() {
+        assert_eq!(get_address_after(&FILE, 0x10000, 0), Ok(0x8_0002_0000));
+        assert_eq!(
+            get_address_after(&FILE, 0x10000, 0x14000),
+            Ok(0x8_0002_4000)
+        );
+        assert_eq!(get_address_after(&(0..0x10000), 0x10000, 0), Ok(0x10000));
+        assert_eq!(get_address_after(&(0..0x10001), 0, 0), Ok(0x10001));
+        assert_eq!(
+            get_address_after(&(0..u64::MAX - 0x100), 0x10000, 0),
+            Err(PlacementError::Overflow)
+        );
+        assert_eq!(
+            get_address_after(&(0..u64::MAX - 0xFFFF), 0x10000, 0x100),
+            Ok(0xFFFF_FFFF_FFFF_0100)
+        );
+    }
+
+    #[test]
+    fn placement() {
+        let reserved = [PAYLOAD];
+
+        assert_eq!(
+            place(0x8_0002_0000, 0x4000, &FILE, &reserved),
+            Ok(0x8_0002_0000..0x8_0002_4000)
+        );
+        // Right against the file and the payload on both sides.
+        assert_eq!(
+            place(FILE.end, PAYLOAD.start - FILE.end, &FILE, &reserved),
+            Ok(FILE.end..PAYLOAD.start)
+        );
+        assert_eq!(
+            place(PAYLOAD.end, 0x1000, &FILE, &reserved),
+            Ok(PAYLOAD.end..PAYLOAD.end + 0x1000)
+        );
+        assert_eq!(place(0x1000, 0, &FILE, &reserved), Ok(0x1000..0x1000));
+    }
+
+    #[test]
+    fn placement_overlapping_file() {
+        let reserved = [PAYLOAD];
+
+        assert_eq!(
+            place(FILE.start, 0x1000, &FILE, &reserved),
+            Err(PlacementError::OverlapsFile)
+        );
+        assert_eq!(
+            place(FILE.end - 1, 0x1000, &FILE, &reserved),
+            Err(PlacementError::OverlapsFile)
+        );
+        assert_eq!(
+            place(FILE.start - 0x1000, 0x1001, &FILE, &reserved),
+            Err(PlacementError::OverlapsFile)
+        );
+    }
+
+    #[test]
+    fn placement_overlapping_payload() {
+        let reserved = [0x1000..0x2000, PAYLOAD];
+
+        // A non relocatable image linked where the payload runs.
+        assert_eq!(
+            place(PAYLOAD.start, 0x8000, &FILE, &reserved),
+            Err(PlacementError::OverlapsReserved)
+        );
+        // One ending in the payload heap or stack.
+        assert_eq!(
+            place(0x8_0300_0000, 0x110_0000, &FILE, &reserved),
+            Err(PlacementError::OverlapsReserved)
+        );
+        assert_eq!(
+            place(PAYLOAD.end - 1, 0x10, &FILE, &reserved),
+            Err(PlacementError::OverlapsReserved)
+        );
+        // One covering the whole payload.
+        assert_eq!(
+            place(0x8_0300_0000, 0x200_0000, &FILE, &reserved),
+            Err(PlacementError::OverlapsReserved)
+        );
+        assert_eq!(
+            place(0x1800, 0x10, &FILE, &reserved),
+            Err(PlacementError::OverlapsReserved)
+        );
+        // An end wrapping around the address space is never compared.
+        assert_eq!(
+            place(0xFFFF_FFFF_FFFF_0000, 0x1_0000, &FILE, &reserved),
+            Err(PlacementError::Overflow)
+        );
+    }
+}
@@ -0,0 +1,168 @@
+//! Copying the file data of an image to memory
+//!
+//! ELF program headers and Mach-O segment commands describe the same thing: a range of the file
+//! mapped at a virtual address, zero filled past its file data. Both loaders go through these
+//! helpers once their headers are validated.
+
+use core::ops::Range;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum SegmentError {
+    /// No segment, or one wrapping around the address space or with more file data than memory.
+    InvalidSegment,
+    /// The file ends before the data of a segment.
+    Truncated,
+    /// The memory given to hold the image is too small.
+    DoesNotFit,
+}
+
+pub type Result<T> = core::result::Result<T, SegmentError>;
+
+/// Part of a file mapped in memory.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Mapping {
+    pub virtual_address: u64,
+    pub memory_size: u64,
+    pub file_offset: u64,
+    pub file_size: u64,
+}
+
+/// Virtual addresses spanned by `mappings`, from the lowest to the end of the highest.
+pub fn get_load_range(mappings: impl Iterator<Item = Mapping>) -> Result<Range<u64>> {
+    let mut range: Option<Range<u64>> = None;
+
+    for mapping in mappings {
+        let end = mapping
+            .virtual_address
+            .checked_add(mapping.memory_size)
+            .ok_or(SegmentError::InvalidSegment)?;
+
+        if mapping.file_size > mapping.memory_size {
+            return Err(SegmentError::InvalidSegment);
+        }
+
+        range = Some(match range {
+            Some(range) => range.start.min(mapping.virtual_address)..range.end.max(end),
+            None => mapping.virtual_address..end,
+        });
+    }
+
+    range.ok_or(SegmentError::InvalidSegment)
+}
+
+/// Bytes of the file to read, its `headers_size` bytes of headers or up to the end of the last
+/// mapped data if it goes further.
+pub fn get_file_size(headers_size: u64, mappings: impl Iterator<Item = Mapping>) -> u64 {
+    mappings
+        .map(|mapping| mapping.file_offset.saturating_add(mapping.file_size))
+        .fold(headers_size, u64::max)
+}
+
+/// Zero the first `range` bytes of `memory`, then copy the file data of `mappings` in it, `memory`
+/// starting at `range.start`. Returns the memory holding the image.
+pub fn copy<'a>(
+    data: &[u8],
+    mappings: impl Iterator<Item = Mapping>,
+    range: &Range<u64>,
+    memory: &'a mut [u8],
+) -> Result<&'a mut [u8]> {
+    let image = memory
+        .get_mut(..(range.end - range.start) as usize)
+        .ok_or(SegmentError::DoesNotFit)?;
+
+    for value in image.iter_mut() {
+        *value = 0;
+    }
+
+    for mapping in mappings {
+        let start = mapping.file_offset as usize;
+        let data = start
+            .checked_add(mapping.file_size as usize)
+            .and_then(|end| data.get(start..end))
+            .ok_or(SegmentError::Truncated)?;
+        let destination = (mapping.virtual_address - range.start) as usize;
+
+        image[destination..destination + data.len()].copy_from_slice(data);
+    }
+
+    Ok(image)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAPPINGS: [Mapping; 2] = [
+        Mapping {
+            virtual_address: 0x2000,
+            memory_size: 0x100,
+            file_offset: 0x10,
+            file_size: 0x8,
+        },
+        Mapping {
+            virtual_address: 0x1000,
+            memory_size: 0x10,
+            file_offset: 0x0,
+            file_size: 0x10,
+        },
+    ];
+
+    #[test]
+    fn ranges() {
+        assert_eq!(get_load_range(MAPPINGS.iter().copied()), Ok(0x1000..0x2100));
+        assert_eq!(get_file_size(0x8, MAPPINGS.iter().copied()), 0x18);
+        assert_eq!(get_file_size(0x40, MAPPINGS.iter().copied()), 0x40);
+
+        assert_eq!(
+            get_load_range(core::iter::empty()),
+            Err(SegmentError::InvalidSegment)
+        );
+
+        let mut mapping = MAPPINGS[0];
+
+        mapping.file_size = 0x101;
+
+        assert_eq!(
+            get_load_range(core::iter::once(mapping)),
+            Err(SegmentError::InvalidSegment)
+        );
+
+        mapping.virtual_address = u64::MAX - 0xFF;
+
+        assert_eq!(
+            get_load_range(core::iter::once(mapping)),
+            Err(SegmentError::InvalidSegment)
+        );
+    }
+
+    #[test]
+    fn copies() {
+        let data: Vec<u8> = (1..=0x18).collect();
+        let mut memory = vec![0xFFu8; 0x1200];
+        let range = 0x1000..0x2100;
+
+        assert_eq!(
+            copy(&data, MAPPINGS.iter().copied(), &range, &mut memory).map(|image| image.len()),
+            Ok(0x1100)
+        );
+        assert_eq!(&memory[..0x10], &data[..0x10]);
+        assert_eq!(&memory[0x1000..0x1008], &data[0x10..]);
+        assert!(memory[0x10..0x1000].iter().all(|value| *value == 0));
+        assert!(memory[0x1008..0x1100].iter().all(|value| *value == 0));
+        assert!(memory[0x1100..].iter().all(|value| *value == 0xFF));
+
+        assert_eq!(
+            copy(
+                &data,
+                MAPPINGS.iter().copied(),
+                &range,
+                &mut memory[..0x10FF]
+            ),
+            Err(SegmentError::DoesNotFit)
+        );
+        assert_eq!(
+            copy(&data[..0x17], MAPPINGS.iter().copied(), &range, &mut memory),
+            Err(SegmentError::Truncated)
+        );
+    }
+}
@@ -0,0 +1,1219 @@
+//! m1n1 UART proxy protocol codec
+//!
+//! This crate only deals with the wire format and is generic over the serial transport,
+//! so it can be shared between m1saka and host tooling.
+#![cfg_attr(not(test), no_std)]
+
+mod opcode;
+mod server;
+mod transport;
+
+pub use opcode::ProxyOpcode;
+pub use server::{run_proxy, ProxyHandler, BYTE_TIMEOUT};
+pub use transport::{
+    Loopback, LoopbackError, Mailbox, MailboxRing, MailboxTransport, ProxyTransport,
+    MAILBOX_RING_SIZE,
+};
+
+use core::convert::From;
+use core::convert::TryFrom;
+use core::convert::TryInto;
+
+use embedded_hal::serial::{Read, Write};
+use embedded_hal::timer::CountDown;
+use void::Void;
+
+/// Size of a request packet.
+pub const REQUEST_SIZE: usize = 64;
+
+/// Size of a reply packet.
+pub const REPLY_SIZE: usize = 36;
+
+/// Magic present at the start of every request.
+pub const REQUEST_MAGIC: [u8; 3] = [0xFF, 0x55, 0xAA];
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[repr(u32)]
+pub enum CommandId {
+    NoOperation,
+    Proxy,
+    MemoryRead,
+    MemoryWrite,
+    Boot,
+}
+
+impl TryFrom<u32> for CommandId {
+    type Error = &'static str;
+
+    fn try_from(value: u32) -> Result<Self, Self::Error> {
+        if value & 0x00FFFFFF != 0x00AA55FF {
+            return Err("Invalid magic");
+        }
+
+        CommandId::try_from((value >> 24) as u8)
+    }
+}
+
+impl TryFrom<u8> for CommandId {
+    type Error = &'static str;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(CommandId::NoOperation),
+            1 => Ok(CommandId::Proxy),
+            2 => Ok(CommandId::MemoryRead),
+            3 => Ok(CommandId::MemoryWrite),
+            4 => Ok(CommandId::Boot),
+            _ => Err("Unknown command"),
+        }
+    }
+}
+
+impl From<CommandId> for u8 {
+    fn from(value: CommandId) -> u8 {
+        value as u8
+    }
+}
+
+impl From<CommandId> for u32 {
+    fn from(value: CommandId) -> u32 {
+        u32::from(u8::from(value)) << 24 | 0x00AA55FF
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[repr(i32)]
+pub enum Status {
+    Ok = 0,
+    BadCommand = -1,
+    Invalid = -2,
+    TransferError = -3,
+    ChecksumMismatch = -4,
+}
+
+impl TryFrom<i32> for Status {
+    type Error = &'static str;
+
+    fn try_from(value: i32) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(Status::Ok),
+            -1 => Ok(Status::BadCommand),
+            -2 => Ok(Status::Invalid),
+            -3 => Ok(Status::TransferError),
+            -4 => Ok(Status::ChecksumMismatch),
+            _ => Err("Unknown status"),
+        }
+    }
+}
+
+impl From<Status> for i32 {
+    fn from(value: Status) -> i32 {
+        value as i32
+    }
+}
+
+/// Status of a proxy operation, matching m1n1's `S_*` codes.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[repr(i64)]
+pub enum ProxyStatus {
+    Ok = 0,
+    BadCommand = -1,
+    Exception = -2,
+    Rejected = -3,
+}
+
+impl TryFrom<i64> for ProxyStatus {
+    type Error = &'static str;
+
+    fn try_from(value: i64) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(ProxyStatus::Ok),
+            -1 => Ok(ProxyStatus::BadCommand),
+            -2 => Ok(ProxyStatus::Exception),
+            -3 => Ok(ProxyStatus::Rejected),
+            _ => Err("Unknown proxy status"),
+        }
+    }
+}
+
+impl From<ProxyStatus> for i64 {
+    fn from(value: ProxyStatus) -> i64 {
+        value as i64
+    }
+}
+
+/// Error while decoding a packet.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum DecodeError {
+    InvalidMagic,
+    UnknownCommand(u8),
+    UnknownStatus(i32),
+    UnknownProxyStatus(i64),
+    ChecksumMismatch {
+        command_id: CommandId,
+        expected: u32,
+        computed: u32,
+    },
+}
+
+/// Error while receiving or sending a packet over a serial transport.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Error<E> {
+    Serial(E),
+    Decode(DecodeError),
+    /// No byte was received before the timeout expired.
+    Timeout,
+    /// The request stopped arriving after its command id.
+    Truncated(CommandId),
+}
+
+impl<E> From<DecodeError> for Error<E> {
+    fn from(error: DecodeError) -> Self {
+        Error::Decode(error)
+    }
+}
+
+pub fn checksum(buffer: &[u8]) -> u32 {
+    let mut sum: u32 = 0xDEADBEEF;
+
+    for val in buffer {
+        sum = sum.wrapping_mul(31337);
+        sum = sum.wrapping_add(u32::from(*val ^ 0x5A));
+    }
+
+    sum ^ 0xADDEDBAD
+}
+
+fn read_u32(raw: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(raw[offset..offset + 4].try_into().unwrap())
+}
+
+fn read_u64(raw: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(raw[offset..offset + 8].try_into().unwrap())
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct ProxyReply {
+    pub opcode: u64,
+    pub status: ProxyStatus,
+    pub return_value: u64,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum UartReply {
+    Simple {
+        command_id: CommandId,
+        status: Status,
+    },
+    Proxy {
+        command_id: CommandId,
+        status: Status,
+        reply: ProxyReply,
+    },
+    Memory {
+        command_id: CommandId,
+        status: Status,
+        data_checksum: u32,
+    },
+}
+
+impl UartReply {
+    pub const fn no_operation() -> Self {
+        UartReply::Simple {
+            command_id: CommandId::NoOperation,
+            status: Status::Ok,
+        }
+    }
+
+    pub const fn boot() -> Self {
+        UartReply::Simple {
+            command_id: CommandId::Boot,
+            status: Status::Ok,
+        }
+    }
+
+    pub fn proxy(reply: ProxyReply) -> Self {
+        UartReply::Proxy {
+            command_id: CommandId::Proxy,
+            status: Status::Ok,
+            reply,
+        }
+    }
+
+    pub fn memory(command_id: CommandId, data_checksum: u32) -> Self {
+        UartReply::Memory {
+            command_id,
+            status: Status::Ok,
+            data_checksum,
+        }
+    }
+
+    pub fn simple_error(command_id: CommandId, status: Status) -> Self {
+        UartReply::Simple { command_id, status }
+    }
+
+    pub fn simple_error_from_request(request: UartRequest, status: Status) -> Self {
+        UartReply::Simple {
+            command_id: request.get_command_id(),
+            status,
+        }
+    }
+
+    pub fn get_command_id(&self) -> CommandId {
+        match self {
+            UartReply::Simple { command_id, .. } => *command_id,
+            UartReply::Proxy { command_id, .. } => *command_id,
+            UartReply::Memory { command_id, .. } => *command_id,
+        }
+    }
+
+    pub fn get_status(&self) -> Status {
+        match self {
+            UartReply::Simple { status, .. } => *status,
+            UartReply::Proxy { status, .. } => *status,
+            UartReply::Memory { status, .. } => *status,
+        }
+    }
+
+    pub fn to_raw_packet(&self) -> [u8; REPLY_SIZE] {
+        let mut result = [0; REPLY_SIZE];
+
+        let slice = &mut result[..];
+
+        match self {
+            UartReply::Simple { command_id, status } => {
+                let command_id = &u32::to_le_bytes(u32::from(*command_id))[..];
+                let status = &i32::to_le_bytes(i32::from(*status))[..];
+
+                slice[..4].copy_from_slice(command_id);
+                slice[4..8].copy_from_slice(status);
+            }
+            UartReply::Proxy {
+                command_id,
+                status,
+                reply,
+            } => {
+                let command_id = &u32::to_le_bytes(u32::from(*command_id))[..];
+                let status = &i32::to_le_bytes(i32::from(*status))[..];
+
+                slice[..4].copy_from_slice(command_id);
+                slice[4..8].copy_from_slice(status);
+
+                let opcode = &u64::to_le_bytes(reply.opcode)[..];
+                let proxy_status = &i64::to_le_bytes(i64::from(reply.status))[..];
+                let ret_value = &u64::to_le_bytes(reply.return_value)[..];
+
+                slice[8..16].copy_from_slice(opcode);
+                slice[16..24].copy_from_slice(proxy_status);
+                slice[24..32].copy_from_slice(ret_value);
+            }
+            UartReply::Memory {
+                command_id,
+                status,
+                data_checksum,
+            } => {
+                let command_id = &u32::to_le_bytes(u32::from(*command_id))[..];
+                let status = &i32::to_le_bytes(i32::from(*status))[..];
+                let data_checksum = &u32::to_le_bytes(*data_checksum)[..];
+
+                slice[..4].copy_from_slice(command_id);
+                slice[4..8].copy_from_slice(status);
+                slice[8..12].copy_from_slice(data_checksum);
+            }
+        }
+
+        // Update checksum
+        let checksum = checksum(&slice[..32]);
+        let raw_checksum = &u32::to_le_bytes(checksum)[..];
+
+        slice[32..].copy_from_slice(raw_checksum);
+
+        result
+    }
+
+    pub fn from_raw_packet(raw_packet: &[u8; REPLY_SIZE]) -> Result<Self, DecodeError> {
+        let raw_command_id = read_u32(raw_packet, 0);
+
+        if raw_command_id & 0x00FFFFFF != 0x00AA55FF {
+            return Err(DecodeError::InvalidMagic);
+        }
+
+        let command_id = CommandId::try_from(raw_command_id)
+            .map_err(|_| DecodeError::UnknownCommand((raw_command_id >> 24) as u8))?;
+
+        let expected_checksum = read_u32(raw_packet, 32);
+        let computed_checksum = checksum(&raw_packet[..32]);
+
+        if expected_checksum != computed_checksum {
+            return Err(DecodeError::ChecksumMismatch {
+                command_id,
+                expected: expected_checksum,
+                computed: computed_checksum,
+            });
+        }
+
+        let raw_status = read_u32(raw_packet, 4) as i32;
+        let status =
+            Status::try_from(raw_status).map_err(|_| DecodeError::UnknownStatus(raw_status))?;
+
+        match command_id {
+            CommandId::Proxy if status == Status::Ok => {
+                let raw_proxy_status = read_u64(raw_packet, 16) as i64;
+                let proxy_status = ProxyStatus::try_from(raw_proxy_status)
+                    .map_err(|_| DecodeError::UnknownProxyStatus(raw_proxy_status))?;
+
+                Ok(UartReply::Proxy {
+                    command_id,
+                    status,
+                    reply: ProxyReply {
+                        opcode: read_u64(raw_packet, 8),
+                        status: proxy_status,
+                        return_value: read_u64(raw_packet, 24),
+                    },
+                })
+            }
+            CommandId::MemoryRead | CommandId::MemoryWrite => Ok(UartReply::Memory {
+                command_id,
+                status,
+                data_checksum: read_u32(raw_packet, 8),
+            }),
+            _ => Ok(UartReply::Simple { command_id, status }),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct ProxyRequest {
+    pub opcode: u64,
+    pub args: [u64; 6],
+}
+
+impl ProxyRequest {
+    pub fn is_exit(&self) -> bool {
+        self.opcode == ProxyOpcode::Exit as u64
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum UartRequest {
+    Simple {
+        command_id: CommandId,
+    },
+    Proxy {
+        command_id: CommandId,
+        request: ProxyRequest,
+    },
+    Memory {
+        command_id: CommandId,
+        address: u64,
+        size: u64,
+        data_checksum: u32,
+    },
+    Boot {
+        command_id: CommandId,
+        entry: u64,
+    },
+}
+
+impl UartRequest {
+    pub fn get_command_id(&self) -> CommandId {
+        match self {
+            UartRequest::Simple { command_id } => *command_id,
+            UartRequest::Proxy { command_id, .. } => *command_id,
+            UartRequest::Memory { command_id, .. } => *command_id,
+            UartRequest::Boot { command_id, .. } => *command_id,
+        }
+    }
+
+    pub fn to_raw_packet(&self) -> [u8; REQUEST_SIZE] {
+        let mut result = [0; REQUEST_SIZE];
+
+        let slice = &mut result[..];
+
+        let command_id = &u32::to_le_bytes(u32::from(self.get_command_id()))[..];
+
+        slice[..4].copy_from_slice(command_id);
+
+        match self {
+            UartRequest::Simple { .. } => {}
+            UartRequest::Proxy { request, .. } => {
+                slice[4..12].copy_from_slice(&u64::to_le_bytes(request.opcode)[..]);
+
+                for (i, arg) in request.args.iter().enumerate() {
+                    let offset = 12 + i * core::mem::size_of::<u64>();
+
+                    slice[offset..offset + 8].copy_from_slice(&u64::to_le_bytes(*arg)[..]);
+                }
+            }
+            UartRequest::Memory {
+                address,
+                size,
+                data_checksum,
+                ..
+            } => {
+                slice[4..12].copy_from_slice(&u64::to_le_bytes(*address)[..]);
+                slice[12..20].copy_from_slice(&u64::to_le_bytes(*size)[..]);
+                slice[20..24].copy_from_slice(&u32::to_le_bytes(*data_checksum)[..]);
+            }
+            UartRequest::Boot { entry, .. } => {
+                slice[4..12].copy_from_slice(&u64::to_le_bytes(*entry)[..]);
+            }
+        }
+
+        // Update checksum
+        let checksum = checksum(&slice[..60]);
+        let raw_checksum = &u32::to_le_bytes(checksum)[..];
+
+        slice[60..].copy_from_slice(raw_checksum);
+
+        result
+    }
+
+    pub fn from_raw_packet(raw_packet: &[u8; REQUEST_SIZE]) -> Result<Self, DecodeError> {
+        if raw_packet[..3] != REQUEST_MAGIC {
+            return Err(DecodeError::InvalidMagic);
+        }
+
+        let raw_command_id = raw_packet[3];
+        let command_id = CommandId::try_from(raw_command_id)
+            .map_err(|_| DecodeError::UnknownCommand(raw_command_id))?;
+
+        let expected_checksum = read_u32(raw_packet, 60);
+        let computed_checksum = checksum(&raw_packet[..60]);
+
+        if expected_checksum != computed_checksum {
+            return Err(DecodeError::ChecksumMismatch {
+                command_id,
+                expected: expected_checksum,
+                computed: computed_checksum,
+            });
+        }
+
+        match command_id {
+            CommandId::Proxy => {
+                let mut args = [0x0; 6];
+
+                for (i, arg) in args.iter_mut().enumerate() {
+                    *arg = read_u64(raw_packet, 12 + i * core::mem::size_of::<u64>());
+                }
+
+                let request = ProxyRequest {
+                    opcode: read_u64(raw_packet, 4),
+                    args,
+                };
+
+                Ok(UartRequest::Proxy {
+                    command_id,
+                    request,
+                })
+            }
+            CommandId::MemoryRead | CommandId::MemoryWrite => Ok(UartRequest::Memory {
+                command_id,
+                address: read_u64(raw_packet, 4),
+                size: read_u64(raw_packet, 12),
+                data_checksum: read_u32(raw_packet, 20),
+            }),
+            CommandId::Boot => Ok(UartRequest::Boot {
+                command_id,
+                entry: read_u64(raw_packet, 4),
+            }),
+            _ => Ok(UartRequest::Simple { command_id }),
+        }
+    }
+}
+
+fn read_byte<S: Read<u8>>(serial: &mut S) -> Result<u8, Error<S::Error>> {
+    nb::block!(serial.read()).map_err(Error::Serial)
+}
+
+fn read_byte_timeout<S: Read<u8>, T: CountDown>(
+    serial: &mut S,
+    timer: &mut T,
+    timeout: T::Time,
+) -> Result<u8, Error<S::Error>> {
+    timer.start(timeout);
+
+    loop {
+        match serial.read() {
+            Ok(value) => return Ok(value),
+            Err(nb::Error::Other(error)) => return Err(Error::Serial(error)),
+            Err(nb::Error::WouldBlock) => {
+                if timer.wait().is_ok() {
+                    return Err(Error::Timeout);
+                }
+            }
+        }
+    }
+}
+
+fn write_bytes<S: Write<u8>>(serial: &mut S, data: &[u8]) -> Result<(), Error<S::Error>> {
+    for value in data {
+        nb::block!(serial.write(*value)).map_err(Error::Serial)?;
+    }
+
+    Ok(())
+}
+
+/// Countdown that never expires, used when reads are allowed to block forever.
+pub struct NoTimeout;
+
+impl CountDown for NoTimeout {
+    type Time = ();
+
+    fn start<T: Into<Self::Time>>(&mut self, _count: T) {}
+
+    fn wait(&mut self) -> nb::Result<(), Void> {
+        Err(nb::Error::WouldBlock)
+    }
+}
+
+/// Receive a request, waiting forever between bytes.
+pub fn read_request<S: Read<u8>>(serial: &mut S) -> Result<UartRequest, Error<S::Error>> {
+    read_request_timeout(serial, &mut NoTimeout, ())
+}
+
+/// Receive a request, giving up if the gap between two bytes of the packet exceeds `byte_timeout`.
+///
+/// The input is scanned for the request magic followed by a known command id, so garbage and
+/// partial packets are skipped instead of desynchronising the stream.
+/// Waiting for the start of a packet is not bounded by the timeout.
+pub fn read_request_timeout<S, T>(
+    serial: &mut S,
+    timer: &mut T,
+    byte_timeout: T::Time,
+) -> Result<UartRequest, Error<S::Error>>
+where
+    S: Read<u8>,
+    T: CountDown,
+    T::Time: Clone,
+{
+    let mut raw_packet = [0x0u8; REQUEST_SIZE];
+
+    let command_id = loop {
+        raw_packet.copy_within(1..4, 0);
+
+        if raw_packet[..3] != REQUEST_MAGIC {
+            raw_packet[3] = read_byte(serial)?;
+            continue;
+        }
+
+        raw_packet[3] = read_byte_timeout(serial, timer, byte_timeout.clone())?;
+
+        if let Ok(command_id) = CommandId::try_from(raw_packet[3]) {
+            break command_id;
+        }
+    };
+
+    for entry in raw_packet.iter_mut().skip(4) {
+        *entry = read_byte_timeout(serial, timer, byte_timeout.clone()).map_err(
+            |error| match error {
+                Error::Timeout => Error::Truncated(command_id),
+                error => error,
+            },
+        )?;
+    }
+
+    Ok(UartRequest::from_raw_packet(&raw_packet)?)
+}
+
+/// Send a request.
+pub fn write_request<S: Write<u8>>(
+    serial: &mut S,
+    request: &UartRequest,
+) -> Result<(), Error<S::Error>> {
+    write_bytes(serial, &request.to_raw_packet()[..])
+}
+
+/// Receive a reply.
+pub fn read_reply<S: Read<u8>>(serial: &mut S) -> Result<UartReply, Error<S::Error>> {
+    let mut raw_packet = [0x0u8; REPLY_SIZE];
+
+    for entry in raw_packet.iter_mut() {
+        *entry = read_byte(serial)?;
+    }
+
+    Ok(UartReply::from_raw_packet(&raw_packet)?)
+}
+
+/// Send a reply.
+pub fn write_reply<S: Write<u8>>(serial: &mut S, reply: &UartReply) -> Result<(), Error<S::Error>> {
+    write_bytes(serial, &reply.to_raw_packet()[..])
+}
+
+/// Receive the data following a memory write request, returning its checksum.
+pub fn read_data<S: Read<u8>>(serial: &mut S, data: &mut [u8]) -> Result<u32, Error<S::Error>> {
+    read_data_timeout(serial, data, &mut NoTimeout, ())
+}
+
+/// Receive the data following a memory write request, giving up if the gap between two bytes exceeds `byte_timeout`.
+pub fn read_data_timeout<S, T>(
+    serial: &mut S,
+    data: &mut [u8],
+    timer: &mut T,
+    byte_timeout: T::Time,
+) -> Result<u32, Error<S::Error>>
+where
+    S: Read<u8>,
+    T: CountDown,
+    T::Time: Clone,
+{
+    for entry in data.iter_mut() {
+        *entry = read_byte_timeout(serial, timer, byte_timeout.clone())?;
+    }
+
+    Ok(checksum(data))
+}
+
+/// Send the data following a memory read reply.
+pub fn write_data<S: Write<u8>>(serial: &mut S, data: &[u8]) -> Result<(), Error<S::Error>> {
+    write_bytes(serial, data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::VecDeque;
+
+    #[derive(Debug, PartialEq, Eq, Clone, Copy)]
+    struct EndOfStream;
+
+    #[derive(Default)]
+    struct MockSerial {
+        input: VecDeque<u8>,
+        output: Vec<u8>,
+        stalled: bool,
+    }
+
+    impl MockSerial {
+        fn with_input(input: &[u8]) -> Self {
+            MockSerial {
+                input: input.iter().copied().collect(),
+                ..Default::default()
+            }
+        }
+
+        /// Serial that keeps waiting for more data once the input is exhausted.
+        fn stalled_with_input(input: &[u8]) -> Self {
+            MockSerial {
+                stalled: true,
+                ..MockSerial::with_input(input)
+            }
+        }
+    }
+
+    impl Read<u8> for MockSerial {
+        type Error = EndOfStream;
+
+        fn read(&mut self) -> nb::Result<u8, Self::Error> {
+            match self.input.pop_front() {
+                Some(value) => Ok(value),
+                None if self.stalled => Err(nb::Error::WouldBlock),
+                None => Err(nb::Error::Other(EndOfStream)),
+            }
+        }
+    }
+
+    /// Countdown expiring after a given number of polls.
+    struct MockTimer {
+        remaining: u32,
+    }
+
+    impl CountDown for MockTimer {
+        type Time = u32;
+
+        fn start<T: Into<Self::Time>>(&mut self, count: T) {
+            self.remaining = count.into();
+        }
+
+        fn wait(&mut self) -> nb::Result<(), Void> {
+            if self.remaining == 0 {
+                return Ok(());
+            }
+
+            self.remaining -= 1;
+
+            Err(nb::Error::WouldBlock)
+        }
+    }
+
+    impl Write<u8> for MockSerial {
+        type Error = EndOfStream;
+
+        fn write(&mut self, word: u8) -> nb::Result<(), Self::Error> {
+            self.output.push(word);
+            Ok(())
+        }
+
+        fn flush(&mut self) -> nb::Result<(), Self::Error> {
+            Ok(())
+        }
+    }
+
+    const ALL_COMMANDS: [CommandId; 5] = [
+        CommandId::NoOperation,
+        CommandId::Proxy,
+        CommandId::MemoryRead,
+        CommandId::MemoryWrite,
+        CommandId::Boot,
+    ];
+
+    fn all_requests() -> Vec<UartRequest> {
+        vec![
+            UartRequest::Simple {
+                command_id: CommandId::NoOperation,
+            },
+            UartRequest::Proxy {
+                command_id: CommandId::Proxy,
+                request: ProxyRequest {
+                    opcode: ProxyOpcode::Read32 as u64,
+                    args: [0x2_3520_0000, 1, 2, 3, 4, u64::MAX],
+                },
+            },
+            UartRequest::Memory {
+                command_id: CommandId::MemoryRead,
+                address: 0x8_0000_0000,
+                size: 0x4000,
+                data_checksum: 0,
+            },
+            UartRequest::Memory {
+                command_id: CommandId::MemoryWrite,
+                address: 0x8_0400_0000,
+                size: 4,
+                data_checksum: checksum(b"m1n1"),
+            },
+            UartRequest::Boot {
+                command_id: CommandId::Boot,
+                entry: 0x8_0380_0000,
+            },
+        ]
+    }
+
+    #[test]
+    fn checksum_matches_reference() {
+        assert_eq!(checksum(b""), 0x73736542);
+        assert_eq!(checksum(b"m1n1"), 0x946e1585);
+        assert_eq!(
+            checksum(&[0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15]),
+            0x58dc10ca
+        );
+    }
+
+    #[test]
+    fn command_id_round_trip() {
+        for command_id in ALL_COMMANDS.iter() {
+            let raw = u32::from(*command_id);
+
+            assert_eq!(raw & 0x00FFFFFF, 0x00AA55FF);
+            assert_eq!(CommandId::try_from(raw), Ok(*command_id));
+            assert_eq!(CommandId::try_from(u8::from(*command_id)), Ok(*command_id));
+        }
+
+        assert!(CommandId::try_from(0x05AA55FFu32).is_err());
+        assert!(CommandId::try_from(0x00AA55FEu32).is_err());
+        assert!(CommandId::try_from(5u8).is_err());
+    }
+
+    #[test]
+    fn status_round_trip() {
+        for status in [
+            Status::Ok,
+            Status::BadCommand,
+            Status::Invalid,
+            Status::TransferError,
+            Status::ChecksumMismatch,
+        ]
+        .iter()
+        {
+            assert_eq!(Status::try_from(i32::from(*status)), Ok(*status));
+        }
+
+        assert!(Status::try_from(1).is_err());
+        assert!(Status::try_from(-5).is_err());
+    }
+
+    #[test]
+    fn proxy_status_round_trip() {
+        for status in [
+            ProxyStatus::Ok,
+            ProxyStatus::BadCommand,
+            ProxyStatus::Exception,
+            ProxyStatus::Rejected,
+        ]
+        .iter()
+        {
+            assert_eq!(ProxyStatus::try_from(i64::from(*status)), Ok(*status));
+        }
+
+        assert!(ProxyStatus::try_from(1).is_err());
+        assert!(ProxyStatus::try_from(-4).is_err());
+    }
+
+    #[test]
+    fn request_round_trip() {
+        for request in all_requests() {
+            let raw_packet = request.to_raw_packet();
+
+            assert_eq!(raw_packet[..3], REQUEST_MAGIC);
+            assert_eq!(raw_packet[3], u8::from(request.get_command_id()));
+            assert_eq!(UartRequest::from_raw_packet(&raw_packet), Ok(request));
+        }
+    }
+
+    #[test]
+    fn request_layout_matches_m1n1() {
+        let request = UartRequest::Memory {
+            command_id: CommandId::MemoryWrite,
+            address: 0x1122334455667788,
+            size: 0x10,
+            data_checksum: 0xCAFEBABE,
+        };
+
+        let raw_packet = request.to_raw_packet();
+
+        assert_eq!(raw_packet[..4], [0xFF, 0x55, 0xAA, 0x03]);
+        assert_eq!(
+            raw_packet[4..12],
+            [0x88, 0x77, 0x66, 0x55, 0x44, 0x33, 0x22, 0x11]
+        );
+        assert_eq!(raw_packet[12..20], [0x10, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(raw_packet[20..24], [0xBE, 0xBA, 0xFE, 0xCA]);
+        assert_eq!(
+            raw_packet[60..],
+            u32::to_le_bytes(checksum(&raw_packet[..60]))
+        );
+    }
+
+    #[test]
+    fn reply_round_trip() {
+        let replies = [
+            UartReply::no_operation(),
+            UartReply::boot(),
+            UartReply::proxy(ProxyReply {
+                opcode: ProxyOpcode::Call as u64,
+                status: ProxyStatus::Exception,
+                return_value: 0x96000010,
+            }),
+            UartReply::memory(CommandId::MemoryRead, 0x12345678),
+            UartReply::Memory {
+                command_id: CommandId::MemoryWrite,
+                status: Status::ChecksumMismatch,
+                data_checksum: 0x87654321,
+            },
+            UartReply::simple_error(CommandId::Proxy, Status::ChecksumMismatch),
+            UartReply::simple_error(CommandId::Boot, Status::BadCommand),
+        ];
+
+        for reply in replies.iter() {
+            let raw_packet = reply.to_raw_packet();
+
+            assert_eq!(UartReply::from_raw_packet(&raw_packet), Ok(*reply));
+        }
+    }
+
+    #[test]
+    fn reply_layout_matches_m1n1() {
+        let raw_packet = UartReply::proxy(ProxyReply {
+            opcode: 0x104,
+            status: ProxyStatus::BadCommand,
+            return_value: 0xAABBCCDD,
+        })
+        .to_raw_packet();
+
+        assert_eq!(raw_packet[..4], [0xFF, 0x55, 0xAA, 0x01]);
+        assert_eq!(raw_packet[4..8], [0, 0, 0, 0]);
+        assert_eq!(raw_packet[8..16], [0x04, 0x01, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(raw_packet[16..24], [0xFF; 8]);
+        assert_eq!(raw_packet[24..32], [0xDD, 0xCC, 0xBB, 0xAA, 0, 0, 0, 0]);
+        assert_eq!(
+            raw_packet[32..],
+            u32::to_le_bytes(checksum(&raw_packet[..32]))
+        );
+    }
+
+    #[test]
+    fn request_invalid_magic() {
+        let mut raw_packet = UartRequest::Simple {
+            command_id: CommandId::NoOperation,
+        }
+        .to_raw_packet();
+
+        raw_packet[1] = 0x56;
+
+        assert_eq!(
+            UartRequest::from_raw_packet(&raw_packet),
+            Err(DecodeError::InvalidMagic)
+        );
+    }
+
+    #[test]
+    fn request_unknown_command() {
+        let mut raw_packet = UartRequest::Simple {
+            command_id: CommandId::NoOperation,
+        }
+        .to_raw_packet();
+
+        raw_packet[3] = 0x42;
+
+        assert_eq!(
+            UartRequest::from_raw_packet(&raw_packet),
+            Err(DecodeError::UnknownCommand(0x42))
+        );
+    }
+
+    #[test]
+    fn request_corruption_is_detected() {
+        for request in all_requests() {
+            let raw_packet = request.to_raw_packet();
+
+            // Flip every bit after the header one by one.
+            for position in 4..REQUEST_SIZE {
+                for bit in 0..8 {
+                    let mut corrupted = raw_packet;
+
+                    corrupted[position] ^= 1 << bit;
+
+                    match UartRequest::from_raw_packet(&corrupted) {
+                        Err(DecodeError::ChecksumMismatch { command_id, .. }) => {
+                            assert_eq!(command_id, request.get_command_id())
+                        }
+                        result => panic!(
+                            "Corruption at {}:{} not detected: {:?}",
+                            position, bit, result
+                        ),
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn reply_corruption_is_detected() {
+        let raw_packet = UartReply::memory(CommandId::MemoryRead, 0x12345678).to_raw_packet();
+
+        for position in 4..REPLY_SIZE {
+            let mut corrupted = raw_packet;
+
+            corrupted[position] ^= 0x80;
+
+            assert!(matches!(
+                UartReply::from_raw_packet(&corrupted),
+                Err(DecodeError::ChecksumMismatch {
+                    command_id: CommandId::MemoryRead,
+                    ..
+                })
+            ));
+        }
+
+        let mut corrupted = raw_packet;
+        corrupted[0] = 0;
+
+        assert_eq!(
+            UartReply::from_raw_packet(&corrupted),
+            Err(DecodeError::InvalidMagic)
+        );
+    }
+
+    #[test]
+    fn reply_unknown_status() {
+        let mut raw_packet = UartReply::no_operation().to_raw_packet();
+
+        raw_packet[4..8].copy_from_slice(&i32::to_le_bytes(-42));
+
+        let checksum = checksum(&raw_packet[..32]);
+        raw_packet[32..].copy_from_slice(&u32::to_le_bytes(checksum));
+
+        assert_eq!(
+            UartReply::from_raw_packet(&raw_packet),
+            Err(DecodeError::UnknownStatus(-42))
+        );
+    }
+
+    #[test]
+    fn read_request_over_serial() {
+        for request in all_requests() {
+            let mut serial = MockSerial::with_input(&request.to_raw_packet());
+
+            assert_eq!(read_request(&mut serial), Ok(request));
+            assert!(serial.input.is_empty());
+        }
+    }
+
+    #[test]
+    fn read_request_resyncs_on_garbage() {
+        let request = all_requests()[1];
+        let prefixes: [&[u8]; 5] = [
+            &[0x00],
+            &[0xFF],
+            &[0xFF, 0x55],
+            &[0x12, 0xFF, 0x55, 0xFF, 0x00, 0xAA],
+            &[0xFF, 0xFF, 0x55, 0x55, 0xAA],
+        ];
+
+        for prefix in prefixes.iter() {
+            let mut input = prefix.to_vec();
+            input.extend_from_slice(&request.to_raw_packet());
+
+            let mut serial = MockSerial::with_input(&input);
+
+            assert_eq!(read_request(&mut serial), Ok(request));
+            assert!(serial.input.is_empty());
+        }
+    }
+
+    #[test]
+    fn read_request_skips_unknown_command() {
+        let request = all_requests()[0];
+
+        // Both a bogus command id and a magic directly followed by another packet.
+        for prefix in [[0xFF, 0x55, 0xAA, 0x10], [0x00, 0xFF, 0x55, 0xAA]].iter() {
+            let mut input = prefix.to_vec();
+            input.extend_from_slice(&request.to_raw_packet());
+
+            let mut serial = MockSerial::with_input(&input);
+
+            assert_eq!(read_request(&mut serial), Ok(request));
+            assert!(serial.input.is_empty());
+        }
+    }
+
+    #[test]
+    fn read_request_timeout_on_truncated_packet() {
+        let request = all_requests()[1];
+        let raw_packet = request.to_raw_packet();
+
+        let mut serial = MockSerial::stalled_with_input(&raw_packet[..3]);
+        let mut timer = MockTimer { remaining: 0 };
+
+        assert_eq!(
+            read_request_timeout(&mut serial, &mut timer, 10),
+            Err(Error::Timeout)
+        );
+
+        for length in 4..REQUEST_SIZE {
+            let mut serial = MockSerial::stalled_with_input(&raw_packet[..length]);
+
+            assert_eq!(
+                read_request_timeout(&mut serial, &mut timer, 10),
+                Err(Error::Truncated(CommandId::Proxy))
+            );
+        }
+
+        // Timeouts are only triggered by missing data.
+        let mut serial = MockSerial::stalled_with_input(&raw_packet);
+
+        assert_eq!(
+            read_request_timeout(&mut serial, &mut timer, 0),
+            Ok(request)
+        );
+    }
+
+    #[test]
+    fn read_request_truncated() {
+        let request = all_requests()[1];
+        let raw_packet = request.to_raw_packet();
+
+        for length in 0..REQUEST_SIZE {
+            let mut serial = MockSerial::with_input(&raw_packet[..length]);
+
+            assert_eq!(read_request(&mut serial), Err(Error::Serial(EndOfStream)));
+        }
+    }
+
+    #[test]
+    fn read_request_checksum_mismatch() {
+        let mut raw_packet = all_requests()[3].to_raw_packet();
+        raw_packet[63] ^= 0xFF;
+
+        let mut serial = MockSerial::with_input(&raw_packet);
+
+        assert!(matches!(
+            read_request(&mut serial),
+            Err(Error::Decode(DecodeError::ChecksumMismatch {
+                command_id: CommandId::MemoryWrite,
+                ..
+            }))
+        ));
+        assert!(serial.input.is_empty());
+    }
+
+    #[test]
+    fn write_and_read_reply_over_serial() {
+        let reply = UartReply::proxy(ProxyReply {
+            opcode: ProxyOpcode::GetBase as u64,
+            status: ProxyStatus::Ok,
+            return_value: 0x8_0380_0000,
+        });
+
+        let mut serial = MockSerial::default();
+        write_reply(&mut serial, &reply).unwrap();
+
+        assert_eq!(serial.output.len(), REPLY_SIZE);
+
+        let mut serial = MockSerial::with_input(&serial.output);
+
+        assert_eq!(read_reply(&mut serial), Ok(reply));
+    }
+
+    #[test]
+    fn read_reply_truncated() {
+        let raw_packet = UartReply::no_operation().to_raw_packet();
+        let mut serial = MockSerial::with_input(&raw_packet[..REPLY_SIZE - 1]);
+
+        assert_eq!(read_reply(&mut serial), Err(Error::Serial(EndOfStream)));
+    }
+
+    #[test]
+    fn write_request_over_serial() {
+        for request in all_requests() {
+            let mut serial = MockSerial::default();
+            write_request(&mut serial, &request).unwrap();
+
+            assert_eq!(serial.output, request.to_raw_packet().to_vec());
+        }
+    }
+
+    #[test]
+    fn data_transfer_over_serial() {
+        let data = b"Hello I'm m1saka say m1saka";
+
+        let mut serial = MockSerial::default();
+        write_data(&mut serial, data).unwrap();
+
+        let mut serial = MockSerial::with_input(&serial.output);
+        let mut buffer = [0x0u8; 27];
+
+        assert_eq!(read_data(&mut serial, &mut buffer), Ok(checksum(data)));
+        assert_eq!(&buffer, data);
+
+        let mut serial = MockSerial::with_input(&data[..10]);
+
+        assert_eq!(
+            read_data(&mut serial, &mut buffer),
+            Err(Error::Serial(EndOfStream))
+        );
+
+        let mut serial = MockSerial::stalled_with_input(&data[..10]);
+        let mut timer = MockTimer { remaining: 0 };
+
+        assert_eq!(
+            read_data_timeout(&mut serial, &mut buffer, &mut timer, 10),
+            Err(Error::Timeout)
+        );
+    }
+
+    #[test]
+    fn proxy_opcode_round_trip() {
+        for raw_opcode in 0..0x1000u64 {
+            if let Ok(opcode) = ProxyOpcode::try_from(raw_opcode) {
+                assert_eq!(opcode as u64, raw_opcode);
+            }
+        }
+
+        assert!(ProxyRequest {
+            opcode: ProxyOpcode::Exit as u64,
+            args: [0; 6]
+        }
+        .is_exit());
+    }
+}
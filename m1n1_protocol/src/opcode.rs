@@ -0,0 +1,145 @@
+//! m1n1 proxy opcodes
+
+use core::convert::TryFrom;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[repr(u64)]
+pub enum ProxyOpcode {
+    // System functions
+    Nop = 0x000,
+    Exit = 0x001,
+    Call = 0x002,
+    GetBootArgs = 0x003,
+    GetBase = 0x004,
+    SetBaud = 0x005,
+    Udelay = 0x006,
+    SetExcGuard = 0x007,
+    GetExcCount = 0x008,
+
+    // Generic register functions
+    Write64 = 0x100,
+    Write32 = 0x101,
+    Write16 = 0x102,
+    Write8 = 0x103,
+    Read64 = 0x104,
+    Read32 = 0x105,
+    Read16 = 0x106,
+    Read8 = 0x107,
+    Set64 = 0x108,
+    Set32 = 0x109,
+    Set16 = 0x10a,
+    Set8 = 0x10b,
+    Clear64 = 0x10c,
+    Clear32 = 0x10d,
+    Clear16 = 0x10e,
+    Clear8 = 0x10f,
+    Mask64 = 0x110,
+    Mask32 = 0x111,
+    Mask16 = 0x112,
+    Mask8 = 0x113,
+    WriteRead64 = 0x114,
+    WriteRead32 = 0x115,
+    WriteRead16 = 0x116,
+    WriteRead8 = 0x117,
+
+    // Memory block transfer functions
+    Memcpy64 = 0x200,
+    Memcpy32 = 0x201,
+    Memcpy16 = 0x202,
+    Memcpy8 = 0x203,
+    Memset64 = 0x204,
+    Memset32 = 0x205,
+    Memset16 = 0x206,
+    Memset8 = 0x207,
+
+    // Cache and memory ops
+    IcIalluis = 0x300,
+    IcIallu = 0x301,
+    IcIvau = 0x302,
+    DcIvac = 0x303,
+    DcIsw = 0x304,
+    DcCsw = 0x305,
+    DcCisw = 0x306,
+    DcZva = 0x307,
+    DcCvac = 0x308,
+    DcCvau = 0x309,
+    DcCivac = 0x30a,
+
+    // Compression
+    XzDec = 0x400,
+    GzDec = 0x401,
+
+    // Linux boot
+    KbootBoot = 0x700,
+    KbootSetChosen = 0x701,
+    KbootSetInitrd = 0x702,
+    KbootPrepareDt = 0x703,
+}
+
+impl TryFrom<u64> for ProxyOpcode {
+    type Error = &'static str;
+
+    fn try_from(value: u64) -> Result<Self, Self::Error> {
+        match value {
+            0x000 => Ok(ProxyOpcode::Nop),
+            0x001 => Ok(ProxyOpcode::Exit),
+            0x002 => Ok(ProxyOpcode::Call),
+            0x003 => Ok(ProxyOpcode::GetBootArgs),
+            0x004 => Ok(ProxyOpcode::GetBase),
+            0x005 => Ok(ProxyOpcode::SetBaud),
+            0x006 => Ok(ProxyOpcode::Udelay),
+            0x007 => Ok(ProxyOpcode::SetExcGuard),
+            0x008 => Ok(ProxyOpcode::GetExcCount),
+            0x100 => Ok(ProxyOpcode::Write64),
+            0x101 => Ok(ProxyOpcode::Write32),
+            0x102 => Ok(ProxyOpcode::Write16),
+            0x103 => Ok(ProxyOpcode::Write8),
+            0x104 => Ok(ProxyOpcode::Read64),
+            0x105 => Ok(ProxyOpcode::Read32),
+            0x106 => Ok(ProxyOpcode::Read16),
+            0x107 => Ok(ProxyOpcode::Read8),
+            0x108 => Ok(ProxyOpcode::Set64),
+            0x109 => Ok(ProxyOpcode::Set32),
+            0x10a => Ok(ProxyOpcode::Set16),
+            0x10b => Ok(ProxyOpcode::Set8),
+            0x10c => Ok(ProxyOpcode::Clear64),
+            0x10d => Ok(ProxyOpcode::Clear32),
+            0x10e => Ok(ProxyOpcode::Clear16),
+            0x10f => Ok(ProxyOpcode::Clear8),
+            0x110 => Ok(ProxyOpcode::Mask64),
+            0x111 => Ok(ProxyOpcode::Mask32),
+            0x112 => Ok(ProxyOpcode::Mask16),
+            0x113 => Ok(ProxyOpcode::Mask8),
+            0x114 => Ok(ProxyOpcode::WriteRead64),
+            0x115 => Ok(ProxyOpcode::WriteRead32),
+            0x116 => Ok(ProxyOpcode::WriteRead16),
+            0x117 => Ok(ProxyOpcode::WriteRead8),
+            0x200 => Ok(ProxyOpcode::Memcpy64),
+            0x201 => Ok(ProxyOpcode::Memcpy32),
+            0x202 => Ok(ProxyOpcode::Memcpy16),
+            0x203 => Ok(ProxyOpcode::Memcpy8),
+            0x204 => Ok(ProxyOpcode::Memset64),
+            0x205 => Ok(ProxyOpcode::Memset32),
+            0x206 => Ok(ProxyOpcode::Memset16),
+            0x207 => Ok(ProxyOpcode::Memset8),
+            0x300 => Ok(ProxyOpcode::IcIalluis),
+            0x301 => Ok(ProxyOpcode::IcIallu),
+            0x302 => Ok(ProxyOpcode::IcIvau),
+            0x303 => Ok(ProxyOpcode::DcIvac),
+            0x304 => Ok(ProxyOpcode::DcIsw),
+            0x305 => Ok(ProxyOpcode::DcCsw),
+            0x306 => Ok(ProxyOpcode::DcCisw),
+            0x307 => Ok(ProxyOpcode::DcZva),
+            0x308 => Ok(ProxyOpcode::DcCvac),
+            0x309 => Ok(ProxyOpcode::DcCvau),
+            0x30a => Ok(ProxyOpcode::DcCivac),
+            0x400 => Ok(ProxyOpcode::XzDec),
+            0x401 => Ok(ProxyOpcode::GzDec),
+            0x700 => Ok(ProxyOpcode::KbootBoot),
+            0x701 => Ok(ProxyOpcode::KbootSetChosen),
+            0x702 => Ok(ProxyOpcode::KbootSetInitrd),
+            0x703 => Ok(ProxyOpcode::KbootPrepareDt),
+            _ => Err("Unknown proxy opcode"),
+        }
+    }
+}
@@ -0,0 +1,969 @@
+//! Target side of the proxy, generic over the transport
+
+use core::convert::TryFrom;
+
+use embedded_hal::serial::{Read, Write};
+use embedded_hal::timer::CountDown;
+use void::Void;
+
+use log::{error, info, warn};
+
+use crate::transport::ProxyTransport;
+use crate::{
+    CommandId, DecodeError, Error, ProxyOpcode, ProxyReply, ProxyRequest, ProxyStatus, Status,
+    UartReply, UartRequest, REQUEST_SIZE,
+};
+
+/// Maximum time to wait for the next byte once a packet started, in microseconds.
+pub const BYTE_TIMEOUT: u64 = 500_000;
+
+/// Time the host has to confirm a baud rate change, in microseconds.
+pub const BAUD_CONFIRM_TIMEOUT: u64 = 1_000_000;
+
+/// Target specific part of the proxy.
+pub trait ProxyHandler {
+    /// Get the memory accessed by a memory read or write request, None if it cannot be accessed.
+    fn get_memory(&mut self, address: u64, size: u64) -> Option<&mut [u8]>;
+
+    fn handle_proxy(&mut self, request: &ProxyRequest) -> ProxyReply;
+
+    /// Called once the reply to a proxy request was sent.
+    ///
+    /// Requests handing control to something else (like m1n1's P_KBOOT_BOOT) only prepare it
+    /// when handled, so that the host gets its reply, and jump from here.
+    fn after_proxy_reply(&mut self) {}
+
+    /// Hand control to the image at `entry`, only returning if it cannot be booted.
+    ///
+    /// The request was already acknowledged when this is called.
+    fn boot(&mut self, entry: u64);
+}
+
+/// Exposes a transport through the serial traits used by the codec.
+///
+/// Timeouts are handled by the transport, a read that timed out is reported as `WouldBlock`.
+struct TransportSerial<'a, T> {
+    transport: &'a mut T,
+    timeout: u64,
+}
+
+impl<T: ProxyTransport> Read<u8> for TransportSerial<'_, T> {
+    type Error = T::Error;
+
+    fn read(&mut self) -> nb::Result<u8, Self::Error> {
+        match self.transport.read_byte(self.timeout) {
+            Ok(Some(value)) => Ok(value),
+            Ok(None) => Err(nb::Error::WouldBlock),
+            Err(error) => Err(nb::Error::Other(error)),
+        }
+    }
+}
+
+impl<T: ProxyTransport> Write<u8> for TransportSerial<'_, T> {
+    type Error = T::Error;
+
+    fn write(&mut self, word: u8) -> nb::Result<(), Self::Error> {
+        self.transport.write(&[word]).map_err(nb::Error::Other)
+    }
+
+    fn flush(&mut self) -> nb::Result<(), Self::Error> {
+        self.transport.flush().map_err(nb::Error::Other)
+    }
+}
+
+/// Countdown that is always expired, as the transport already waited for the timeout.
+struct Expired;
+
+impl CountDown for Expired {
+    type Time = ();
+
+    fn start<T: Into<Self::Time>>(&mut self, _count: T) {}
+
+    fn wait(&mut self) -> nb::Result<(), Void> {
+        Ok(())
+    }
+}
+
+type Result<T, E> = core::result::Result<T, Error<E>>;
+
+fn write_reply<T: ProxyTransport>(
+    serial: &mut TransportSerial<'_, T>,
+    reply: &UartReply,
+) -> Result<(), T::Error> {
+    crate::write_reply(serial, reply)?;
+
+    serial.transport.flush().map_err(Error::Serial)
+}
+
+fn read_packet<T: ProxyTransport>(
+    serial: &mut TransportSerial<'_, T>,
+) -> Result<Option<UartRequest>, T::Error> {
+    match crate::read_request_timeout(serial, &mut Expired, ()) {
+        Ok(request) => Ok(Some(request)),
+        Err(Error::Serial(error)) => Err(Error::Serial(error)),
+        Err(Error::Timeout) => {
+            warn!("Timeout while reading packet command id");
+
+            Ok(None)
+        }
+        Err(Error::Truncated(command_id)) => {
+            warn!("Truncated {:?} packet", command_id);
+
+            write_reply(
+                serial,
+                &UartReply::simple_error(command_id, Status::TransferError),
+            )?;
+
+            Ok(None)
+        }
+        Err(Error::Decode(DecodeError::ChecksumMismatch {
+            command_id,
+            expected,
+            computed,
+        })) => {
+            error!("Bad checksum {:x} vs {:x}", expected, computed);
+
+            write_reply(
+                serial,
+                &UartReply::simple_error(command_id, Status::ChecksumMismatch),
+            )?;
+
+            Ok(None)
+        }
+        Err(Error::Decode(error)) => {
+            error!("Cannot read packet: {:?}", error);
+
+            Ok(None)
+        }
+    }
+}
+
+fn handle_memory_read<T: ProxyTransport, H: ProxyHandler>(
+    serial: &mut TransportSerial<'_, T>,
+    handler: &mut H,
+    address: u64,
+    size: u64,
+) -> Result<Option<UartReply>, T::Error> {
+    let data = match handler.get_memory(address, size) {
+        Some(data) => data,
+        None => {
+            error!(
+                "Invalid memory read at 0x{:x} (0x{:x} bytes)",
+                address, size
+            );
+
+            return Ok(Some(UartReply::simple_error(
+                CommandId::MemoryRead,
+                Status::Invalid,
+            )));
+        }
+    };
+
+    let reply = UartReply::memory(CommandId::MemoryRead, crate::checksum(data));
+
+    crate::write_reply(serial, &reply)?;
+    crate::write_data(serial, data)?;
+    serial.transport.flush().map_err(Error::Serial)?;
+
+    Ok(None)
+}
+
+/// Consume data the host sends, stopping early if it stops arriving.
+fn skip_data<T: ProxyTransport>(
+    serial: &mut TransportSerial<'_, T>,
+    size: u64,
+) -> Result<(), T::Error> {
+    let mut buffer = [0x0u8; 0x40];
+    let mut remaining = size;
+
+    while remaining > 0 {
+        let chunk_size = core::cmp::min(remaining, buffer.len() as u64);
+
+        match crate::read_data_timeout(serial, &mut buffer[..chunk_size as usize], &mut Expired, ())
+        {
+            Ok(_) => remaining -= chunk_size,
+            Err(Error::Serial(error)) => return Err(Error::Serial(error)),
+            Err(_) => break,
+        }
+    }
+
+    Ok(())
+}
+
+fn handle_memory_write<T: ProxyTransport, H: ProxyHandler>(
+    serial: &mut TransportSerial<'_, T>,
+    handler: &mut H,
+    address: u64,
+    size: u64,
+    expected_checksum: u32,
+) -> Result<UartReply, T::Error> {
+    let data = match handler.get_memory(address, size) {
+        Some(data) => data,
+        None => {
+            error!(
+                "Invalid memory write at 0x{:x} (0x{:x} bytes)",
+                address, size
+            );
+
+            skip_data(serial, size)?;
+
+            return Ok(UartReply::simple_error(
+                CommandId::MemoryWrite,
+                Status::Invalid,
+            ));
+        }
+    };
+
+    let data_checksum = match crate::read_data_timeout(serial, data, &mut Expired, ()) {
+        Ok(data_checksum) => data_checksum,
+        Err(Error::Serial(error)) => return Err(Error::Serial(error)),
+        Err(_) => {
+            error!("Transfer error while writing to 0x{:x}", address);
+
+            return Ok(UartReply::simple_error(
+                CommandId::MemoryWrite,
+                Status::TransferError,
+            ));
+        }
+    };
+
+    if data_checksum != expected_checksum {
+        error!(
+            "Bad data checksum {:x} vs {:x}",
+            expected_checksum, data_checksum
+        );
+
+        return Ok(UartReply::Memory {
+            command_id: CommandId::MemoryWrite,
+            status: Status::ChecksumMismatch,
+            data_checksum,
+        });
+    }
+
+    Ok(UartReply::memory(CommandId::MemoryWrite, data_checksum))
+}
+
+/// Wait for the host to send a nop, skipping a bounded amount of garbage.
+fn wait_baudrate_confirmation<T: ProxyTransport>(transport: &mut T) -> Result<bool, T::Error> {
+    let expected = UartRequest::Simple {
+        command_id: CommandId::NoOperation,
+    }
+    .to_raw_packet();
+    let mut matched = 0;
+
+    for _ in 0..REQUEST_SIZE * 4 {
+        let value = match transport
+            .read_byte(BAUD_CONFIRM_TIMEOUT)
+            .map_err(Error::Serial)?
+        {
+            Some(value) => value,
+            None => return Ok(false),
+        };
+
+        if value == expected[matched] {
+            matched += 1;
+        } else if value == expected[0] {
+            matched = 1;
+        } else {
+            matched = 0;
+        }
+
+        if matched == expected.len() {
+            return Ok(true);
+        }
+    }
+
+    Ok(false)
+}
+
+/// Handle P_SET_BAUD.
+///
+/// The reply is sent at the current rate, the host then confirms the new rate with a nop.
+/// Without confirmation the previous rate is restored.
+fn handle_set_baudrate<T: ProxyTransport>(
+    serial: &mut TransportSerial<'_, T>,
+    request: &ProxyRequest,
+) -> Result<Option<UartReply>, T::Error> {
+    let mut reply = ProxyReply {
+        opcode: request.opcode,
+        status: ProxyStatus::Rejected,
+        return_value: 0,
+    };
+
+    let baud_rate = u32::try_from(request.args[0]).ok();
+    let previous_baud_rate = serial.transport.get_baudrate();
+
+    let (baud_rate, previous_baud_rate) = match (baud_rate, previous_baud_rate) {
+        (Some(baud_rate), Some(previous_baud_rate)) => (baud_rate, previous_baud_rate),
+        _ => return Ok(Some(UartReply::proxy(reply))),
+    };
+
+    reply.status = ProxyStatus::Ok;
+    reply.return_value = u64::from(previous_baud_rate);
+
+    write_reply(serial, &UartReply::proxy(reply))?;
+
+    let achieved_baud_rate = match serial.transport.set_baudrate(baud_rate) {
+        Some(achieved_baud_rate) => achieved_baud_rate,
+        None => {
+            error!("Cannot switch to {} baud", baud_rate);
+
+            return Ok(None);
+        }
+    };
+
+    if wait_baudrate_confirmation(serial.transport)? {
+        write_reply(serial, &UartReply::no_operation())?;
+
+        info!("Switched to {} baud", achieved_baud_rate);
+    } else {
+        serial.transport.set_baudrate(previous_baud_rate);
+
+        warn!(
+            "No confirmation at {} baud, back to {} baud",
+            achieved_baud_rate, previous_baud_rate
+        );
+    }
+
+    Ok(None)
+}
+
+/// Handle a boot request, acknowledging it before handing control to the image.
+fn handle_boot<T: ProxyTransport, H: ProxyHandler>(
+    serial: &mut TransportSerial<'_, T>,
+    handler: &mut H,
+    entry: u64,
+) -> Result<Option<UartReply>, T::Error> {
+    info!("Booting 0x{:x}", entry);
+
+    write_reply(serial, &UartReply::boot())?;
+
+    handler.boot(entry);
+
+    error!("Cannot boot 0x{:x}", entry);
+
+    Ok(Some(UartReply::simple_error(
+        CommandId::Boot,
+        Status::Invalid,
+    )))
+}
+
+/// Handle a request, returning the reply to send back or None if the reply was already sent.
+fn handle_packet<T: ProxyTransport, H: ProxyHandler>(
+    serial: &mut TransportSerial<'_, T>,
+    handler: &mut H,
+    packet: UartRequest,
+) -> Result<Option<UartReply>, T::Error> {
+    let reply = match packet {
+        UartRequest::Simple {
+            command_id: CommandId::NoOperation,
+        } => UartReply::no_operation(),
+        UartRequest::Proxy { request, .. } if request.opcode == ProxyOpcode::SetBaud as u64 => {
+            return handle_set_baudrate(serial, &request)
+        }
+        UartRequest::Proxy { request, .. } => UartReply::proxy(handler.handle_proxy(&request)),
+        UartRequest::Memory {
+            command_id: CommandId::MemoryRead,
+            address,
+            size,
+            ..
+        } => return handle_memory_read(serial, handler, address, size),
+        UartRequest::Memory {
+            command_id: CommandId::MemoryWrite,
+            address,
+            size,
+            data_checksum,
+        } => handle_memory_write(serial, handler, address, size, data_checksum)?,
+        UartRequest::Boot { entry, .. } => return handle_boot(serial, handler, entry),
+        _ => {
+            error!("Unhandled command: {:?}", packet);
+
+            UartReply::simple_error_from_request(packet, Status::BadCommand)
+        }
+    };
+
+    Ok(Some(reply))
+}
+
+/// Serve the proxy over a transport until the host requests an exit.
+///
+/// Errors are only returned when the transport itself fails.
+pub fn run_proxy<T: ProxyTransport, H: ProxyHandler>(
+    transport: &mut T,
+    handler: &mut H,
+) -> Result<(), T::Error> {
+    let mut serial = TransportSerial {
+        transport,
+        timeout: BYTE_TIMEOUT,
+    };
+
+    write_reply(&mut serial, &UartReply::boot())?;
+
+    loop {
+        let packet = match read_packet(&mut serial)? {
+            Some(packet) => packet,
+            None => continue,
+        };
+
+        let is_proxy = matches!(&packet, UartRequest::Proxy { .. });
+        let should_exit =
+            matches!(&packet, UartRequest::Proxy { request, .. } if request.is_exit());
+
+        if let Some(reply) = handle_packet(&mut serial, handler, packet)? {
+            write_reply(&mut serial, &reply)?;
+        }
+
+        if is_proxy {
+            handler.after_proxy_reply();
+        }
+
+        if should_exit {
+            return Ok(());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use crate::transport::{Loopback, LoopbackError, Mailbox, MailboxTransport};
+    use crate::REPLY_SIZE;
+
+    const BASE: u64 = 0x8_0380_0000;
+
+    struct TestHandler {
+        memory: Vec<u8>,
+    }
+
+    impl ProxyHandler for TestHandler {
+        fn get_memory(&mut self, address: u64, size: u64) -> Option<&mut [u8]> {
+            let start = address.checked_sub(BASE)? as usize;
+
+            self.memory
+                .get_mut(start..start.checked_add(size as usize)?)
+        }
+
+        fn handle_proxy(&mut self, request: &ProxyRequest) -> ProxyReply {
+            let (status, return_value) = match ProxyOpcode::try_from(request.opcode) {
+                Ok(ProxyOpcode::Nop) | Ok(ProxyOpcode::Exit) => (ProxyStatus::Ok, 0),
+                Ok(ProxyOpcode::GetBase) => (ProxyStatus::Ok, BASE),
+                _ => (ProxyStatus::BadCommand, 0),
+            };
+
+            ProxyReply {
+                opcode: request.opcode,
+                status,
+                return_value,
+            }
+        }
+
+        fn boot(&mut self, _entry: u64) {}
+    }
+
+    fn proxy_request(opcode: ProxyOpcode) -> UartRequest {
+        UartRequest::Proxy {
+            command_id: CommandId::Proxy,
+            request: ProxyRequest {
+                opcode: opcode as u64,
+                args: [0; 6],
+            },
+        }
+    }
+
+    fn memory_request(command_id: CommandId, address: u64, data: &[u8]) -> UartRequest {
+        UartRequest::Memory {
+            command_id,
+            address,
+            size: data.len() as u64,
+            data_checksum: crate::checksum(data),
+        }
+    }
+
+    fn push_request(input: &mut Vec<u8>, request: UartRequest) {
+        input.extend_from_slice(&request.to_raw_packet());
+    }
+
+    fn take_reply(output: &mut &[u8]) -> UartReply {
+        let mut raw_packet = [0x0u8; REPLY_SIZE];
+
+        raw_packet.copy_from_slice(&output[..REPLY_SIZE]);
+        *output = &output[REPLY_SIZE..];
+
+        UartReply::from_raw_packet(&raw_packet).unwrap()
+    }
+
+    /// Requests covering the whole command set, ending with an exit.
+    fn command_set_input() -> Vec<u8> {
+        let mut input = Vec::new();
+
+        push_request(
+            &mut input,
+            UartRequest::Simple {
+                command_id: CommandId::NoOperation,
+            },
+        );
+
+        push_request(
+            &mut input,
+            memory_request(CommandId::MemoryWrite, BASE + 0x10, b"m1n1"),
+        );
+        input.extend_from_slice(b"m1n1");
+
+        push_request(
+            &mut input,
+            memory_request(CommandId::MemoryRead, BASE + 0x10, b"m1n1"),
+        );
+
+        // Out of range accesses, the write data must be skipped.
+        push_request(
+            &mut input,
+            memory_request(CommandId::MemoryRead, BASE - 1, b"m1"),
+        );
+        push_request(
+            &mut input,
+            memory_request(CommandId::MemoryWrite, BASE + 0xFFFF, b"m1n1"),
+        );
+        input.extend_from_slice(b"m1n1");
+
+        // Corrupted data checksum.
+        push_request(
+            &mut input,
+            UartRequest::Memory {
+                command_id: CommandId::MemoryWrite,
+                address: BASE,
+                size: 2,
+                data_checksum: 0,
+            },
+        );
+        input.extend_from_slice(b"m1");
+
+        push_request(&mut input, proxy_request(ProxyOpcode::GetBase));
+        push_request(&mut input, proxy_request(ProxyOpcode::Call));
+        // Images cannot be booted by the test handler.
+        push_request(
+            &mut input,
+            UartRequest::Boot {
+                command_id: CommandId::Boot,
+                entry: BASE,
+            },
+        );
+
+        // Corrupted packet checksum.
+        let mut raw_packet = proxy_request(ProxyOpcode::Nop).to_raw_packet();
+        raw_packet[60] ^= 0xFF;
+        input.extend_from_slice(&raw_packet);
+
+        push_request(&mut input, proxy_request(ProxyOpcode::Exit));
+
+        input
+    }
+
+    fn check_command_set_output(mut output: &[u8]) {
+        let output = &mut output;
+
+        assert_eq!(take_reply(output), UartReply::boot());
+        assert_eq!(take_reply(output), UartReply::no_operation());
+        assert_eq!(
+            take_reply(output),
+            UartReply::memory(CommandId::MemoryWrite, crate::checksum(b"m1n1"))
+        );
+        assert_eq!(
+            take_reply(output),
+            UartReply::memory(CommandId::MemoryRead, crate::checksum(b"m1n1"))
+        );
+        assert_eq!(&output[..4], b"m1n1");
+        *output = &output[4..];
+
+        assert_eq!(
+            take_reply(output),
+            UartReply::Memory {
+                command_id: CommandId::MemoryRead,
+                status: Status::Invalid,
+                data_checksum: 0,
+            }
+        );
+        assert_eq!(
+            take_reply(output),
+            UartReply::Memory {
+                command_id: CommandId::MemoryWrite,
+                status: Status::Invalid,
+                data_checksum: 0,
+            }
+        );
+        assert_eq!(
+            take_reply(output),
+            UartReply::Memory {
+                command_id: CommandId::MemoryWrite,
+                status: Status::ChecksumMismatch,
+                data_checksum: crate::checksum(b"m1"),
+            }
+        );
+        assert_eq!(
+            take_reply(output),
+            UartReply::proxy(ProxyReply {
+                opcode: ProxyOpcode::GetBase as u64,
+                status: ProxyStatus::Ok,
+                return_value: BASE,
+            })
+        );
+        assert_eq!(
+            take_reply(output),
+            UartReply::proxy(ProxyReply {
+                opcode: ProxyOpcode::Call as u64,
+                status: ProxyStatus::BadCommand,
+                return_value: 0,
+            })
+        );
+        assert_eq!(take_reply(output), UartReply::boot());
+        assert_eq!(
+            take_reply(output),
+            UartReply::simple_error(CommandId::Boot, Status::Invalid)
+        );
+        assert_eq!(
+            take_reply(output),
+            UartReply::simple_error(CommandId::Proxy, Status::ChecksumMismatch)
+        );
+        assert_eq!(
+            take_reply(output),
+            UartReply::proxy(ProxyReply {
+                opcode: ProxyOpcode::Exit as u64,
+                status: ProxyStatus::Ok,
+                return_value: 0,
+            })
+        );
+        assert!(output.is_empty());
+    }
+
+    #[test]
+    fn command_set_over_loopback() {
+        let input = command_set_input();
+        let mut output = vec![0x0u8; 0x1000];
+        let mut loopback = Loopback::new(&input, &mut output);
+        let mut handler = TestHandler {
+            memory: vec![0; 0x1000],
+        };
+
+        assert_eq!(run_proxy(&mut loopback, &mut handler), Ok(()));
+        assert_eq!(&handler.memory[0x10..0x14], b"m1n1");
+        assert_eq!(&handler.memory[..2], b"m1");
+
+        check_command_set_output(loopback.get_output());
+    }
+
+    #[test]
+    fn command_set_over_mailbox() {
+        let mailbox = Mailbox::new();
+
+        for value in command_set_input() {
+            assert!(mailbox.request.push(value));
+        }
+
+        let mut transport = MailboxTransport::new(&mailbox, AlwaysExpired);
+        let mut handler = TestHandler {
+            memory: vec![0; 0x1000],
+        };
+
+        assert_eq!(run_proxy(&mut transport, &mut handler), Ok(()));
+
+        let mut output = Vec::new();
+
+        while let Some(value) = mailbox.reply.pop() {
+            output.push(value);
+        }
+
+        check_command_set_output(&output);
+    }
+
+    #[test]
+    fn stops_on_transport_error() {
+        let mut input = Vec::new();
+        push_request(&mut input, proxy_request(ProxyOpcode::Nop));
+
+        let mut output = vec![0x0u8; 0x100];
+        let mut loopback = Loopback::new(&input, &mut output);
+        let mut handler = TestHandler { memory: Vec::new() };
+
+        assert_eq!(
+            run_proxy(&mut loopback, &mut handler),
+            Err(Error::Serial(LoopbackError::EndOfInput))
+        );
+        assert_eq!(loopback.get_output().len(), REPLY_SIZE * 2);
+    }
+
+    /// Transport sharing what was written with the handler.
+    struct SharedLoopback {
+        input: Vec<u8>,
+        position: usize,
+        output: Rc<RefCell<Vec<u8>>>,
+    }
+
+    impl ProxyTransport for SharedLoopback {
+        type Error = LoopbackError;
+
+        fn read_byte(&mut self, _timeout: u64) -> core::result::Result<Option<u8>, Self::Error> {
+            let value = *self
+                .input
+                .get(self.position)
+                .ok_or(LoopbackError::EndOfInput)?;
+
+            self.position += 1;
+
+            Ok(Some(value))
+        }
+
+        fn write(&mut self, data: &[u8]) -> core::result::Result<(), Self::Error> {
+            self.output.borrow_mut().extend_from_slice(data);
+
+            Ok(())
+        }
+
+        fn flush(&mut self) -> core::result::Result<(), Self::Error> {
+            Ok(())
+        }
+    }
+
+    /// Handler recording how much was sent each time a proxy request is done.
+    struct AfterReplyHandler {
+        output: Rc<RefCell<Vec<u8>>>,
+        sent: Vec<usize>,
+    }
+
+    impl ProxyHandler for AfterReplyHandler {
+        fn get_memory(&mut self, _address: u64, _size: u64) -> Option<&mut [u8]> {
+            None
+        }
+
+        fn handle_proxy(&mut self, request: &ProxyRequest) -> ProxyReply {
+            ProxyReply {
+                opcode: request.opcode,
+                status: ProxyStatus::Ok,
+                return_value: 0,
+            }
+        }
+
+        fn after_proxy_reply(&mut self) {
+            self.sent.push(self.output.borrow().len());
+        }
+
+        fn boot(&mut self, _entry: u64) {}
+    }
+
+    #[test]
+    fn after_proxy_reply() {
+        let mut input = Vec::new();
+        push_request(&mut input, proxy_request(ProxyOpcode::KbootBoot));
+        push_request(
+            &mut input,
+            UartRequest::Simple {
+                command_id: CommandId::NoOperation,
+            },
+        );
+        push_request(&mut input, proxy_request(ProxyOpcode::Exit));
+
+        let output = Rc::new(RefCell::new(Vec::new()));
+        let mut transport = SharedLoopback {
+            input,
+            position: 0,
+            output: output.clone(),
+        };
+        let mut handler = AfterReplyHandler {
+            output: output.clone(),
+            sent: Vec::new(),
+        };
+
+        assert_eq!(run_proxy(&mut transport, &mut handler), Ok(()));
+
+        // Only after proxy requests, once their reply is out.
+        assert_eq!(handler.sent, [REPLY_SIZE * 2, REPLY_SIZE * 4]);
+        assert_eq!(output.borrow().len(), REPLY_SIZE * 4);
+    }
+
+    /// Loopback keeping track of baud rate changes.
+    struct BaudRateLoopback<'a> {
+        loopback: Loopback<'a>,
+        baud_rates: Vec<u32>,
+    }
+
+    impl ProxyTransport for BaudRateLoopback<'_> {
+        type Error = LoopbackError;
+
+        fn read_byte(&mut self, timeout: u64) -> core::result::Result<Option<u8>, Self::Error> {
+            // Running out of input right after a switch behaves like a host that never confirms.
+            match self.loopback.read_byte(timeout) {
+                Err(LoopbackError::EndOfInput) if self.baud_rates.len() & 1 == 0 => Ok(None),
+                result => result,
+            }
+        }
+
+        fn write(&mut self, data: &[u8]) -> core::result::Result<(), Self::Error> {
+            self.loopback.write(data)
+        }
+
+        fn flush(&mut self) -> core::result::Result<(), Self::Error> {
+            self.loopback.flush()
+        }
+
+        fn get_baudrate(&self) -> Option<u32> {
+            self.baud_rates.last().copied()
+        }
+
+        fn set_baudrate(&mut self, baud_rate: u32) -> Option<u32> {
+            if baud_rate > 3_000_000 {
+                return None;
+            }
+
+            self.baud_rates.push(baud_rate);
+
+            Some(baud_rate)
+        }
+    }
+
+    fn set_baudrate_request(baud_rate: u64) -> UartRequest {
+        UartRequest::Proxy {
+            command_id: CommandId::Proxy,
+            request: ProxyRequest {
+                opcode: ProxyOpcode::SetBaud as u64,
+                args: [baud_rate, 0, 0, 0, 0, 0],
+            },
+        }
+    }
+
+    fn set_baudrate_reply(status: ProxyStatus, return_value: u64) -> UartReply {
+        UartReply::proxy(ProxyReply {
+            opcode: ProxyOpcode::SetBaud as u64,
+            status,
+            return_value,
+        })
+    }
+
+    fn run_baudrate_session(input: &[u8]) -> (Vec<u8>, Vec<u32>) {
+        let mut output = vec![0x0u8; 0x1000];
+        let mut transport = BaudRateLoopback {
+            loopback: Loopback::new(input, &mut output),
+            baud_rates: vec![115_200],
+        };
+        let mut handler = TestHandler { memory: Vec::new() };
+
+        let result = run_proxy(&mut transport, &mut handler);
+        let baud_rates = transport.baud_rates;
+
+        assert_eq!(result, Ok(()));
+
+        (transport.loopback.get_output().to_vec(), baud_rates)
+    }
+
+    #[test]
+    fn set_baudrate_confirmed() {
+        let mut input = Vec::new();
+        push_request(&mut input, set_baudrate_request(1_500_000));
+        // Garbage sent while the host switches.
+        input.extend_from_slice(&[0x00, 0xFF, 0x55, 0x13]);
+        push_request(
+            &mut input,
+            UartRequest::Simple {
+                command_id: CommandId::NoOperation,
+            },
+        );
+        push_request(&mut input, proxy_request(ProxyOpcode::Exit));
+
+        let (output, baud_rates) = run_baudrate_session(&input);
+        let output = &mut &output[..];
+
+        assert_eq!(baud_rates, [115_200, 1_500_000]);
+        assert_eq!(take_reply(output), UartReply::boot());
+        assert_eq!(
+            take_reply(output),
+            set_baudrate_reply(ProxyStatus::Ok, 115_200)
+        );
+        assert_eq!(take_reply(output), UartReply::no_operation());
+        assert_eq!(take_reply(output).get_command_id(), CommandId::Proxy);
+        assert!(output.is_empty());
+    }
+
+    #[test]
+    fn set_baudrate_falls_back_without_confirmation() {
+        let mut input = Vec::new();
+        push_request(&mut input, set_baudrate_request(1_500_000));
+
+        let mut output = vec![0x0u8; 0x1000];
+        let mut transport = BaudRateLoopback {
+            loopback: Loopback::new(&input, &mut output),
+            baud_rates: vec![115_200],
+        };
+
+        let mut handler = TestHandler { memory: Vec::new() };
+
+        // The input ends right after the switch, then again once back to the previous rate.
+        assert_eq!(
+            run_proxy(&mut transport, &mut handler),
+            Err(Error::Serial(LoopbackError::EndOfInput))
+        );
+        assert_eq!(transport.baud_rates, [115_200, 1_500_000, 115_200]);
+    }
+
+    #[test]
+    fn set_baudrate_falls_back_on_garbage() {
+        let mut input = Vec::new();
+        push_request(&mut input, set_baudrate_request(1_500_000));
+        input.extend_from_slice(&[0x42; REQUEST_SIZE * 4]);
+        push_request(&mut input, proxy_request(ProxyOpcode::Exit));
+
+        let (_, baud_rates) = run_baudrate_session(&input);
+
+        assert_eq!(baud_rates, [115_200, 1_500_000, 115_200]);
+    }
+
+    #[test]
+    fn set_baudrate_rejected() {
+        let mut input = Vec::new();
+        push_request(&mut input, set_baudrate_request(u64::MAX));
+        push_request(&mut input, set_baudrate_request(4_000_000));
+        push_request(&mut input, proxy_request(ProxyOpcode::Exit));
+
+        let (output, baud_rates) = run_baudrate_session(&input);
+        let output = &mut &output[..];
+
+        assert_eq!(baud_rates, [115_200]);
+        assert_eq!(take_reply(output), UartReply::boot());
+        assert_eq!(
+            take_reply(output),
+            set_baudrate_reply(ProxyStatus::Rejected, 0)
+        );
+        // Unreachable rates are only noticed after replying, the host falls back on its own.
+        assert_eq!(
+            take_reply(output),
+            set_baudrate_reply(ProxyStatus::Ok, 115_200)
+        );
+        assert_eq!(take_reply(output).get_command_id(), CommandId::Proxy);
+        assert!(output.is_empty());
+
+        // Transports without a baud rate reject the change.
+        let mut input = Vec::new();
+        push_request(&mut input, set_baudrate_request(115_200));
+
+        let mut output = vec![0x0u8; 0x100];
+        let mut loopback = Loopback::new(&input, &mut output);
+
+        assert!(run_proxy(&mut loopback, &mut TestHandler { memory: Vec::new() }).is_err());
+        assert_eq!(
+            take_reply(&mut &loopback.get_output()[REPLY_SIZE..]),
+            set_baudrate_reply(ProxyStatus::Rejected, 0)
+        );
+    }
+
+    struct AlwaysExpired;
+
+    impl CountDown for AlwaysExpired {
+        type Time = u64;
+
+        fn start<T: Into<Self::Time>>(&mut self, _count: T) {}
+
+        fn wait(&mut self) -> nb::Result<(), Void> {
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,305 @@
+//! Transports the proxy can be served over
+
+use core::cell::UnsafeCell;
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use embedded_hal::timer::CountDown;
+
+/// Byte stream the proxy is served over.
+pub trait ProxyTransport {
+    type Error: core::fmt::Debug;
+
+    /// Read a byte, returning None if nothing arrived within `timeout` microseconds.
+    fn read_byte(&mut self, timeout: u64) -> Result<Option<u8>, Self::Error>;
+
+    fn write(&mut self, data: &[u8]) -> Result<(), Self::Error>;
+
+    /// Wait for all written data to be sent.
+    fn flush(&mut self) -> Result<(), Self::Error>;
+
+    /// Current baud rate, None if the transport doesn't have one.
+    fn get_baudrate(&self) -> Option<u32> {
+        None
+    }
+
+    /// Switch to a new baud rate, returning the achieved rate or None if it cannot be used.
+    fn set_baudrate(&mut self, _baud_rate: u32) -> Option<u32> {
+        None
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum LoopbackError {
+    EndOfInput,
+    OutputFull,
+}
+
+/// Memory-backed transport reading requests from a buffer and writing replies to another.
+///
+/// Running out of input is reported as an error so a handler stops once everything was processed.
+pub struct Loopback<'a> {
+    input: &'a [u8],
+    output: &'a mut [u8],
+    read_position: usize,
+    write_position: usize,
+}
+
+impl<'a> Loopback<'a> {
+    pub fn new(input: &'a [u8], output: &'a mut [u8]) -> Self {
+        Loopback {
+            input,
+            output,
+            read_position: 0,
+            write_position: 0,
+        }
+    }
+
+    /// Data written so far.
+    pub fn get_output(&self) -> &[u8] {
+        &self.output[..self.write_position]
+    }
+}
+
+impl ProxyTransport for Loopback<'_> {
+    type Error = LoopbackError;
+
+    fn read_byte(&mut self, _timeout: u64) -> Result<Option<u8>, Self::Error> {
+        let value = *self
+            .input
+            .get(self.read_position)
+            .ok_or(LoopbackError::EndOfInput)?;
+
+        self.read_position += 1;
+
+        Ok(Some(value))
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<(), Self::Error> {
+        let end = self.write_position + data.len();
+
+        self.output
+            .get_mut(self.write_position..end)
+            .ok_or(LoopbackError::OutputFull)?
+            .copy_from_slice(data);
+
+        self.write_position = end;
+
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+/// Size of each direction of a mailbox.
+pub const MAILBOX_RING_SIZE: usize = 0x1000;
+
+/// Single producer, single consumer byte ring living in shared memory.
+#[repr(C)]
+pub struct MailboxRing {
+    /// Index of the next byte to write, only updated by the producer.
+    head: AtomicU32,
+    /// Index of the next byte to read, only updated by the consumer.
+    tail: AtomicU32,
+    data: UnsafeCell<[u8; MAILBOX_RING_SIZE]>,
+}
+
+unsafe impl Sync for MailboxRing {}
+
+impl MailboxRing {
+    pub const fn new() -> Self {
+        MailboxRing {
+            head: AtomicU32::new(0),
+            tail: AtomicU32::new(0),
+            data: UnsafeCell::new([0; MAILBOX_RING_SIZE]),
+        }
+    }
+
+    /// Push a byte, returning false if the ring is full.
+    pub fn push(&self, value: u8) -> bool {
+        let head = self.head.load(Ordering::Relaxed);
+        let next_head = (head + 1) % MAILBOX_RING_SIZE as u32;
+
+        if next_head == self.tail.load(Ordering::Acquire) {
+            return false;
+        }
+
+        unsafe { core::ptr::write_volatile(&mut (*self.data.get())[head as usize], value) };
+
+        self.head.store(next_head, Ordering::Release);
+
+        true
+    }
+
+    /// Pop a byte, returning None if the ring is empty.
+    pub fn pop(&self) -> Option<u8> {
+        let tail = self.tail.load(Ordering::Relaxed);
+
+        if tail == self.head.load(Ordering::Acquire) {
+            return None;
+        }
+
+        let value = unsafe { core::ptr::read_volatile(&(*self.data.get())[tail as usize]) };
+
+        self.tail
+            .store((tail + 1) % MAILBOX_RING_SIZE as u32, Ordering::Release);
+
+        Some(value)
+    }
+}
+
+impl Default for MailboxRing {
+    fn default() -> Self {
+        MailboxRing::new()
+    }
+}
+
+/// Pair of rings shared with the host.
+#[repr(C)]
+#[derive(Default)]
+pub struct Mailbox {
+    /// Data sent by the host.
+    pub request: MailboxRing,
+    /// Data sent to the host.
+    pub reply: MailboxRing,
+}
+
+impl Mailbox {
+    pub const fn new() -> Self {
+        Mailbox {
+            request: MailboxRing::new(),
+            reply: MailboxRing::new(),
+        }
+    }
+}
+
+/// Transport over a shared memory mailbox, using a countdown in microseconds for timeouts.
+pub struct MailboxTransport<'a, C> {
+    mailbox: &'a Mailbox,
+    timer: C,
+}
+
+impl<'a, C: CountDown<Time = u64>> MailboxTransport<'a, C> {
+    pub fn new(mailbox: &'a Mailbox, timer: C) -> Self {
+        MailboxTransport { mailbox, timer }
+    }
+}
+
+impl<C: CountDown<Time = u64>> ProxyTransport for MailboxTransport<'_, C> {
+    // No error possible
+    type Error = ();
+
+    fn read_byte(&mut self, timeout: u64) -> Result<Option<u8>, Self::Error> {
+        self.timer.start(timeout);
+
+        loop {
+            if let Some(value) = self.mailbox.request.pop() {
+                return Ok(Some(value));
+            }
+
+            if self.timer.wait().is_ok() {
+                return Ok(None);
+            }
+        }
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<(), Self::Error> {
+        for value in data {
+            while !self.mailbox.reply.push(*value) {}
+        }
+
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        // Data is visible to the host as soon as it was pushed.
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use void::Void;
+
+    /// Countdown expiring after a given number of polls.
+    struct PollTimer {
+        remaining: u64,
+    }
+
+    impl CountDown for PollTimer {
+        type Time = u64;
+
+        fn start<T: Into<Self::Time>>(&mut self, count: T) {
+            self.remaining = count.into();
+        }
+
+        fn wait(&mut self) -> nb::Result<(), Void> {
+            if self.remaining == 0 {
+                return Ok(());
+            }
+
+            self.remaining -= 1;
+
+            Err(nb::Error::WouldBlock)
+        }
+    }
+
+    #[test]
+    fn loopback_transfer() {
+        let mut output = [0x0u8; 4];
+        let mut loopback = Loopback::new(b"m1", &mut output);
+
+        assert_eq!(loopback.read_byte(0), Ok(Some(b'm')));
+        assert_eq!(loopback.read_byte(0), Ok(Some(b'1')));
+        assert_eq!(loopback.read_byte(0), Err(LoopbackError::EndOfInput));
+
+        assert_eq!(loopback.write(b"n1"), Ok(()));
+        assert_eq!(loopback.write(b"m1"), Ok(()));
+        assert_eq!(loopback.write(b"!"), Err(LoopbackError::OutputFull));
+        assert_eq!(loopback.get_output(), b"n1m1");
+    }
+
+    #[test]
+    fn mailbox_ring_wraps() {
+        let ring = MailboxRing::new();
+
+        for round in 0..3 {
+            for i in 0..MAILBOX_RING_SIZE - 1 {
+                assert!(ring.push((i + round) as u8));
+            }
+
+            assert!(!ring.push(0));
+
+            for i in 0..MAILBOX_RING_SIZE - 1 {
+                assert_eq!(ring.pop(), Some((i + round) as u8));
+            }
+
+            assert_eq!(ring.pop(), None);
+        }
+    }
+
+    #[test]
+    fn mailbox_transfer() {
+        let mailbox = Mailbox::new();
+        let mut transport = MailboxTransport::new(&mailbox, PollTimer { remaining: 0 });
+
+        assert_eq!(transport.read_byte(10), Ok(None));
+
+        assert!(mailbox.request.push(0x42));
+        assert_eq!(transport.read_byte(10), Ok(Some(0x42)));
+
+        assert_eq!(transport.write(b"m1saka"), Ok(()));
+
+        let mut received = Vec::new();
+
+        while let Some(value) = mailbox.reply.pop() {
+            received.push(value);
+        }
+
+        assert_eq!(received, b"m1saka");
+        assert_eq!(transport.flush(), Ok(()));
+    }
+}
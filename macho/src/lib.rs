@@ -0,0 +1,606 @@
+//! Mach-O 64 loader for arm64 images
+//!
+//! The `LC_SEGMENT_64` segments are copied with the lowest one at a chosen base, the way
+//! iBoot slides the images it loads. Filesets are loaded as a whole and started from the
+//! first of their entries having an entry point, usually the kernel.
+#![cfg_attr(not(test), no_std)]
+
+use core::ops::Range;
+
+use loader::{read_u32, read_u64};
+use loader::{Image, Mapping, PlacementError, SegmentError};
+
+const MH_MAGIC_64: u32 = 0xFEED_FACF;
+const CPU_TYPE_ARM64: u32 = 0x0100_000C;
+
+const MH_EXECUTE: u32 = 0x2;
+const MH_FILESET: u32 = 0xC;
+
+const LC_UNIXTHREAD: u32 = 0x5;
+const LC_SEGMENT_64: u32 = 0x19;
+const LC_MAIN: u32 = 0x8000_0028;
+const LC_FILESET_ENTRY: u32 = 0x8000_0035;
+
+const ARM_THREAD_STATE64: u32 = 6;
+
+pub const HEADER_SIZE: usize = 32;
+const LOAD_COMMAND_SIZE: usize = 8;
+const SEGMENT_COMMAND_SIZE: usize = 72;
+const MAIN_COMMAND_SIZE: usize = 24;
+const FILESET_ENTRY_COMMAND_SIZE: usize = 32;
+
+/// Offset of pc in a `LC_UNIXTHREAD` command, after the flavor, the count and x0 to sp.
+const THREAD_PC_OFFSET: usize = 16 + 32 * 8;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum MachError {
+    InvalidHeader,
+    UnsupportedMachine,
+    UnsupportedType,
+    Truncated,
+    /// A load command runs past `sizeofcmds` or is too small for its type.
+    InvalidCommand,
+    InvalidSegment,
+    /// Neither `LC_UNIXTHREAD` nor `LC_MAIN`, or an entry point outside of the segments.
+    MissingEntryPoint,
+    /// The memory given to `load` is smaller than the span of the segments.
+    DoesNotFit,
+}
+
+impl From<SegmentError> for MachError {
+    fn from(error: SegmentError) -> Self {
+        match error {
+            SegmentError::InvalidSegment => MachError::InvalidSegment,
+            SegmentError::Truncated => MachError::Truncated,
+            SegmentError::DoesNotFit => MachError::DoesNotFit,
+        }
+    }
+}
+
+pub type Result<T> = core::result::Result<T, MachError>;
+
+fn read_str(data: &[u8]) -> &str {
+    let length = data
+        .iter()
+        .position(|value| *value == 0)
+        .unwrap_or(data.len());
+
+    core::str::from_utf8(&data[..length]).unwrap_or("")
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct LoadCommand<'a> {
+    pub command: u32,
+    /// Whole command, including its type and size.
+    pub data: &'a [u8],
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Segment<'a> {
+    pub name: &'a str,
+    pub virtual_address: u64,
+    pub virtual_size: u64,
+    pub file_offset: u64,
+    pub file_size: u64,
+    pub max_protection: u32,
+    pub initial_protection: u32,
+}
+
+impl<'a> Segment<'a> {
+    fn parse(data: &'a [u8]) -> Self {
+        Segment {
+            name: read_str(&data[8..24]),
+            virtual_address: read_u64(data, 24),
+            virtual_size: read_u64(data, 32),
+            file_offset: read_u64(data, 40),
+            file_size: read_u64(data, 48),
+            max_protection: read_u32(data, 56),
+            initial_protection: read_u32(data, 60),
+        }
+    }
+
+    /// Segments without any access, such as `__PAGEZERO`, only reserve address space.
+    pub fn is_loaded(&self) -> bool {
+        self.virtual_size != 0 && self.max_protection != 0
+    }
+
+    fn get_mapping(&self) -> Mapping {
+        Mapping {
+            virtual_address: self.virtual_address,
+            memory_size: self.virtual_size,
+            file_offset: self.file_offset,
+            file_size: self.file_size,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct FilesetEntry<'a> {
+    pub identifier: &'a str,
+    pub virtual_address: u64,
+    pub file_offset: u64,
+}
+
+/// Check if an image starts with a Mach-O 64 header.
+pub fn is_macho(data: &[u8]) -> bool {
+    data.get(..4) == Some(&MH_MAGIC_64.to_le_bytes())
+}
+
+/// Size of the `mach_header_64` followed by its `sizeofcmds` bytes of load commands, which
+/// describe every segment of the file.
+pub fn get_headers_size(header: &[u8]) -> Result<usize> {
+    let header = header.get(..HEADER_SIZE).ok_or(MachError::Truncated)?;
+
+    if !is_macho(header) {
+        return Err(MachError::InvalidHeader);
+    }
+
+    HEADER_SIZE
+        .checked_add(read_u32(header, 20) as usize)
+        .ok_or(MachError::InvalidHeader)
+}
+
+fn read_command(commands: &[u8], offset: usize) -> Result<LoadCommand<'_>> {
+    let header = commands
+        .get(offset..offset + LOAD_COMMAND_SIZE)
+        .ok_or(MachError::InvalidCommand)?;
+    let size = read_u32(header, 4) as usize;
+
+    if size < LOAD_COMMAND_SIZE {
+        return Err(MachError::InvalidCommand);
+    }
+
+    Ok(LoadCommand {
+        command: read_u32(header, 0),
+        data: commands
+            .get(offset..offset + size)
+            .ok_or(MachError::InvalidCommand)?,
+    })
+}
+
+pub struct MachFile<'a> {
+    data: &'a [u8],
+    file_type: u32,
+    command_count: usize,
+    commands: &'a [u8],
+}
+
+impl<'a> MachFile<'a> {
+    /// Validate an arm64 `MH_EXECUTE` or `MH_FILESET` header and walk its load commands.
+    ///
+    /// Nothing past the load commands is read here, segment contents are only needed by `load`.
+    pub fn parse(data: &'a [u8]) -> Result<Self> {
+        let header = data.get(..HEADER_SIZE).ok_or(MachError::Truncated)?;
+
+        if !is_macho(header) {
+            return Err(MachError::InvalidHeader);
+        }
+
+        if read_u32(header, 4) != CPU_TYPE_ARM64 {
+            return Err(MachError::UnsupportedMachine);
+        }
+
+        let file_type = read_u32(header, 12);
+
+        if file_type != MH_EXECUTE && file_type != MH_FILESET {
+            return Err(MachError::UnsupportedType);
+        }
+
+        let commands = data
+            .get(HEADER_SIZE..get_headers_size(header)?)
+            .ok_or(MachError::Truncated)?;
+
+        let file = MachFile {
+            data,
+            file_type,
+            command_count: read_u32(header, 16) as usize,
+            commands,
+        };
+
+        // Walk the commands once, so that iterating them afterwards can't fail.
+        let mut offset = 0;
+
+        for _ in 0..file.command_count {
+            let command = read_command(file.commands, offset)?;
+            let minimum_size = match command.command {
+                LC_SEGMENT_64 => SEGMENT_COMMAND_SIZE,
+                LC_MAIN => MAIN_COMMAND_SIZE,
+                LC_FILESET_ENTRY => FILESET_ENTRY_COMMAND_SIZE,
+                _ => LOAD_COMMAND_SIZE,
+            };
+
+            if command.data.len() < minimum_size {
+                return Err(MachError::InvalidCommand);
+            }
+
+            offset += command.data.len();
+        }
+
+        Ok(file)
+    }
+
+    pub fn is_fileset(&self) -> bool {
+        self.file_type == MH_FILESET
+    }
+
+    pub fn commands(&self) -> impl Iterator<Item = LoadCommand<'a>> {
+        let commands = self.commands;
+        let mut offset = 0;
+
+        (0..self.command_count).map(move |_| {
+            let command = read_command(commands, offset).unwrap();
+
+            offset += command.data.len();
+
+            command
+        })
+    }
+
+    pub fn segments(&self) -> impl Iterator<Item = Segment<'a>> {
+        self.commands()
+            .filter(|command| command.command == LC_SEGMENT_64)
+            .map(|command| Segment::parse(command.data))
+    }
+
+    pub fn fileset_entries(&self) -> impl Iterator<Item = FilesetEntry<'a>> {
+        self.commands()
+            .filter(|command| command.command == LC_FILESET_ENTRY)
+            .map(|command| {
+                let identifier_offset = read_u32(command.data, 24) as usize;
+
+                FilesetEntry {
+                    identifier: command
+                        .data
+                        .get(identifier_offset..)
+                        .map(read_str)
+                        .unwrap_or(""),
+                    virtual_address: read_u64(command.data, 8),
+                    file_offset: read_u64(command.data, 16),
+                }
+            })
+    }
+
+    /// Virtual address of the entry point, from `LC_UNIXTHREAD` or `LC_MAIN`.
+    ///
+    /// The entry point of a fileset is the one of its first entry having one. Entries must be
+    /// executables, a fileset pointing back at itself or at another fileset is never followed.
+    pub fn get_entry(&self) -> Result<u64> {
+        if self.is_fileset() {
+            return self
+                .fileset_entries()
+                .find_map(|entry| {
+                    let data = self.data.get(entry.file_offset as usize..)?;
+                    let file = MachFile::parse(data)
+                        .ok()
+                        .filter(|file| !file.is_fileset())?;
+
+                    file.get_entry().ok()
+                })
+                .ok_or(MachError::MissingEntryPoint);
+        }
+
+        for command in self.commands() {
+            match command.command {
+                LC_UNIXTHREAD => {
+                    if command.data.len() < THREAD_PC_OFFSET + 8
+                        || read_u32(command.data, 8) != ARM_THREAD_STATE64
+                    {
+                        return Err(MachError::InvalidCommand);
+                    }
+
+                    return Ok(read_u64(command.data, THREAD_PC_OFFSET));
+                }
+                LC_MAIN => {
+                    let offset = read_u64(command.data, 8);
+
+                    return self
+                        .segments()
+                        .find(|segment| {
+                            offset >= segment.file_offset
+                                && offset - segment.file_offset < segment.file_size
+                        })
+                        .and_then(|segment| {
+                            segment
+                                .virtual_address
+                                .checked_add(offset - segment.file_offset)
+                        })
+                        .ok_or(MachError::InvalidCommand);
+                }
+                _ => {}
+            }
+        }
+
+        Err(MachError::MissingEntryPoint)
+    }
+
+    /// Bytes of the file holding the load commands and the contents of every segment,
+    /// `__LINKEDIT` and the fileset entries included.
+    pub fn get_file_size(&self) -> u64 {
+        loader::get_file_size(
+            (HEADER_SIZE + self.commands.len()) as u64,
+            self.segments().map(|segment| segment.get_mapping()),
+        )
+    }
+
+    /// Unslid virtual addresses spanned by the segments, leaving out `__PAGEZERO` which only
+    /// reserves the bottom of the address space.
+    pub fn get_load_range(&self) -> Result<Range<u64>> {
+        Ok(loader::get_load_range(
+            self.segments()
+                .filter(Segment::is_loaded)
+                .map(|segment| segment.get_mapping()),
+        )?)
+    }
+
+    /// Copy the segments to `memory`, slid so that the lowest one starts at `address`, and return
+    /// the slid entry point.
+    ///
+    /// The whole image moves by the same slide, a fileset keeps its entries at the same offsets
+    /// from each other as in its `LC_FILESET_ENTRY` commands.
+    pub fn load(&self, memory: &mut [u8], address: u64) -> Result<u64> {
+        let range = self.get_load_range()?;
+        let entry = self.get_entry()?;
+
+        if !range.contains(&entry) {
+            return Err(MachError::MissingEntryPoint);
+        }
+
+        loader::copy(
+            self.data,
+            self.segments()
+                .filter(Segment::is_loaded)
+                .map(|segment| segment.get_mapping()),
+            &range,
+            memory,
+        )?;
+
+        Ok(entry - range.start + address)
+    }
+}
+
+impl<'a> Image<'a> for MachFile<'a> {
+    type Error = MachError;
+
+    const HEADER_SIZE: usize = HEADER_SIZE;
+
+    fn get_headers_size(header: &[u8]) -> Result<usize> {
+        get_headers_size(header)
+    }
+
+    fn parse(data: &'a [u8]) -> Result<Self> {
+        MachFile::parse(data)
+    }
+
+    fn get_file_size(&self) -> u64 {
+        MachFile::get_file_size(self)
+    }
+
+    fn get_load_range(&self) -> Result<Range<u64>> {
+        MachFile::get_load_range(self)
+    }
+
+    /// Images are always slid, right after their file.
+    fn get_load_address(
+        &self,
+        file: &Range<u64>,
+        _: &Range<u64>,
+    ) -> core::result::Result<u64, PlacementError> {
+        loader::get_address_after(file, loader::LOAD_ALIGNMENT, 0)
+    }
+
+    fn load(&self, memory: &mut [u8], address: u64) -> Result<u64> {
+        MachFile::load(self, memory, address)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Written by testdata/make_macho.py.
+    const UNIXTHREAD: &[u8] = include_bytes!("../testdata/unixthread.macho");
+    const MAIN: &[u8] = include_bytes!("../testdata/main.macho");
+    const FILESET: &[u8] = include_bytes!("../testdata/fileset.macho");
+
+    const KERNEL_BASE: u64 = 0xFFFF_FE00_0700_4000;
+
+    // mov x0, #0x1234; ret
+    const CODE: &[u8] = &[0x80, 0x46, 0x82, 0xD2, 0xC0, 0x03, 0x5F, 0xD6];
+
+    #[test]
+    fn headers() {
+        let file = MachFile::parse(UNIXTHREAD).unwrap();
+
+        assert!(!file.is_fileset());
+        assert_eq!(file.commands().count(), 4);
+        assert_eq!(get_headers_size(UNIXTHREAD), Ok(0x218));
+        assert_eq!(file.get_file_size(), 0x320);
+        assert_eq!(file.get_entry(), Ok(0x1_0000_0280));
+        assert_eq!(file.get_load_range(), Ok(0x1_0000_0000..0x1_0000_2000));
+
+        let names: Vec<_> = file.segments().map(|segment| segment.name).collect();
+
+        assert_eq!(names, ["__PAGEZERO", "__TEXT", "__DATA"]);
+        assert!(!file.segments().next().unwrap().is_loaded());
+
+        let file = MachFile::parse(MAIN).unwrap();
+
+        assert_eq!(file.get_entry(), Ok(0x1_0000_0280));
+    }
+
+    #[test]
+    fn load() {
+        let file = MachFile::parse(UNIXTHREAD).unwrap();
+        let mut memory = vec![0xFFu8; 0x2000];
+        let base = 0x8_0380_0000;
+
+        assert_eq!(file.load(&mut memory, base), Ok(base + 0x280));
+        assert_eq!(&memory[..0x20], &UNIXTHREAD[..0x20]);
+        assert_eq!(&memory[0x280..0x288], CODE);
+        assert_eq!(&memory[0x1000..0x1007], b"m1saka\0");
+
+        // The end of the segments in the file, then zero fill.
+        assert!(memory[0x1007..0x1020].iter().all(|value| *value == 0xAA));
+        assert!(memory[0x1020..0x2000].iter().all(|value| *value == 0));
+        assert!(memory[0x300..0x1000].iter().all(|value| *value == 0));
+
+        assert_eq!(
+            file.load(&mut memory[..0x1FFF], base),
+            Err(MachError::DoesNotFit)
+        );
+
+        let file = MachFile::parse(&UNIXTHREAD[..0x310]).unwrap();
+
+        assert_eq!(file.load(&mut memory, base), Err(MachError::Truncated));
+    }
+
+    #[test]
+    fn fileset() {
+        let file = MachFile::parse(FILESET).unwrap();
+
+        assert!(file.is_fileset());
+        assert_eq!(file.get_file_size(), 0x700);
+        assert_eq!(file.get_load_range(), Ok(KERNEL_BASE..KERNEL_BASE + 0x3000));
+
+        let entries: Vec<_> = file.fileset_entries().collect();
+
+        assert_eq!(
+            entries,
+            [
+                FilesetEntry {
+                    identifier: "com.example.driver.Example",
+                    virtual_address: KERNEL_BASE + 0x2000,
+                    file_offset: 0x600,
+                },
+                FilesetEntry {
+                    identifier: "com.apple.kernel",
+                    virtual_address: KERNEL_BASE + 0x1000,
+                    file_offset: 0x400,
+                },
+            ]
+        );
+
+        // The extension doesn't have an entry point, the kernel does.
+        assert_eq!(file.get_entry(), Ok(KERNEL_BASE + 0x11C0));
+
+        let mut memory = vec![0xFFu8; 0x3000];
+
+        assert_eq!(file.load(&mut memory, 0x8_0000_0000), Ok(0x8_0000_11C0));
+        assert_eq!(&memory[0x11C0..0x11C8], CODE);
+        assert_eq!(&memory[0x1000..0x1004], &MH_MAGIC_64.to_le_bytes());
+        assert_eq!(&memory[0x2000..0x2004], &MH_MAGIC_64.to_le_bytes());
+
+        // Without the kernel, there's nothing to start.
+        let file = MachFile::parse(&FILESET[..0x400]).unwrap();
+
+        assert_eq!(file.get_entry(), Err(MachError::MissingEntryPoint));
+    }
+
+    #[test]
+    fn fileset_entry_loop() {
+        let mut data = FILESET.to_vec();
+
+        // The extension entry pointing at the fileset itself is skipped.
+        data[0x108..0x110].copy_from_slice(&0u64.to_le_bytes());
+
+        assert_eq!(
+            MachFile::parse(&data).unwrap().get_entry(),
+            Ok(KERNEL_BASE + 0x11C0)
+        );
+
+        // So is the kernel one, instead of recursing until the stack overflows.
+        data[0x148..0x150].copy_from_slice(&0u64.to_le_bytes());
+
+        assert_eq!(
+            MachFile::parse(&data).unwrap().get_entry(),
+            Err(MachError::MissingEntryPoint)
+        );
+    }
+
+    #[test]
+    fn invalid_headers() {
+        let mut data = UNIXTHREAD.to_vec();
+
+        // A 32-bit mach_header, as built for armv7.
+        data[..4].copy_from_slice(&0xFEED_FACEu32.to_le_bytes());
+
+        assert!(!is_macho(&data));
+        assert_eq!(get_headers_size(&data), Err(MachError::InvalidHeader));
+        assert_eq!(MachFile::parse(&data).err(), Some(MachError::InvalidHeader));
+
+        let mut data = UNIXTHREAD.to_vec();
+
+        // CPU_TYPE_ARM64_32, the 32-bit pointer ABI of the watches.
+        data[4..8].copy_from_slice(&0x0200_000Cu32.to_le_bytes());
+
+        assert_eq!(
+            MachFile::parse(&data).err(),
+            Some(MachError::UnsupportedMachine)
+        );
+
+        let mut data = UNIXTHREAD.to_vec();
+
+        // MH_KEXT_BUNDLE, kexts are only started as entries of a fileset.
+        data[12] = 0xB;
+
+        assert_eq!(
+            MachFile::parse(&data).err(),
+            Some(MachError::UnsupportedType)
+        );
+
+        // sizeofcmds past the end of the data given.
+        assert_eq!(get_headers_size(&UNIXTHREAD[..HEADER_SIZE]), Ok(0x218));
+        assert_eq!(
+            MachFile::parse(&UNIXTHREAD[..0x217]).err(),
+            Some(MachError::Truncated)
+        );
+        assert!(MachFile::parse(&UNIXTHREAD[..0x218]).is_ok());
+        assert_eq!(
+            get_headers_size(&UNIXTHREAD[..HEADER_SIZE - 1]),
+            Err(MachError::Truncated)
+        );
+    }
+
+    #[test]
+    fn invalid_commands() {
+        let mut data = UNIXTHREAD.to_vec();
+
+        // The last command overflowing the commands.
+        data[0xFD] = 0x10;
+
+        assert_eq!(
+            MachFile::parse(&data).err(),
+            Some(MachError::InvalidCommand)
+        );
+
+        let mut data = UNIXTHREAD.to_vec();
+
+        // Segment command too small.
+        data[0x24] = 0x10;
+
+        assert_eq!(
+            MachFile::parse(&data).err(),
+            Some(MachError::InvalidCommand)
+        );
+
+        let mut data = UNIXTHREAD.to_vec();
+
+        // ARM_THREAD_STATE32
+        data[0xF8 + 8] = 1;
+
+        assert_eq!(
+            MachFile::parse(&data).unwrap().get_entry(),
+            Err(MachError::InvalidCommand)
+        );
+
+        let mut data = MAIN.to_vec();
+
+        // __TEXT mapped so high that the LC_MAIN entry offset wraps around.
+        data[0x80..0x88].copy_from_slice(&(u64::MAX - 0x100).to_le_bytes());
+
+        assert_eq!(
+            MachFile::parse(&data).unwrap().get_entry(),
+            Err(MachError::InvalidCommand)
+        );
+    }
+}
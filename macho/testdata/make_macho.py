@@ -0,0 +1,127 @@
+#!/usr/bin/env python3
+# Generate the Mach-O fixtures of the macho unit tests: an executable with its
+# entry point in LC_UNIXTHREAD like m1n1.macho, the same with LC_MAIN, and a
+# fileset holding a kernel and an extension like a kernel collection.
+#
+# Segments are packed instead of page aligned to keep the files small.
+
+import os
+import struct
+
+MH_MAGIC_64 = 0xFEEDFACF
+CPU_TYPE_ARM64 = 0x0100000C
+MH_EXECUTE, MH_FILESET = 0x2, 0xC
+
+LC_UNIXTHREAD, LC_SEGMENT_64 = 0x5, 0x19
+LC_MAIN, LC_FILESET_ENTRY = 0x80000028, 0x80000035
+
+ARM_THREAD_STATE64, ARM_THREAD_STATE64_COUNT = 6, 68
+
+VM_PROT_RX, VM_PROT_RW = 0x5, 0x3
+
+# mov x0, #0x1234; ret
+CODE = struct.pack("<II", 0xD2824680, 0xD65F03C0)
+
+
+def header(file_type, commands):
+    data = b"".join(commands)
+
+    return struct.pack(
+        "<IIIIIIII", MH_MAGIC_64, CPU_TYPE_ARM64, 0, file_type, len(commands), len(data), 0, 0
+    ) + data
+
+
+def segment(name, vmaddr, vmsize, fileoff, filesize, protection):
+    return struct.pack(
+        "<II16sQQQQIIII",
+        LC_SEGMENT_64,
+        72,
+        name.encode(),
+        vmaddr,
+        vmsize,
+        fileoff,
+        filesize,
+        protection,
+        protection,
+        0,
+        0,
+    )
+
+
+def unix_thread(pc):
+    # x0-x28, fp, lr, sp, pc, cpsr and padding
+    state = struct.pack("<33QII", *([0] * 32 + [pc]), 0x3C5, 0)
+
+    return struct.pack("<IIII", LC_UNIXTHREAD, 16 + len(state), ARM_THREAD_STATE64,
+                       ARM_THREAD_STATE64_COUNT) + state
+
+
+def main(entry_offset):
+    return struct.pack("<IIQQ", LC_MAIN, 24, entry_offset, 0)
+
+
+def fileset_entry(vmaddr, fileoff, identifier):
+    identifier = identifier.encode() + b"\0"
+    identifier += b"\0" * (-(32 + len(identifier)) % 8)
+
+    return struct.pack("<IIQQII", LC_FILESET_ENTRY, 32 + len(identifier), vmaddr, fileoff, 32, 0) + identifier
+
+
+def place(data, offset, content):
+    assert len(data) <= offset
+
+    return data + b"\0" * (offset - len(data)) + content
+
+
+def executable(entry_command):
+    base = 0x1_0000_0000
+    commands = [
+        # Reserves the low 4GB, never loaded.
+        segment("__PAGEZERO", 0x0, base, 0x0, 0x0, 0),
+        segment("__TEXT", base, 0x1000, 0x0, 0x300, VM_PROT_RX),
+        # Message followed by zero fill.
+        segment("__DATA", base + 0x1000, 0x1000, 0x300, 0x20, VM_PROT_RW),
+        entry_command(base + 0x280),
+    ]
+
+    data = place(header(MH_EXECUTE, commands), 0x280, CODE)
+
+    return place(data, 0x300, b"m1saka\0".ljust(0x20, b"\xAA"))
+
+
+def fileset():
+    base = 0xFFFF_FE00_0700_4000
+    kernel, extension = base + 0x1000, base + 0x2000
+
+    kernel_header = header(
+        MH_EXECUTE,
+        [segment("__TEXT", kernel, 0x1000, 0x400, 0x200, VM_PROT_RX), unix_thread(kernel + 0x1C0)],
+    )
+    extension_header = header(
+        MH_EXECUTE, [segment("__TEXT", extension, 0x1000, 0x600, 0x100, VM_PROT_RX)]
+    )
+
+    commands = [
+        segment("__TEXT", base, 0x1000, 0x0, 0x400, VM_PROT_RX),
+        segment("__KERNEL_TEXT", kernel, 0x1000, 0x400, 0x200, VM_PROT_RX),
+        segment("__EXTENSION_TEXT", extension, 0x1000, 0x600, 0x100, VM_PROT_RX),
+        # The extension comes first, without an entry point.
+        fileset_entry(extension, 0x600, "com.example.driver.Example"),
+        fileset_entry(kernel, 0x400, "com.apple.kernel"),
+    ]
+
+    data = place(header(MH_FILESET, commands), 0x400, kernel_header)
+    data = place(data, 0x5C0, CODE)
+
+    return place(data, 0x600, extension_header.ljust(0x100, b"\0"))
+
+
+os.chdir(os.path.dirname(os.path.abspath(__file__)))
+
+for name, data in [
+    ("unixthread.macho", executable(unix_thread)),
+    ("main.macho", executable(lambda entry: main(entry - 0x1_0000_0000))),
+    ("fileset.macho", fileset()),
+]:
+    with open(name, "wb") as file:
+        file.write(data)
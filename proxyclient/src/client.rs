@@ -0,0 +1,501 @@
+//! m1n1 proxy client
+
+use std::convert::TryInto;
+use std::fmt;
+use std::io;
+use std::thread;
+use std::time::Duration;
+
+use embedded_hal::serial::{Read, Write};
+use m1n1_protocol::{
+    CommandId, DecodeError, ProxyOpcode, ProxyReply, ProxyRequest, ProxyStatus, Status, UartReply,
+    UartRequest, REPLY_SIZE, REQUEST_MAGIC,
+};
+
+/// Fields of iBoot's boot arguments, laid out as in the payload's `BootArgs`.
+const BOOT_ARGS_VIRT_BASE_OFFSET: usize = 0x8;
+const BOOT_ARGS_PHYS_BASE_OFFSET: usize = 0x10;
+const BOOT_ARGS_DEVICE_TREE_OFFSET: usize = 0x60;
+const BOOT_ARGS_DEVICE_TREE_SIZE_OFFSET: usize = 0x68;
+
+#[derive(Debug)]
+pub enum ClientError {
+    Io(io::Error),
+    Decode(DecodeError),
+    UnexpectedReply(UartReply),
+    Status(CommandId, Status),
+    Proxy(ProxyReply),
+    DataChecksumMismatch { expected: u32, computed: u32 },
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientError::Io(error) => write!(f, "I/O error: {}", error),
+            ClientError::Decode(error) => write!(f, "Invalid reply: {:?}", error),
+            ClientError::UnexpectedReply(reply) => write!(f, "Unexpected reply: {:?}", reply),
+            ClientError::Status(command_id, status) => {
+                write!(f, "{:?} failed with status {:?}", command_id, status)
+            }
+            ClientError::Proxy(reply) => write!(
+                f,
+                "Proxy opcode 0x{:x} failed with status {:?} (0x{:x})",
+                reply.opcode, reply.status, reply.return_value
+            ),
+            ClientError::DataChecksumMismatch { expected, computed } => write!(
+                f,
+                "Data checksum mismatch: {:x} vs {:x}",
+                expected, computed
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ClientError {}
+
+impl From<io::Error> for ClientError {
+    fn from(error: io::Error) -> Self {
+        ClientError::Io(error)
+    }
+}
+
+impl From<DecodeError> for ClientError {
+    fn from(error: DecodeError) -> Self {
+        ClientError::Decode(error)
+    }
+}
+
+impl From<m1n1_protocol::Error<io::Error>> for ClientError {
+    fn from(error: m1n1_protocol::Error<io::Error>) -> Self {
+        match error {
+            m1n1_protocol::Error::Serial(error) => ClientError::Io(error),
+            m1n1_protocol::Error::Decode(error) => ClientError::Decode(error),
+            m1n1_protocol::Error::Timeout | m1n1_protocol::Error::Truncated(_) => {
+                ClientError::Io(io::ErrorKind::TimedOut.into())
+            }
+        }
+    }
+}
+
+pub type Result<T> = std::result::Result<T, ClientError>;
+
+/// Time given to the target to switch baud rate once its reply was received.
+const BAUD_RATE_SWITCH_DELAY: Duration = Duration::from_millis(10);
+
+/// Serial port whose baud rate can be changed.
+pub trait BaudRate {
+    fn set_baudrate(&mut self, baud_rate: u32) -> io::Result<()>;
+}
+
+/// Client for a target running the m1n1 proxy (m1n1 itself or m1saka).
+pub struct ProxyClient<S> {
+    serial: S,
+    console: Vec<u8>,
+}
+
+impl<S> ProxyClient<S>
+where
+    S: Read<u8, Error = io::Error> + Write<u8, Error = io::Error>,
+{
+    pub fn new(serial: S) -> Self {
+        ProxyClient {
+            serial,
+            console: Vec::new(),
+        }
+    }
+
+    /// Take everything that was received outside of a reply (usually logs from the target).
+    pub fn take_console_output(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.console)
+    }
+
+    fn read_byte(&mut self) -> Result<u8> {
+        Ok(nb::block!(self.serial.read())?)
+    }
+
+    /// Read the next reply, skipping any data preceding the reply magic.
+    pub fn read_reply(&mut self) -> Result<UartReply> {
+        let mut raw_packet = [0x0u8; REPLY_SIZE];
+        let mut window = [0x0u8; 3];
+        let mut skipped = 0;
+
+        while window != REQUEST_MAGIC {
+            if skipped >= window.len() {
+                self.console.push(window[0]);
+            }
+
+            window.rotate_left(1);
+            window[2] = self.read_byte()?;
+            skipped += 1;
+        }
+
+        raw_packet[..3].copy_from_slice(&window);
+
+        for entry in raw_packet.iter_mut().skip(3) {
+            *entry = self.read_byte()?;
+        }
+
+        Ok(UartReply::from_raw_packet(&raw_packet)?)
+    }
+
+    fn send_request(&mut self, request: &UartRequest) -> Result<()> {
+        m1n1_protocol::write_request(&mut self.serial, request)?;
+        nb::block!(self.serial.flush())?;
+
+        Ok(())
+    }
+
+    fn read_reply_for(&mut self, command_id: CommandId) -> Result<UartReply> {
+        let reply = self.read_reply()?;
+
+        if reply.get_command_id() != command_id {
+            return Err(ClientError::UnexpectedReply(reply));
+        }
+
+        if reply.get_status() != Status::Ok {
+            return Err(ClientError::Status(command_id, reply.get_status()));
+        }
+
+        Ok(reply)
+    }
+
+    /// Wait for the target to announce it started.
+    pub fn wait_boot(&mut self) -> Result<()> {
+        loop {
+            if let UartReply::Simple {
+                command_id: CommandId::Boot,
+                status,
+            } = self.read_reply()?
+            {
+                if status != Status::Ok {
+                    return Err(ClientError::Status(CommandId::Boot, status));
+                }
+
+                return Ok(());
+            }
+        }
+    }
+
+    pub fn nop(&mut self) -> Result<()> {
+        self.send_request(&UartRequest::Simple {
+            command_id: CommandId::NoOperation,
+        })?;
+        self.read_reply_for(CommandId::NoOperation)?;
+
+        Ok(())
+    }
+
+    pub fn read_memory(&mut self, address: u64, data: &mut [u8]) -> Result<()> {
+        self.send_request(&UartRequest::Memory {
+            command_id: CommandId::MemoryRead,
+            address,
+            size: data.len() as u64,
+            data_checksum: 0,
+        })?;
+
+        let expected = match self.read_reply_for(CommandId::MemoryRead)? {
+            UartReply::Memory { data_checksum, .. } => data_checksum,
+            reply => return Err(ClientError::UnexpectedReply(reply)),
+        };
+
+        let computed = m1n1_protocol::read_data(&mut self.serial, data)?;
+
+        if expected != computed {
+            return Err(ClientError::DataChecksumMismatch { expected, computed });
+        }
+
+        Ok(())
+    }
+
+    pub fn write_memory(&mut self, address: u64, data: &[u8]) -> Result<()> {
+        let expected = m1n1_protocol::checksum(data);
+
+        self.send_request(&UartRequest::Memory {
+            command_id: CommandId::MemoryWrite,
+            address,
+            size: data.len() as u64,
+            data_checksum: expected,
+        })?;
+        m1n1_protocol::write_data(&mut self.serial, data)?;
+        nb::block!(self.serial.flush())?;
+
+        match self.read_reply_for(CommandId::MemoryWrite)? {
+            UartReply::Memory { data_checksum, .. } if data_checksum == expected => Ok(()),
+            UartReply::Memory { data_checksum, .. } => Err(ClientError::DataChecksumMismatch {
+                expected,
+                computed: data_checksum,
+            }),
+            reply => Err(ClientError::UnexpectedReply(reply)),
+        }
+    }
+
+    fn send_proxy_request(&mut self, opcode: ProxyOpcode, args: &[u64]) -> Result<()> {
+        assert!(args.len() <= 6, "Too many proxy arguments");
+
+        let mut request = ProxyRequest {
+            opcode: opcode as u64,
+            args: [0; 6],
+        };
+
+        request.args[..args.len()].copy_from_slice(args);
+
+        self.send_request(&UartRequest::Proxy {
+            command_id: CommandId::Proxy,
+            request,
+        })
+    }
+
+    /// Execute a proxy opcode, returning its return value.
+    pub fn proxy(&mut self, opcode: ProxyOpcode, args: &[u64]) -> Result<u64> {
+        self.send_proxy_request(opcode, args)?;
+
+        match self.read_reply_for(CommandId::Proxy)? {
+            UartReply::Proxy { reply, .. } if reply.status == ProxyStatus::Ok => {
+                Ok(reply.return_value)
+            }
+            UartReply::Proxy { reply, .. } => Err(ClientError::Proxy(reply)),
+            reply => Err(ClientError::UnexpectedReply(reply)),
+        }
+    }
+
+    pub fn get_base(&mut self) -> Result<u64> {
+        self.proxy(ProxyOpcode::GetBase, &[])
+    }
+
+    pub fn get_boot_args(&mut self) -> Result<u64> {
+        self.proxy(ProxyOpcode::GetBootArgs, &[])
+    }
+
+    /// Read the Apple device tree iBoot passed to the target.
+    pub fn read_device_tree(&mut self) -> Result<Vec<u8>> {
+        let mut boot_args = [0; BOOT_ARGS_DEVICE_TREE_SIZE_OFFSET + 4];
+        let boot_args_address = self.get_boot_args()?;
+
+        self.read_memory(boot_args_address, &mut boot_args)?;
+
+        let read_u64 =
+            |offset: usize| u64::from_le_bytes(boot_args[offset..offset + 8].try_into().unwrap());
+        let address = read_u64(BOOT_ARGS_DEVICE_TREE_OFFSET)
+            .wrapping_sub(read_u64(BOOT_ARGS_VIRT_BASE_OFFSET))
+            .wrapping_add(read_u64(BOOT_ARGS_PHYS_BASE_OFFSET));
+        let size = u32::from_le_bytes(
+            boot_args[BOOT_ARGS_DEVICE_TREE_SIZE_OFFSET..]
+                .try_into()
+                .unwrap(),
+        );
+
+        let mut device_tree = vec![0; size as usize];
+
+        self.read_memory(address, &mut device_tree)?;
+
+        Ok(device_tree)
+    }
+
+    /// Call a function on the target with up to five arguments.
+    pub fn call(&mut self, address: u64, args: &[u64]) -> Result<u64> {
+        assert!(args.len() <= 5, "Too many call arguments");
+
+        let mut call_args = vec![address];
+        call_args.extend_from_slice(args);
+
+        self.proxy(ProxyOpcode::Call, &call_args)
+    }
+
+    /// Hand control to the image at `entry`.
+    ///
+    /// The image is expected to send a boot reply once started.
+    pub fn boot(&mut self, entry: u64) -> Result<()> {
+        self.send_request(&UartRequest::Boot {
+            command_id: CommandId::Boot,
+            entry,
+        })?;
+        self.read_reply_for(CommandId::Boot)?;
+
+        self.wait_boot()
+    }
+
+    /// Upload an image and boot it.
+    pub fn chainload(&mut self, address: u64, image: &[u8]) -> Result<()> {
+        self.write_memory(address, image)?;
+
+        self.boot(address)
+    }
+}
+
+impl<S> ProxyClient<S>
+where
+    S: Read<u8, Error = io::Error> + Write<u8, Error = io::Error> + BaudRate,
+{
+    /// Switch both sides to a new baud rate, returning the previous one.
+    ///
+    /// If the target doesn't answer at the new rate, both sides go back to the previous one.
+    pub fn set_baudrate(&mut self, baud_rate: u32) -> Result<u32> {
+        let previous_baud_rate = self.proxy(ProxyOpcode::SetBaud, &[u64::from(baud_rate)])? as u32;
+
+        self.serial.set_baudrate(baud_rate)?;
+        thread::sleep(BAUD_RATE_SWITCH_DELAY);
+
+        if let Err(error) = self.nop() {
+            self.serial.set_baudrate(previous_baud_rate)?;
+
+            return Err(error);
+        }
+
+        Ok(previous_baud_rate)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::serial::SerialPort;
+    use crate::simulator::Simulator;
+
+    const BASE: u64 = 0x8_0380_0000;
+
+    fn start_simulator() -> ProxyClient<SerialPort> {
+        let (master, slave) = SerialPort::open_pty().unwrap();
+
+        thread::spawn(move || Simulator::new(master, BASE, 0x10000).run());
+
+        let mut client = ProxyClient::new(slave);
+        client.wait_boot().unwrap();
+        client
+    }
+
+    #[test]
+    fn boot_and_nop() {
+        let mut client = start_simulator();
+
+        client.nop().unwrap();
+
+        assert_eq!(
+            client.take_console_output(),
+            b"Hello I'm m1saka say m1saka\r\n".to_vec()
+        );
+    }
+
+    #[test]
+    fn get_base() {
+        let mut client = start_simulator();
+
+        assert_eq!(client.get_base().unwrap(), BASE);
+    }
+
+    #[test]
+    fn read_device_tree() {
+        let mut client = start_simulator();
+        let virt_base = 0xFFFF_FE00_0700_0000u64;
+        let mut boot_args = vec![0; 0x2E0];
+
+        boot_args[0x8..0x10].copy_from_slice(&virt_base.to_le_bytes());
+        boot_args[0x10..0x18].copy_from_slice(&BASE.to_le_bytes());
+        boot_args[0x60..0x68].copy_from_slice(&(virt_base + 0x4000).to_le_bytes());
+        boot_args[0x68..0x6C].copy_from_slice(&0x123u32.to_le_bytes());
+
+        let device_tree: Vec<u8> = (0..0x123).map(|i| (i * 3) as u8).collect();
+
+        client.write_memory(BASE, &boot_args).unwrap();
+        client.write_memory(BASE + 0x4000, &device_tree).unwrap();
+
+        assert_eq!(client.get_boot_args().unwrap(), BASE);
+        assert_eq!(client.read_device_tree().unwrap(), device_tree);
+    }
+
+    #[test]
+    fn write_then_read_memory() {
+        let mut client = start_simulator();
+
+        let data: Vec<u8> = (0..0x1234).map(|i| (i * 7) as u8).collect();
+        client.write_memory(BASE + 0x100, &data).unwrap();
+
+        let mut result = vec![0; data.len()];
+        client.read_memory(BASE + 0x100, &mut result).unwrap();
+
+        assert_eq!(result, data);
+    }
+
+    #[test]
+    fn register_access() {
+        let mut client = start_simulator();
+
+        client
+            .proxy(ProxyOpcode::Write32, &[BASE + 0x40, 0xCAFEBABE])
+            .unwrap();
+
+        assert_eq!(
+            client.proxy(ProxyOpcode::Read32, &[BASE + 0x40]).unwrap(),
+            0xCAFEBABE
+        );
+        assert_eq!(
+            client.proxy(ProxyOpcode::Read8, &[BASE + 0x43]).unwrap(),
+            0xCA
+        );
+    }
+
+    #[test]
+    fn unknown_opcode() {
+        let mut client = start_simulator();
+
+        match client.proxy(ProxyOpcode::GetExcCount, &[]) {
+            Err(ClientError::Proxy(reply)) => assert_eq!(reply.status, ProxyStatus::BadCommand),
+            result => panic!("Unexpected result: {:?}", result),
+        }
+    }
+
+    #[test]
+    fn memory_access_out_of_range() {
+        let mut client = start_simulator();
+
+        let mut data = [0x0u8; 4];
+
+        match client.read_memory(0x1000, &mut data) {
+            Err(ClientError::Status(CommandId::MemoryRead, Status::Invalid)) => {}
+            result => panic!("Unexpected result: {:?}", result),
+        }
+
+        // The session must still be usable afterward.
+        client.nop().unwrap();
+    }
+
+    #[test]
+    fn call() {
+        let mut client = start_simulator();
+
+        assert_eq!(client.call(BASE + 0x1000, &[42, 1, 2]).unwrap(), 42);
+    }
+
+    #[test]
+    fn chainload() {
+        let mut client = start_simulator();
+
+        // b +8, followed by some data.
+        let image = [0x02, 0x00, 0x00, 0x14, 0xAA, 0xBB, 0xCC, 0xDD];
+
+        client.chainload(BASE + 0x2000, &image).unwrap();
+        client.nop().unwrap();
+    }
+
+    #[test]
+    fn boot_invalid_image() {
+        let mut client = start_simulator();
+
+        match client.boot(BASE + 0x3000) {
+            Err(ClientError::Status(CommandId::Boot, Status::Invalid)) => {}
+            result => panic!("Unexpected result: {:?}", result),
+        }
+
+        client.nop().unwrap();
+    }
+
+    #[test]
+    fn set_baudrate() {
+        let mut client = start_simulator();
+
+        assert_eq!(client.set_baudrate(1_500_000).unwrap(), 115_200);
+        assert_eq!(client.set_baudrate(115_200).unwrap(), 1_500_000);
+
+        client.nop().unwrap();
+    }
+}
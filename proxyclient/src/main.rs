@@ -0,0 +1,201 @@
+//! Host-side client for m1saka's m1n1 proxy.
+
+mod client;
+mod serial;
+#[cfg(test)]
+mod simulator;
+
+use std::env;
+use std::fs;
+use std::io;
+use std::io::Write;
+use std::process;
+
+use client::ProxyClient;
+use serial::SerialPort;
+
+const DEFAULT_BAUDRATE: u32 = 1_500_000;
+
+/// Offset from the running payload base where chainloaded images are uploaded by default.
+const DEFAULT_CHAINLOAD_OFFSET: u64 = 0x100_0000;
+
+const USAGE: &str = "Usage: proxyclient [-d DEVICE] [-b BAUDRATE] [-s BAUDRATE] COMMAND [ARGS...]
+
+Options:
+    -d DEVICE                   Serial device to use
+    -b BAUDRATE                 Baud rate the target is currently using
+    -s BAUDRATE                 Switch to a faster baud rate before running the command
+
+Commands:
+    nop                         Check that the proxy is responding
+    read ADDRESS SIZE [FILE]    Read memory, hexdump it or save it to FILE
+    write ADDRESS FILE          Write the content of FILE to memory
+    call ADDRESS [ARGS...]      Call a function with up to five arguments
+    boot ADDRESS                Jump to an already uploaded image
+    chainload FILE [ADDRESS]    Upload FILE and jump to it
+    dump-adt FILE               Save the Apple device tree iBoot passed to FILE
+
+The device defaults to the M1N1DEVICE environment variable.";
+
+fn usage() -> ! {
+    eprintln!("{}", USAGE);
+    process::exit(1);
+}
+
+fn parse_number(value: &str) -> u64 {
+    let result = if let Some(hex) = value.strip_prefix("0x") {
+        u64::from_str_radix(hex, 16)
+    } else {
+        value.parse()
+    };
+
+    match result {
+        Ok(value) => value,
+        Err(_) => {
+            eprintln!("Invalid number: {}", value);
+            usage()
+        }
+    }
+}
+
+fn hexdump(address: u64, data: &[u8]) {
+    for (i, line) in data.chunks(16).enumerate() {
+        let bytes: Vec<String> = line.iter().map(|b| format!("{:02x}", b)).collect();
+
+        println!("{:016x}: {}", address + (i * 16) as u64, bytes.join(" "));
+    }
+}
+
+fn flush_console(client: &mut ProxyClient<SerialPort>) {
+    let output = client.take_console_output();
+
+    if !output.is_empty() {
+        io::stderr().write_all(&output).unwrap();
+    }
+}
+
+fn run_command(
+    client: &mut ProxyClient<SerialPort>,
+    command: &str,
+    args: &[String],
+) -> client::Result<()> {
+    match (command, args) {
+        ("nop", []) => {
+            client.nop()?;
+            println!("Proxy is alive");
+        }
+        ("read", [address, size]) | ("read", [address, size, _]) => {
+            let address = parse_number(address);
+            let mut data = vec![0; parse_number(size) as usize];
+
+            client.read_memory(address, &mut data)?;
+
+            match args.get(2) {
+                Some(path) => fs::write(path, &data)?,
+                None => hexdump(address, &data),
+            }
+        }
+        ("write", [address, path]) => {
+            let data = fs::read(path)?;
+
+            client.write_memory(parse_number(address), &data)?;
+        }
+        ("call", [address, call_args @ ..]) if call_args.len() <= 5 => {
+            let call_args: Vec<u64> = call_args.iter().map(|arg| parse_number(arg)).collect();
+            let result = client.call(parse_number(address), &call_args)?;
+
+            println!("0x{:x}", result);
+        }
+        ("boot", [address]) => {
+            client.boot(parse_number(address))?;
+            println!("Booted 0x{:x}", parse_number(address));
+        }
+        ("chainload", [path]) | ("chainload", [path, _]) => {
+            let image = fs::read(path)?;
+            let address = match args.get(1) {
+                Some(address) => parse_number(address),
+                None => client.get_base()? + DEFAULT_CHAINLOAD_OFFSET,
+            };
+
+            println!("Chainloading {} at 0x{:x}", path, address);
+
+            client.chainload(address, &image)?;
+        }
+        ("dump-adt", [path]) => {
+            let device_tree = client.read_device_tree()?;
+
+            fs::write(path, &device_tree)?;
+            println!(
+                "Saved {} bytes of device tree to {}",
+                device_tree.len(),
+                path
+            );
+        }
+        _ => usage(),
+    }
+
+    Ok(())
+}
+
+fn main() {
+    let mut args = env::args().skip(1).peekable();
+    let mut device = env::var("M1N1DEVICE").ok();
+    let mut baudrate = DEFAULT_BAUDRATE;
+    let mut switch_baudrate = None;
+
+    while let Some(arg) = args.peek() {
+        match arg.as_str() {
+            "-d" => {
+                args.next();
+                device = Some(args.next().unwrap_or_else(|| usage()));
+            }
+            "-b" => {
+                args.next();
+                baudrate = parse_number(&args.next().unwrap_or_else(|| usage())) as u32;
+            }
+            "-s" => {
+                args.next();
+                switch_baudrate =
+                    Some(parse_number(&args.next().unwrap_or_else(|| usage())) as u32);
+            }
+            "-h" | "--help" => usage(),
+            _ => break,
+        }
+    }
+
+    let command = args.next().unwrap_or_else(|| usage());
+    let command_args: Vec<String> = args.collect();
+
+    let device = device.unwrap_or_else(|| {
+        eprintln!("No device specified, use -d or M1N1DEVICE");
+        usage()
+    });
+
+    let serial = match SerialPort::open(&device, baudrate) {
+        Ok(serial) => serial,
+        Err(error) => {
+            eprintln!("Cannot open {}: {}", device, error);
+            process::exit(1);
+        }
+    };
+
+    let mut client = ProxyClient::new(serial);
+
+    if let Some(switch_baudrate) = switch_baudrate {
+        if let Err(error) = client.set_baudrate(switch_baudrate) {
+            eprintln!(
+                "Cannot switch to {} baud, staying at {} baud: {}",
+                switch_baudrate, baudrate, error
+            );
+        }
+    }
+
+    let result = run_command(&mut client, &command, &command_args);
+
+    flush_console(&mut client);
+
+    if let Err(error) = result {
+        eprintln!("{}", error);
+        process::exit(1);
+    }
+}
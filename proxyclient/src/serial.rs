@@ -0,0 +1,273 @@
+//! Raw serial port access over termios
+
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::io::{Read, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::AsRawFd;
+use std::time::Duration;
+
+use embedded_hal::serial;
+use m1n1_protocol::ProxyTransport;
+
+use crate::client::BaudRate;
+
+pub struct SerialPort {
+    file: File,
+    write_buffer: Vec<u8>,
+    baud_rate: Option<u32>,
+    timeout: Duration,
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn get_speed(baud_rate: u32) -> io::Result<libc::speed_t> {
+    let speed = match baud_rate {
+        9600 => libc::B9600,
+        19200 => libc::B19200,
+        38400 => libc::B38400,
+        57600 => libc::B57600,
+        115200 => libc::B115200,
+        230400 => libc::B230400,
+        460800 => libc::B460800,
+        500000 => libc::B500000,
+        576000 => libc::B576000,
+        921600 => libc::B921600,
+        1000000 => libc::B1000000,
+        1152000 => libc::B1152000,
+        1500000 => libc::B1500000,
+        2000000 => libc::B2000000,
+        2500000 => libc::B2500000,
+        3000000 => libc::B3000000,
+        3500000 => libc::B3500000,
+        4000000 => libc::B4000000,
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Unsupported baud rate {}", baud_rate),
+            ))
+        }
+    };
+
+    Ok(speed)
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn get_speed(baud_rate: u32) -> io::Result<libc::speed_t> {
+    // BSD derivatives use the actual rate as speed value.
+    Ok(baud_rate as libc::speed_t)
+}
+
+fn check(result: libc::c_int) -> io::Result<()> {
+    if result < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+impl SerialPort {
+    /// Open a serial device and configure it in raw mode.
+    pub fn open(path: &str, baud_rate: u32) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(libc::O_NOCTTY)
+            .open(path)?;
+
+        let mut port = SerialPort::from_file(file)?;
+        port.set_baudrate(baud_rate)?;
+
+        Ok(port)
+    }
+
+    /// Wrap an already opened file (like a PTY) and configure it in raw mode.
+    pub fn from_file(file: File) -> io::Result<Self> {
+        let mut port = SerialPort {
+            file,
+            write_buffer: Vec::new(),
+            baud_rate: None,
+            timeout: Duration::default(),
+        };
+
+        port.update_termios(|termios| unsafe { libc::cfmakeraw(termios) })?;
+        port.set_timeout(Duration::from_secs(1))?;
+
+        Ok(port)
+    }
+
+    /// Create a pseudo terminal pair, returning the master and slave sides.
+    #[cfg(test)]
+    pub fn open_pty() -> io::Result<(Self, Self)> {
+        use std::os::unix::io::FromRawFd;
+
+        let mut master = 0;
+        let mut slave = 0;
+
+        check(unsafe {
+            libc::openpty(
+                &mut master,
+                &mut slave,
+                std::ptr::null_mut(),
+                std::ptr::null_mut(),
+                std::ptr::null_mut(),
+            )
+        })?;
+
+        let (master, slave) = unsafe { (File::from_raw_fd(master), File::from_raw_fd(slave)) };
+
+        Ok((
+            SerialPort::from_file(master)?,
+            SerialPort::from_file(slave)?,
+        ))
+    }
+
+    /// Open another handle on the same port, sharing its configuration.
+    #[cfg(test)]
+    pub fn try_clone(&self) -> io::Result<Self> {
+        Ok(SerialPort {
+            file: self.file.try_clone()?,
+            write_buffer: Vec::new(),
+            baud_rate: self.baud_rate,
+            timeout: self.timeout,
+        })
+    }
+
+    fn update_termios<F: FnOnce(&mut libc::termios)>(&self, update: F) -> io::Result<()> {
+        let fd = self.file.as_raw_fd();
+        let mut termios = unsafe { std::mem::zeroed::<libc::termios>() };
+
+        check(unsafe { libc::tcgetattr(fd, &mut termios) })?;
+        update(&mut termios);
+        check(unsafe { libc::tcsetattr(fd, libc::TCSANOW, &termios) })
+    }
+
+    pub fn set_baudrate(&mut self, baud_rate: u32) -> io::Result<()> {
+        let speed = get_speed(baud_rate)?;
+
+        self.drain()?;
+
+        let mut result = 0;
+        self.update_termios(|termios| result = unsafe { libc::cfsetspeed(termios, speed) })?;
+
+        check(result)?;
+        self.baud_rate = Some(baud_rate);
+
+        Ok(())
+    }
+
+    /// Set the maximum time a read can wait for a byte, with a 100ms granularity.
+    pub fn set_timeout(&mut self, timeout: Duration) -> io::Result<()> {
+        let deciseconds = (timeout.as_millis() / 100).clamp(1, 255);
+
+        self.update_termios(|termios| {
+            termios.c_cc[libc::VMIN] = 0;
+            termios.c_cc[libc::VTIME] = deciseconds as libc::cc_t;
+        })?;
+        self.timeout = timeout;
+
+        Ok(())
+    }
+
+    /// Flush pending writes and wait for them to be transmitted.
+    pub fn drain(&mut self) -> io::Result<()> {
+        self.file.write_all(&self.write_buffer)?;
+        self.write_buffer.clear();
+
+        check(unsafe { libc::tcdrain(self.file.as_raw_fd()) })
+    }
+
+    /// Read a byte after sending pending writes, returning None if the read timed out.
+    fn receive_byte(&mut self) -> io::Result<Option<u8>> {
+        if !self.write_buffer.is_empty() {
+            self.file.write_all(&self.write_buffer)?;
+            self.write_buffer.clear();
+        }
+
+        let mut value = [0x0u8; 1];
+
+        match self.file.read(&mut value)? {
+            0 => Ok(None),
+            _ => Ok(Some(value[0])),
+        }
+    }
+}
+
+impl BaudRate for SerialPort {
+    fn set_baudrate(&mut self, baud_rate: u32) -> io::Result<()> {
+        SerialPort::set_baudrate(self, baud_rate)
+    }
+}
+
+impl serial::Read<u8> for SerialPort {
+    type Error = io::Error;
+
+    fn read(&mut self) -> nb::Result<u8, Self::Error> {
+        match self.receive_byte()? {
+            Some(value) => Ok(value),
+            None => Err(nb::Error::Other(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "Timed out while reading serial",
+            ))),
+        }
+    }
+}
+
+impl serial::Write<u8> for SerialPort {
+    type Error = io::Error;
+
+    fn write(&mut self, word: u8) -> nb::Result<(), Self::Error> {
+        self.write_buffer.push(word);
+
+        if self.write_buffer.len() >= 0x1000 {
+            self.file.write_all(&self.write_buffer)?;
+            self.write_buffer.clear();
+        }
+
+        Ok(())
+    }
+
+    fn flush(&mut self) -> nb::Result<(), Self::Error> {
+        self.file.write_all(&self.write_buffer)?;
+        self.write_buffer.clear();
+
+        Ok(())
+    }
+}
+
+/// Serves the proxy over a serial port, used to simulate a target.
+impl ProxyTransport for SerialPort {
+    type Error = io::Error;
+
+    fn read_byte(&mut self, timeout: u64) -> io::Result<Option<u8>> {
+        let timeout = Duration::from_micros(timeout);
+
+        if timeout != self.timeout {
+            self.set_timeout(timeout)?;
+        }
+
+        self.receive_byte()
+    }
+
+    fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        self.write_buffer.extend_from_slice(data);
+
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.write_all(&self.write_buffer)?;
+        self.write_buffer.clear();
+
+        Ok(())
+    }
+
+    fn get_baudrate(&self) -> Option<u32> {
+        self.baud_rate
+    }
+
+    fn set_baudrate(&mut self, baud_rate: u32) -> Option<u32> {
+        SerialPort::set_baudrate(self, baud_rate).ok()?;
+
+        Some(baud_rate)
+    }
+}
@@ -0,0 +1,143 @@
+//! Simulation of m1saka over a serial port, used to test the client.
+//!
+//! Requests are served by the same `m1n1_protocol::run_proxy` as on the target, only the memory
+//! and the proxy opcodes are simulated.
+
+use std::convert::TryFrom;
+use std::io;
+use std::ops::Range;
+
+use m1n1_protocol::{
+    Error, ProxyHandler, ProxyOpcode, ProxyReply, ProxyRequest, ProxyStatus, ProxyTransport,
+};
+
+use crate::serial::SerialPort;
+
+/// Baud rate the console starts at.
+const INITIAL_BAUDRATE: u32 = 115_200;
+
+const BANNER: &[u8] = b"Hello I'm m1saka say m1saka\r\n";
+
+pub struct Simulator {
+    serial: SerialPort,
+    base: u64,
+    memory: Vec<u8>,
+}
+
+impl Simulator {
+    /// Create a simulator exposing `size` bytes of memory at `base`.
+    pub fn new(serial: SerialPort, base: u64, size: usize) -> Self {
+        Simulator {
+            serial,
+            base,
+            memory: vec![0; size],
+        }
+    }
+
+    fn get_range(&self, address: u64, size: u64) -> Option<Range<usize>> {
+        let start = address.checked_sub(self.base)? as usize;
+        let end = start.checked_add(size as usize)?;
+
+        if end > self.memory.len() {
+            return None;
+        }
+
+        Some(start..end)
+    }
+
+    fn read(&self, address: u64, size: u64) -> Option<u64> {
+        let range = self.get_range(address, size)?;
+        let mut value = [0x0u8; 8];
+
+        value[..size as usize].copy_from_slice(&self.memory[range]);
+
+        Some(u64::from_le_bytes(value))
+    }
+
+    fn write(&mut self, address: u64, size: u64, value: u64) -> Option<u64> {
+        let range = self.get_range(address, size)?;
+
+        self.memory[range].copy_from_slice(&u64::to_le_bytes(value)[..size as usize]);
+
+        Some(0)
+    }
+
+    /// Simulate a call returning its first argument.
+    fn call(&mut self, request: &ProxyRequest) -> Option<u64> {
+        self.read(request.args[0], 4)?;
+
+        Some(request.args[1])
+    }
+
+    /// Start like m1saka does and serve the proxy until the other side of the serial port is closed.
+    pub fn run(&mut self) -> Result<(), Error<io::Error>> {
+        let mut serial = self.serial.try_clone().map_err(Error::Serial)?;
+
+        serial
+            .set_baudrate(INITIAL_BAUDRATE)
+            .map_err(Error::Serial)?;
+        ProxyTransport::write(&mut serial, BANNER).map_err(Error::Serial)?;
+
+        m1n1_protocol::run_proxy(&mut serial, self)
+    }
+}
+
+impl ProxyHandler for Simulator {
+    fn get_memory(&mut self, address: u64, size: u64) -> Option<&mut [u8]> {
+        let range = self.get_range(address, size)?;
+
+        Some(&mut self.memory[range])
+    }
+
+    fn handle_proxy(&mut self, request: &ProxyRequest) -> ProxyReply {
+        let args = &request.args;
+
+        let result = match ProxyOpcode::try_from(request.opcode) {
+            Ok(ProxyOpcode::Nop) | Ok(ProxyOpcode::Exit) => Some(0),
+            Ok(ProxyOpcode::GetBase) => Some(self.base),
+            // The boot arguments are at the start of the memory, as iBoot puts them before us.
+            Ok(ProxyOpcode::GetBootArgs) => Some(self.base),
+            Ok(ProxyOpcode::Call) => self.call(request),
+            Ok(ProxyOpcode::Read64) => self.read(args[0], 8),
+            Ok(ProxyOpcode::Read32) => self.read(args[0], 4),
+            Ok(ProxyOpcode::Read16) => self.read(args[0], 2),
+            Ok(ProxyOpcode::Read8) => self.read(args[0], 1),
+            Ok(ProxyOpcode::Write64) => self.write(args[0], 8, args[1]),
+            Ok(ProxyOpcode::Write32) => self.write(args[0], 4, args[1]),
+            Ok(ProxyOpcode::Write16) => self.write(args[0], 2, args[1]),
+            Ok(ProxyOpcode::Write8) => self.write(args[0], 1, args[1]),
+            _ => {
+                return ProxyReply {
+                    opcode: request.opcode,
+                    status: ProxyStatus::BadCommand,
+                    return_value: 0,
+                }
+            }
+        };
+
+        match result {
+            Some(return_value) => ProxyReply {
+                opcode: request.opcode,
+                status: ProxyStatus::Ok,
+                return_value,
+            },
+            // Invalid accesses would fault on real hardware.
+            None => ProxyReply {
+                opcode: request.opcode,
+                status: ProxyStatus::Exception,
+                return_value: 0,
+            },
+        }
+    }
+
+    /// Only images starting with a branch can be booted, they are assumed to be m1saka again.
+    fn boot(&mut self, entry: u64) {
+        match self.read(entry, 4) {
+            Some(instruction) if instruction as u32 & 0xFC00_0000 == 0x1400_0000 => {
+                // Only returns once the port is closed, the reply sent afterward is lost.
+                let _ = self.run();
+            }
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,121 @@
+//! Handing control to another image
+
+use core::convert::Infallible;
+use core::mem::size_of;
+use core::ops::Range;
+
+use elf::{ElfError, ElfFile};
+use loader::{Image, PlacementError};
+use log::info;
+use macho::{MachError, MachFile};
+
+use crate::boot_args::{self, BootArgs};
+use crate::mmu;
+use crate::rt;
+
+#[derive(Debug)]
+pub enum BootError<E> {
+    /// The image is malformed or cannot be loaded.
+    Image(E),
+    /// The image would overwrite its file, the payload, the boot arguments or the device tree.
+    Placement(PlacementError),
+}
+
+impl<E> From<PlacementError> for BootError<E> {
+    fn from(error: PlacementError) -> Self {
+        BootError::Placement(error)
+    }
+}
+
+/// Jump to `entry` with the MMU and caches off, passing the iBoot boot arguments in x0.
+pub unsafe fn boot(entry: u64) -> ! {
+    let boot_args_address = boot_args::get_address();
+
+    info!(
+        "Booting 0x{:x} with boot arguments at 0x{:x}",
+        entry, boot_args_address
+    );
+
+    jump(entry, boot_args_address)
+}
+
+/// Jump to `entry` with the MMU and caches off, passing `argument` in x0 and zeroes in x1 to x3.
+///
+/// The destructors of `.fini_array` run first, as when leaving the proxy.
+pub unsafe fn jump(entry: u64, argument: u64) -> ! {
+    rt::run_fini_array();
+    mmu::shutdown();
+
+    asm!(
+        "br {entry}",
+        entry = in(reg) entry,
+        in("x0") argument,
+        in("x1") 0u64,
+        in("x2") 0u64,
+        in("x3") 0u64,
+        options(noreturn)
+    )
+}
+
+/// Memory an image must not be loaded over: the payload, then the boot arguments and the Apple
+/// device tree handed to the image in x0.
+fn get_reserved_ranges() -> Result<[Range<u64>; 3], PlacementError> {
+    let (boot_args, device_tree) = match boot_args::get() {
+        Some(boot_args) => (
+            loader::get_range(boot_args::get_address(), size_of::<BootArgs>() as u64)?,
+            loader::get_range(
+                boot_args.get_device_tree_address(),
+                u64::from(boot_args.device_tree_size),
+            )?,
+        ),
+        None => (0..0, 0..0),
+    };
+
+    Ok([rt::get_image_range(), boot_args, device_tree])
+}
+
+/// Load the image uploaded at `address` where its format places it and boot it, only returning
+/// on failure. The load range must overlap neither the file nor the reserved ranges.
+unsafe fn boot_image<I: Image<'static>>(
+    name: &str,
+    address: u64,
+) -> Result<Infallible, BootError<I::Error>> {
+    let header = core::slice::from_raw_parts(address as *const u8, I::HEADER_SIZE);
+    let headers_size = I::get_headers_size(header).map_err(BootError::Image)?;
+    let headers = core::slice::from_raw_parts(address as *const u8, headers_size);
+    let file_size = I::parse(headers).map_err(BootError::Image)?.get_file_size();
+    let file_range = loader::get_range(address, file_size)?;
+
+    let file = I::parse(core::slice::from_raw_parts(
+        address as *const u8,
+        file_size as usize,
+    ))
+    .map_err(BootError::Image)?;
+    let range = file.get_load_range().map_err(BootError::Image)?;
+    let size = range.end - range.start;
+    let load_address = file.get_load_address(&file_range, &range)?;
+    let load_range = loader::place(load_address, size, &file_range, &get_reserved_ranges()?)?;
+
+    let memory = core::slice::from_raw_parts_mut(load_address as *mut u8, size as usize);
+    let entry = file.load(memory, load_address).map_err(BootError::Image)?;
+
+    info!(
+        "Loaded {} image at 0x{:x}-0x{:x}",
+        name, load_range.start, load_range.end
+    );
+
+    boot(entry)
+}
+
+/// Load the ELF image uploaded at `address` and boot it, only returning on failure.
+///
+/// Position independent images are loaded after their file, others at their own addresses.
+pub unsafe fn boot_elf(address: u64) -> Result<Infallible, BootError<ElfError>> {
+    boot_image::<ElfFile>("ELF", address)
+}
+
+/// Load the Mach-O image uploaded at `address` slid right after its file and boot it, only
+/// returning on failure.
+pub unsafe fn boot_macho(address: u64) -> Result<Infallible, BootError<MachError>> {
+    boot_image::<MachFile>("Mach-O", address)
+}
@@ -0,0 +1,100 @@
+//! iBoot boot arguments
+//!
+//! Layout from m1n1's xnuboot.h (Copyright (c) 2021 The Asahi Linux contributors).
+
+use adt::Adt;
+use static_assertions::assert_eq_size;
+
+use crate::rt;
+
+pub const COMMAND_LINE_SIZE: usize = 608;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct BootVideo {
+    pub base: u64,
+    pub display: u64,
+    pub stride: u64,
+    pub width: u64,
+    pub height: u64,
+    pub depth: u64,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct BootArgs {
+    pub revision: u16,
+    pub version: u16,
+    /// Virtual address the kernel would be mapped at, pointers below are relative to it.
+    pub virt_base: u64,
+    pub phys_base: u64,
+    pub mem_size: u64,
+    pub top_of_kernel_data: u64,
+    pub video: BootVideo,
+    pub machine_type: u32,
+    /// Virtual address of the Apple device tree.
+    pub device_tree: u64,
+    pub device_tree_size: u32,
+    pub command_line: [u8; COMMAND_LINE_SIZE],
+    pub boot_flags: u64,
+    pub mem_size_actual: u64,
+}
+
+assert_eq_size!(BootArgs, [u8; 0x2E0]);
+
+impl BootArgs {
+    /// Command line up to its terminator, empty if it isn't valid UTF-8.
+    pub fn get_command_line(&self) -> &str {
+        let size = self
+            .command_line
+            .iter()
+            .position(|value| *value == 0)
+            .unwrap_or(COMMAND_LINE_SIZE);
+
+        core::str::from_utf8(&self.command_line[..size]).unwrap_or("")
+    }
+
+    /// Translate a virtual address from the boot arguments to a physical one.
+    pub fn to_physical_address(&self, virtual_address: u64) -> u64 {
+        virtual_address
+            .wrapping_sub(self.virt_base)
+            .wrapping_add(self.phys_base)
+    }
+
+    /// Physical address of the Apple device tree.
+    pub fn get_device_tree_address(&self) -> u64 {
+        self.to_physical_address(self.device_tree)
+    }
+
+    /// Apple device tree, None if it's missing or malformed.
+    pub fn get_device_tree(&self) -> Option<Adt<'static>> {
+        if self.device_tree == 0 {
+            return None;
+        }
+
+        let data = unsafe {
+            core::slice::from_raw_parts(
+                self.get_device_tree_address() as *const u8,
+                self.device_tree_size as usize,
+            )
+        };
+
+        Adt::new(data)
+    }
+}
+
+/// Address of the boot arguments, as passed in x0 at entry.
+pub fn get_address() -> u64 {
+    rt::get_entry_registers()[0]
+}
+
+/// Boot arguments iBoot passed, None if we were started without any.
+pub fn get() -> Option<&'static BootArgs> {
+    let address = get_address();
+
+    if address == 0 {
+        return None;
+    }
+
+    Some(unsafe { &*(address as *const BootArgs) })
+}
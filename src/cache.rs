@@ -0,0 +1,180 @@
+//! Cache maintenance operations
+
+use cortex_a::barrier::*;
+
+fn get_ctr() -> u64 {
+    let ctr: u64;
+    unsafe {
+        asm!("mrs {ctr}, ctr_el0", ctr = out(reg) ctr, options(nostack));
+    }
+
+    ctr
+}
+
+pub fn get_dcache_line_size() -> u64 {
+    4 << ((get_ctr() >> 16) & 0xF)
+}
+
+pub fn get_icache_line_size() -> u64 {
+    4 << (get_ctr() & 0xF)
+}
+
+pub fn get_zva_block_size() -> u64 {
+    let dczid: u64;
+    unsafe {
+        asm!("mrs {dczid}, dczid_el0", dczid = out(reg) dczid, options(nostack));
+    }
+
+    4 << (dczid & 0xF)
+}
+
+/// Apply an operation on every line of `line_size` covering `[address, address + size)`.
+unsafe fn for_each_line<F: Fn(u64)>(address: u64, size: u64, line_size: u64, operation: F) {
+    let mut current_address = address & !(line_size - 1);
+    let end_address = address + size;
+
+    while current_address < end_address {
+        operation(current_address);
+
+        current_address += line_size;
+    }
+
+    dsb(SY);
+    isb(SY);
+}
+
+pub fn ic_iallu() {
+    unsafe {
+        asm!("ic iallu", options(nostack));
+    }
+
+    dsb(SY);
+    isb(SY);
+}
+
+pub fn ic_ialluis() {
+    unsafe {
+        asm!("ic ialluis", options(nostack));
+    }
+
+    dsb(SY);
+    isb(SY);
+}
+
+pub unsafe fn ic_ivau_range(address: u64, size: u64) {
+    for_each_line(
+        address,
+        size,
+        get_icache_line_size(),
+        |line| asm!("ic ivau, {line}", line = in(reg) line, options(nostack)),
+    );
+}
+
+pub unsafe fn dc_ivac_range(address: u64, size: u64) {
+    for_each_line(
+        address,
+        size,
+        get_dcache_line_size(),
+        |line| asm!("dc ivac, {line}", line = in(reg) line, options(nostack)),
+    );
+}
+
+pub unsafe fn dc_cvac_range(address: u64, size: u64) {
+    for_each_line(
+        address,
+        size,
+        get_dcache_line_size(),
+        |line| asm!("dc cvac, {line}", line = in(reg) line, options(nostack)),
+    );
+}
+
+pub unsafe fn dc_cvau_range(address: u64, size: u64) {
+    for_each_line(
+        address,
+        size,
+        get_dcache_line_size(),
+        |line| asm!("dc cvau, {line}", line = in(reg) line, options(nostack)),
+    );
+}
+
+pub unsafe fn dc_civac_range(address: u64, size: u64) {
+    for_each_line(
+        address,
+        size,
+        get_dcache_line_size(),
+        |line| asm!("dc civac, {line}", line = in(reg) line, options(nostack)),
+    );
+}
+
+pub unsafe fn dc_zva_range(address: u64, size: u64) {
+    for_each_line(
+        address,
+        size,
+        get_zva_block_size(),
+        |block| asm!("dc zva, {block}", block = in(reg) block, options(nostack)),
+    );
+}
+
+pub unsafe fn dc_isw(set_way: u64) {
+    asm!("dc isw, {sw}", sw = in(reg) set_way, options(nostack));
+}
+
+pub unsafe fn dc_csw(set_way: u64) {
+    asm!("dc csw, {sw}", sw = in(reg) set_way, options(nostack));
+}
+
+pub unsafe fn dc_cisw(set_way: u64) {
+    asm!("dc cisw, {sw}", sw = in(reg) set_way, options(nostack));
+}
+
+/// Apply a set/way operation on every data or unified cache level up to the point of coherency.
+unsafe fn for_each_set_way<F: Fn(u64)>(operation: F) {
+    let clidr: u64;
+    asm!("mrs {clidr}, clidr_el1", clidr = out(reg) clidr, options(nostack));
+
+    let level_of_coherency = (clidr >> 24) & 0x7;
+
+    for level in 0..level_of_coherency {
+        let cache_type = (clidr >> (level * 3)) & 0x7;
+
+        // Skip levels without data or unified caches.
+        if cache_type < 2 {
+            continue;
+        }
+
+        let ccsidr: u64;
+        asm!("msr csselr_el1, {csselr}", csselr = in(reg) level << 1, options(nostack));
+        isb(SY);
+        asm!("mrs {ccsidr}, ccsidr_el1", ccsidr = out(reg) ccsidr, options(nostack));
+
+        let line_shift = (ccsidr & 0x7) + 4;
+        let ways = ((ccsidr >> 3) & 0x3FF) + 1;
+        let sets = ((ccsidr >> 13) & 0x7FFF) + 1;
+        let way_shift = if ways > 1 {
+            (ways as u32 - 1).leading_zeros()
+        } else {
+            0
+        };
+
+        for way in 0..ways {
+            for set in 0..sets {
+                operation((way << way_shift) | (set << line_shift) | (level << 1));
+            }
+        }
+    }
+
+    dsb(SY);
+    isb(SY);
+}
+
+pub unsafe fn dcache_invalidate_all() {
+    for_each_set_way(|set_way| dc_isw(set_way));
+}
+
+pub unsafe fn dcache_clean_all() {
+    for_each_set_way(|set_way| dc_csw(set_way));
+}
+
+pub unsafe fn dcache_clean_invalidate_all() {
+    for_each_set_way(|set_way| dc_cisw(set_way));
+}
@@ -1,4 +1,6 @@
+use core::convert::TryFrom;
 use core::fmt::Write;
+use core::sync::atomic::{AtomicU64, Ordering};
 
 use crate::m1::uart::UART;
 
@@ -218,6 +220,130 @@ unsafe extern "C" fn _current_elx_sync() -> ! {
     )
 }
 
+global_asm!(
+    "
+    .section .text.guarded_call, \"ax\"
+    .align  2
+    .global _guarded_call
+    .global _guarded_call_restore
+
+    /* x0: function, x1: arguments, x2: context */
+    _guarded_call:
+        stp     x19, x20, [x2, #0]
+        stp     x21, x22, [x2, #16]
+        stp     x23, x24, [x2, #32]
+        stp     x25, x26, [x2, #48]
+        stp     x27, x28, [x2, #64]
+        stp     x29, x30, [x2, #80]
+        mov     x9, sp
+        str     x9, [x2, #96]
+
+        /* x19 is callee saved, use it to keep the context around */
+        mov     x19, x2
+        mov     x9, x0
+        mov     x10, x1
+        ldp     x0, x1, [x10, #0]
+        ldp     x2, x3, [x10, #16]
+        ldr     x4, [x10, #32]
+        blr     x9
+        mov     x2, x19
+
+    /* x0: return value, x2: context */
+    _guarded_call_restore:
+        ldp     x19, x20, [x2, #0]
+        ldp     x21, x22, [x2, #16]
+        ldp     x23, x24, [x2, #32]
+        ldp     x25, x26, [x2, #48]
+        ldp     x27, x28, [x2, #64]
+        ldp     x29, x30, [x2, #80]
+        ldr     x9, [x2, #96]
+        mov     sp, x9
+        ret
+    "
+);
+
+/// Callee saved registers (x19 to x30) and stack pointer saved by a guarded call.
+#[repr(C)]
+struct GuardedCallContext {
+    registers: [u64; 13],
+}
+
+static mut GUARDED_CALL_CONTEXT: GuardedCallContext = GuardedCallContext { registers: [0; 13] };
+static mut GUARDED_CALL_ACTIVE: bool = false;
+static mut GUARDED_CALL_FAULT: Option<u64> = None;
+
+extern "C" {
+    fn _guarded_call(function: u64, arguments: *const u64, context: *mut GuardedCallContext)
+        -> u64;
+    fn _guarded_call_restore();
+}
+
+/// Call the function at the given address with up to five arguments.
+///
+/// If a synchronous exception happens during the call, execution resumes here and the ESR is returned as an error.
+pub unsafe fn guarded_call(function: u64, arguments: &[u64; 5]) -> Result<u64, u64> {
+    GUARDED_CALL_FAULT = None;
+    GUARDED_CALL_ACTIVE = true;
+
+    let result = _guarded_call(
+        function,
+        arguments.as_ptr(),
+        &mut GUARDED_CALL_CONTEXT as *mut _,
+    );
+
+    GUARDED_CALL_ACTIVE = false;
+
+    match GUARDED_CALL_FAULT.take() {
+        Some(esr) => Err(esr),
+        None => Ok(result),
+    }
+}
+
+/// Exception guard modes, matching m1n1's `exc_guard_t`.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[repr(u64)]
+pub enum ExceptionGuardMode {
+    /// Exceptions are fatal.
+    Off,
+    /// Skip the faulting instruction.
+    Skip,
+    /// Skip the faulting instruction and mark its destination register with `GUARD_MARKER`.
+    Mark,
+    /// Return from the current function with `GUARD_MARKER` in x0 and disable the guard.
+    Return,
+}
+
+impl TryFrom<u64> for ExceptionGuardMode {
+    type Error = &'static str;
+
+    fn try_from(value: u64) -> Result<Self, Self::Error> {
+        match value & GUARD_TYPE_MASK {
+            0 => Ok(ExceptionGuardMode::Off),
+            1 => Ok(ExceptionGuardMode::Skip),
+            2 => Ok(ExceptionGuardMode::Mark),
+            3 => Ok(ExceptionGuardMode::Return),
+            _ => Err("Unknown exception guard mode"),
+        }
+    }
+}
+
+pub const GUARD_TYPE_MASK: u64 = 0xFF;
+pub const GUARD_SILENT: u64 = 0x100;
+pub const GUARD_MARKER: u64 = 0xACCE_5515_ABAD_1DEA;
+
+static EXCEPTION_GUARD: AtomicU64 = AtomicU64::new(0);
+static EXCEPTION_COUNT: AtomicU64 = AtomicU64::new(0);
+
+/// Set the exception guard configuration, returning the previous one.
+pub fn set_exception_guard(value: u64) -> u64 {
+    EXCEPTION_GUARD.swap(value, Ordering::SeqCst)
+}
+
+/// Get the count of exceptions recovered by the guard since the last call.
+pub fn get_exception_count() -> u64 {
+    EXCEPTION_COUNT.swap(0, Ordering::SeqCst)
+}
+
 #[repr(C)]
 struct ExceptionInfo {
     far_duplicate: u64,
@@ -229,7 +355,7 @@ struct ExceptionInfo {
 }
 
 unsafe fn dump_exception(exception: &mut ExceptionInfo) {
-    let mut uart = UART::INSTANCE;
+    let mut uart = UART::get_console();
 
     writeln!(&mut uart, "Fault address:\t{:20x}\r", exception.far).ok();
     writeln!(&mut uart, "Register dump:\r").ok();
@@ -248,7 +374,7 @@ unsafe fn dump_exception(exception: &mut ExceptionInfo) {
 
 #[no_mangle]
 unsafe extern "C" fn unhandled_vector(exception: &mut ExceptionInfo) {
-    let mut uart = UART::INSTANCE;
+    let mut uart = UART::get_console();
     writeln!(&mut uart, "\r").ok();
     writeln!(
         &mut uart,
@@ -316,9 +442,79 @@ pub fn get_instruction_fault_name(esr: u64) -> &'static str {
     }
 }
 
+/// Try to recover from a data abort using the exception guard, returning true if execution can be resumed.
+unsafe fn apply_exception_guard(exception: &mut ExceptionInfo) -> bool {
+    // Only data aborts are recoverable.
+    if (exception.esr >> 26) != 0x25 {
+        return false;
+    }
+
+    let guard = EXCEPTION_GUARD.load(Ordering::SeqCst);
+    let fault_pc = exception.pc;
+
+    match ExceptionGuardMode::try_from(guard) {
+        Ok(ExceptionGuardMode::Skip) => {
+            exception.pc += 4;
+        }
+        Ok(ExceptionGuardMode::Mark) => {
+            // Assume a load or store, the destination register is in the low bits.
+            let instruction = core::ptr::read_volatile(exception.pc as *const u32);
+            let register = (instruction & 0x1F) as usize;
+
+            if register < exception.x.len() {
+                exception.x[register] = GUARD_MARKER;
+            }
+
+            exception.pc += 4;
+        }
+        Ok(ExceptionGuardMode::Return) => {
+            exception.x[0] = GUARD_MARKER;
+            exception.pc = exception.x[30];
+
+            EXCEPTION_GUARD.store(ExceptionGuardMode::Off as u64, Ordering::SeqCst);
+        }
+        _ => return false,
+    }
+
+    EXCEPTION_COUNT.fetch_add(1, Ordering::SeqCst);
+
+    if guard & GUARD_SILENT == 0 {
+        let mut uart = UART::get_console();
+
+        writeln!(
+            &mut uart,
+            "Recovering from exception (FAR: {:x}, ESR: {:x}, PC: {:x})\r",
+            exception.far, exception.esr, fault_pc
+        )
+        .ok();
+    }
+
+    true
+}
+
+/// Abort the guarded call in progress if any, returning true if execution can be resumed.
+unsafe fn abort_guarded_call(exception: &mut ExceptionInfo) -> bool {
+    if !GUARDED_CALL_ACTIVE {
+        return false;
+    }
+
+    GUARDED_CALL_ACTIVE = false;
+    GUARDED_CALL_FAULT = Some(exception.esr);
+
+    exception.pc = _guarded_call_restore as usize as u64;
+    exception.x[0] = 0;
+    exception.x[2] = &mut GUARDED_CALL_CONTEXT as *mut _ as u64;
+
+    true
+}
+
 #[no_mangle]
 unsafe extern "C" fn current_elx_sync(exception: &mut ExceptionInfo) {
-    let mut uart = UART::INSTANCE;
+    if apply_exception_guard(exception) {
+        return;
+    }
+
+    let mut uart = UART::get_console();
     writeln!(&mut uart, "\r").ok();
     writeln!(
         &mut uart,
@@ -328,5 +524,12 @@ unsafe extern "C" fn current_elx_sync(exception: &mut ExceptionInfo) {
     .ok();
     dump_exception(exception);
 
+    if abort_guarded_call(exception) {
+        writeln!(&mut uart, "\r").ok();
+        writeln!(&mut uart, "Aborting guarded call\r").ok();
+
+        return;
+    }
+
     loop {}
 }
@@ -0,0 +1,136 @@
+//! Linux kernel boot, following m1n1's kboot
+
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use kboot::{BootInfo, Framebuffer, KbootError, MemoryRange};
+use log::info;
+
+use crate::boot;
+use crate::boot_args;
+
+/// Longest string accepted for a `/chosen` property.
+const MAX_STRING_SIZE: usize = 0x1000;
+
+static mut CHOSEN: Vec<(String, String)> = Vec::new();
+static mut INITRD: Option<MemoryRange> = None;
+/// Prepared device tree, kept in 64 bits words as the kernel wants it aligned on 8 bytes.
+static mut DEVICE_TREE: Option<Vec<u64>> = None;
+/// Kernel entry and device tree address of the boot requested by the host.
+static mut PENDING_BOOT: Option<(u64, u64)> = None;
+
+unsafe fn read_str(address: u64) -> kboot::Result<String> {
+    let data = core::slice::from_raw_parts(address as *const u8, MAX_STRING_SIZE);
+    let size = data
+        .iter()
+        .position(|value| *value == 0)
+        .ok_or(KbootError::InvalidString)?;
+
+    core::str::from_utf8(&data[..size])
+        .map(String::from)
+        .map_err(|_| KbootError::InvalidString)
+}
+
+/// Set a string property of `/chosen` from two zero terminated strings in memory.
+pub unsafe fn set_chosen(name_address: u64, value_address: u64) -> kboot::Result<()> {
+    let name = read_str(name_address)?;
+    let value = read_str(value_address)?;
+
+    info!("Setting /chosen/{} to \"{}\"", name, value);
+
+    CHOSEN.retain(|(chosen_name, _)| *chosen_name != name);
+    CHOSEN.push((name, value));
+
+    Ok(())
+}
+
+pub fn set_initrd(address: u64, size: u64) {
+    info!("Initrd at 0x{:x} ({} bytes)", address, size);
+
+    unsafe { INITRD = Some(MemoryRange { address, size }) };
+}
+
+/// Fill the device tree template at `template_address` for the next boot.
+pub unsafe fn prepare_dt(template_address: u64) -> kboot::Result<()> {
+    let header = core::slice::from_raw_parts(template_address as *const u8, 8);
+    let template = core::slice::from_raw_parts(
+        template_address as *const u8,
+        kboot::fdt::get_total_size(header)?,
+    );
+
+    let boot_args = boot_args::get().ok_or(KbootError::MissingBootArguments)?;
+    let video = &boot_args.video;
+
+    let framebuffer = if video.base != 0 {
+        Some(Framebuffer {
+            address: video.base,
+            width: video.width as u32,
+            height: video.height as u32,
+            stride: video.stride as u32,
+            depth: video.depth as u32,
+        })
+    } else {
+        None
+    };
+
+    let data = kboot::prepare_dt(
+        template,
+        &BootInfo {
+            memory: MemoryRange {
+                address: boot_args.phys_base,
+                size: boot_args.mem_size,
+            },
+            framebuffer,
+            initrd: INITRD,
+            chosen: &CHOSEN,
+            adt: boot_args.get_device_tree(),
+        },
+    )?;
+
+    let mut device_tree = Vec::with_capacity((data.len() + 7) / 8);
+
+    for chunk in data.chunks(8) {
+        let mut word = [0x0u8; 8];
+
+        word[..chunk.len()].copy_from_slice(chunk);
+        device_tree.push(u64::from_ne_bytes(word));
+    }
+
+    info!(
+        "Device tree prepared at 0x{:x} ({} bytes)",
+        device_tree.as_ptr() as u64,
+        data.len()
+    );
+
+    DEVICE_TREE = Some(device_tree);
+
+    Ok(())
+}
+
+/// Check the kernel Image at `entry` and schedule its boot with the prepared device tree.
+///
+/// Like m1n1, the kernel is only started by `boot_pending` once the host got the reply.
+pub unsafe fn boot(entry: u64) -> kboot::Result<()> {
+    let device_tree = DEVICE_TREE.as_ref().ok_or(KbootError::DeviceTreeNotReady)?;
+    let header = core::slice::from_raw_parts(entry as *const u8, 0x40);
+
+    if !kboot::is_valid_image(header) {
+        return Err(KbootError::InvalidImage);
+    }
+
+    PENDING_BOOT = Some((entry, device_tree.as_ptr() as u64));
+
+    Ok(())
+}
+
+/// Start the kernel scheduled by `boot`, if any.
+pub unsafe fn boot_pending() {
+    if let Some((entry, device_tree_address)) = PENDING_BOOT.take() {
+        info!(
+            "Booting kernel at 0x{:x} with device tree at 0x{:x}",
+            entry, device_tree_address
+        );
+
+        boot::jump(entry, device_tree_address)
+    }
+}
@@ -1,4 +1,4 @@
-use crate::m1::uart::UART;
+use crate::m1::uart::{BaudRate, UART};
 use core::fmt::Write;
 use log::{Level, Metadata, Record};
 use log::{LevelFilter, SetLoggerError};
@@ -8,10 +8,10 @@ struct UARTLogger {
 }
 
 impl UARTLogger {
-    fn configure(&mut self, baud_rate: u32) {
-        let uart = &UART::INSTANCE;
+    fn configure(&mut self, baud_rate: u32) -> BaudRate {
+        let uart = &UART::get_console();
 
-        uart.init(baud_rate);
+        uart.init(baud_rate).expect("Invalid UART baud rate")
     }
 }
 
@@ -22,7 +22,7 @@ impl log::Log for UARTLogger {
 
     fn log(&self, record: &Record) {
         if self.enabled(record.metadata()) {
-            let mut uart = UART::INSTANCE;
+            let mut uart = UART::get_console();
             writeln!(&mut uart, "{} - {}\r", record.level(), record.args()).ok();
         }
     }
@@ -33,9 +33,15 @@ impl log::Log for UARTLogger {
 static mut LOGGER: UARTLogger = UARTLogger { level: Level::Info };
 
 pub fn init(baud_rate: u32) -> Result<(), SetLoggerError> {
-    unsafe {
-        LOGGER.configure(baud_rate);
+    let baud_rate = unsafe {
+        let baud_rate = LOGGER.configure(baud_rate);
 
-        log::set_logger(&LOGGER).map(|()| log::set_max_level(LevelFilter::Trace))
-    }
+        log::set_logger(&LOGGER).map(|()| log::set_max_level(LevelFilter::Trace))?;
+
+        baud_rate
+    };
+
+    log::info!("UART running at {}", baud_rate);
+
+    Ok(())
 }
@@ -1,3 +1,4 @@
+use adt::Adt;
 use register::mmio::ReadWrite;
 
 #[allow(non_snake_case)]
@@ -18,32 +19,134 @@ pub struct UARTRegister {
 
 unsafe impl core::marker::Sync for UART {}
 
+#[derive(Clone, Copy)]
 pub struct UART {
     pub register_base: *const UARTRegister,
 }
 
 pub const UART_CLOCK: u32 = 24000000;
 
+pub const UART_COMPATIBLE: &str = "uart-1,samsung";
+
+/// UART used for logging and the proxy.
+static mut CONSOLE: UART = UART::INSTANCE;
+
 pub const UTRSTAT_RXDR: u32 = 1 << 0;
 pub const UTRSTAT_TXFE: u32 = 1 << 1;
 pub const UTRSTAT_TXE: u32 = 1 << 2;
 pub const UTRSTAT_TIMEOUT: u32 = 1 << 3;
 
+/// Maximum deviation from a requested baud rate, in hundredths of a percent.
+pub const MAX_BAUD_RATE_ERROR: u32 = 300;
+
+/// Divisor configuration for a requested baud rate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BaudRate {
+    pub divisor: u32,
+    /// Rate actually produced by the divisor.
+    pub achieved: u32,
+    /// Deviation from the requested rate, in hundredths of a percent.
+    pub error: i32,
+}
+
+impl BaudRate {
+    pub fn from_divisor(divisor: u32) -> u32 {
+        UART_CLOCK / (16 * (divisor + 1))
+    }
+
+    /// Compute the divisor for a rate, None if it is out of the reachable range.
+    pub fn compute(baud_rate: u32) -> Option<Self> {
+        if baud_rate == 0 {
+            return None;
+        }
+
+        let divisor = ((UART_CLOCK / baud_rate + 7) / 16).checked_sub(1)?;
+        let achieved = Self::from_divisor(divisor);
+        let error = (i64::from(achieved) - i64::from(baud_rate)) * 10000 / i64::from(baud_rate);
+
+        Some(BaudRate {
+            divisor,
+            achieved,
+            error: error as i32,
+        })
+    }
+}
+
+impl core::fmt::Display for BaudRate {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let sign = if self.error < 0 { "-" } else { "" };
+        let error = self.error.abs();
+
+        write!(
+            f,
+            "{} baud ({}{}.{:02}% error)",
+            self.achieved,
+            sign,
+            error / 100,
+            error % 100
+        )
+    }
+}
+
 impl UART {
+    /// Console set up by iBoot on the Mac mini, used until the device tree gives the real one.
     pub const INSTANCE: Self = UART {
         register_base: 0x0002_3520_0000 as *const UARTRegister,
     };
 
-    pub fn init(&self, baud_rate: u32) {
-        self.set_baudrate(baud_rate);
+    pub const fn new(register_base: u64) -> Self {
+        UART {
+            register_base: register_base as *const UARTRegister,
+        }
+    }
+
+    /// Create the UART described by a device tree node, like `/arm-io/uart0`.
+    ///
+    /// None if the node doesn't exist, isn't a compatible UART or has no registers.
+    pub fn from_adt(adt: &Adt, path: &str) -> Option<Self> {
+        if !adt.find_node(path)?.is_compatible(UART_COMPATIBLE) {
+            return None;
+        }
+
+        Some(Self::new(adt.get_reg(path, 0)?.address))
     }
 
-    pub fn set_baudrate(&self, baud_rate: u32) {
+    pub fn get_console() -> Self {
+        unsafe { CONSOLE }
+    }
+
+    /// Switch the console to another UART, keeping the current baud rate.
+    pub fn set_console(uart: Self) -> Option<BaudRate> {
+        let baud_rate = uart.init(Self::get_console().get_baudrate())?;
+
+        unsafe { CONSOLE = uart };
+
+        Some(baud_rate)
+    }
+
+    pub fn init(&self, baud_rate: u32) -> Option<BaudRate> {
+        self.set_baudrate(baud_rate)
+    }
+
+    /// Switch to a new baud rate once everything was transmitted, returning the configuration used.
+    ///
+    /// Nothing is changed if the rate cannot be reached.
+    pub fn set_baudrate(&self, baud_rate: u32) -> Option<BaudRate> {
+        let baud_rate = BaudRate::compute(baud_rate)?;
+
         self.wait_status(UTRSTAT_TXE);
 
         let ubr_div = unsafe { &((*self.register_base).UBRDIV) };
 
-        ubr_div.set(((UART_CLOCK / baud_rate + 7) / 16) - 1);
+        ubr_div.set(baud_rate.divisor);
+
+        Some(baud_rate)
+    }
+
+    pub fn get_baudrate(&self) -> u32 {
+        let ubr_div = unsafe { &((*self.register_base).UBRDIV) };
+
+        BaudRate::from_divisor(ubr_div.get())
     }
 
     pub fn wait_status(&self, val: u32) {
@@ -80,6 +183,17 @@ impl UART {
         let receive_reg = unsafe { &((*self.register_base).URXH) };
         receive_reg.get() as u8
     }
+
+    pub fn try_get_byte(&self) -> Option<u8> {
+        let utr_stat = unsafe { &((*self.register_base).UTRSTAT) };
+
+        if (utr_stat.get() & UTRSTAT_RXDR) == 0 {
+            return None;
+        }
+
+        let receive_reg = unsafe { &((*self.register_base).URXH) };
+        Some(receive_reg.get() as u8)
+    }
 }
 
 impl core::fmt::Write for UART {
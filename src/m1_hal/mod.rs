@@ -1,5 +1,9 @@
-use crate::m1::uart::UART;
+use crate::m1::uart::{BaudRate, MAX_BAUD_RATE_ERROR, UART};
+use crate::utils::{get_counter, get_counter_frequency};
 use embedded_hal::serial::{Read, Write};
+use embedded_hal::timer::CountDown;
+use m1n1_protocol::ProxyTransport;
+use void::Void;
 
 impl Write<u8> for UART {
     // No error possible
@@ -21,7 +25,7 @@ impl Read<u8> for UART {
     type Error = ();
 
     fn read(&mut self) -> nb::Result<u8, Self::Error> {
-        Ok(self.get_byte())
+        self.try_get_byte().ok_or(nb::Error::WouldBlock)
     }
 }
 
@@ -84,3 +88,69 @@ impl Write<&[u8]> for UART {
         Ok(())
     }
 }
+
+/// Countdown based on the architectural counter, counting in microseconds.
+#[derive(Default)]
+pub struct CounterTimer {
+    deadline: u64,
+}
+
+impl CountDown for CounterTimer {
+    type Time = u64;
+
+    fn start<T: Into<Self::Time>>(&mut self, count: T) {
+        self.deadline = get_counter() + count.into() * get_counter_frequency() / 1_000_000;
+    }
+
+    fn wait(&mut self) -> nb::Result<(), Void> {
+        if get_counter() < self.deadline {
+            return Err(nb::Error::WouldBlock);
+        }
+
+        Ok(())
+    }
+}
+
+impl ProxyTransport for UART {
+    // No error possible
+    type Error = ();
+
+    fn read_byte(&mut self, timeout: u64) -> Result<Option<u8>, Self::Error> {
+        let mut timer = CounterTimer::default();
+
+        timer.start(timeout);
+
+        loop {
+            if let Some(value) = self.try_get_byte() {
+                return Ok(Some(value));
+            }
+
+            if timer.wait().is_ok() {
+                return Ok(None);
+            }
+        }
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<(), Self::Error> {
+        self.write_data(data);
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        self.wait_transmit();
+        Ok(())
+    }
+
+    fn get_baudrate(&self) -> Option<u32> {
+        Some(UART::get_baudrate(self))
+    }
+
+    fn set_baudrate(&mut self, baud_rate: u32) -> Option<u32> {
+        match BaudRate::compute(baud_rate) {
+            Some(config) if config.error.abs() as u32 <= MAX_BAUD_RATE_ERROR => {
+                UART::set_baudrate(self, baud_rate).map(|config| config.achieved)
+            }
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,244 @@
+//! m1n1 proxy opcodes handler
+
+use core::convert::TryFrom;
+
+use crate::boot_args;
+use crate::cache;
+use crate::exception_vectors;
+use crate::linux;
+use crate::utils;
+use m1n1_protocol::{ProxyOpcode, ProxyReply, ProxyRequest, ProxyStatus};
+
+use log::error;
+
+fn get_base() -> u64 {
+    crate::rt::_start as *const () as u64
+}
+
+/// Decompress between two physical buffers, returning the decompressed size or -1 on error like m1n1.
+fn decompress_buffer(
+    name: &str,
+    decompress: fn(&[u8], &mut [u8]) -> decompress::Result<usize>,
+    args: &[u64],
+) -> u64 {
+    let input = unsafe { core::slice::from_raw_parts(args[0] as *const u8, args[1] as usize) };
+    let output = unsafe { core::slice::from_raw_parts_mut(args[2] as *mut u8, args[3] as usize) };
+
+    match decompress(input, output) {
+        Ok(size) => size as u64,
+        Err(error) => {
+            error!("{} decompression failed: {:?}", name, error);
+
+            u64::MAX
+        }
+    }
+}
+
+/// Report the result of a kboot operation, 0 on success or -1 on error like m1n1.
+fn kboot_result(name: &str, result: kboot::Result<()>) -> u64 {
+    match result {
+        Ok(()) => 0,
+        Err(error) => {
+            error!("kboot {} failed: {:?}", name, error);
+
+            u64::MAX
+        }
+    }
+}
+
+pub fn handle_proxy(request: &ProxyRequest) -> ProxyReply {
+    let mut reply = ProxyReply {
+        opcode: request.opcode,
+        status: ProxyStatus::Ok,
+        return_value: 0,
+    };
+
+    let opcode = match ProxyOpcode::try_from(request.opcode) {
+        Ok(opcode) => opcode,
+        Err(_) => {
+            error!("Unknown proxy opcode: 0x{:x}", request.opcode);
+
+            reply.status = ProxyStatus::BadCommand;
+            return reply;
+        }
+    };
+
+    let args = &request.args;
+
+    // NOTE: All register accesses are done with the exact width requested.
+    reply.return_value = match opcode {
+        ProxyOpcode::Nop | ProxyOpcode::Exit => 0,
+        ProxyOpcode::Call => {
+            let arguments = [args[1], args[2], args[3], args[4], args[5]];
+
+            match unsafe { exception_vectors::guarded_call(args[0], &arguments) } {
+                Ok(value) => value,
+                Err(esr) => {
+                    error!("Exception during call to 0x{:x}", args[0]);
+
+                    reply.status = ProxyStatus::Exception;
+                    esr
+                }
+            }
+        }
+        ProxyOpcode::GetBootArgs => boot_args::get_address(),
+        ProxyOpcode::GetBase => get_base(),
+        ProxyOpcode::SetExcGuard => exception_vectors::set_exception_guard(args[0]),
+        ProxyOpcode::GetExcCount => exception_vectors::get_exception_count(),
+        ProxyOpcode::Udelay => {
+            utils::udelay(args[0]);
+            0
+        }
+        ProxyOpcode::Write64 => {
+            unsafe { utils::mmio_write::<u64>(args[0], args[1]) };
+            0
+        }
+        ProxyOpcode::Write32 => {
+            unsafe { utils::mmio_write::<u32>(args[0], args[1] as u32) };
+            0
+        }
+        ProxyOpcode::Write16 => {
+            unsafe { utils::mmio_write::<u16>(args[0], args[1] as u16) };
+            0
+        }
+        ProxyOpcode::Write8 => {
+            unsafe { utils::mmio_write::<u8>(args[0], args[1] as u8) };
+            0
+        }
+        ProxyOpcode::Read64 => unsafe { utils::mmio_read::<u64>(args[0]) },
+        ProxyOpcode::Read32 => u64::from(unsafe { utils::mmio_read::<u32>(args[0]) }),
+        ProxyOpcode::Read16 => u64::from(unsafe { utils::mmio_read::<u16>(args[0]) }),
+        ProxyOpcode::Read8 => u64::from(unsafe { utils::mmio_read::<u8>(args[0]) }),
+        ProxyOpcode::Set64 => unsafe { utils::mmio_set::<u64>(args[0], args[1]) },
+        ProxyOpcode::Set32 => u64::from(unsafe { utils::mmio_set::<u32>(args[0], args[1] as u32) }),
+        ProxyOpcode::Set16 => u64::from(unsafe { utils::mmio_set::<u16>(args[0], args[1] as u16) }),
+        ProxyOpcode::Set8 => u64::from(unsafe { utils::mmio_set::<u8>(args[0], args[1] as u8) }),
+        ProxyOpcode::Clear64 => unsafe { utils::mmio_clear::<u64>(args[0], args[1]) },
+        ProxyOpcode::Clear32 => {
+            u64::from(unsafe { utils::mmio_clear::<u32>(args[0], args[1] as u32) })
+        }
+        ProxyOpcode::Clear16 => {
+            u64::from(unsafe { utils::mmio_clear::<u16>(args[0], args[1] as u16) })
+        }
+        ProxyOpcode::Clear8 => {
+            u64::from(unsafe { utils::mmio_clear::<u8>(args[0], args[1] as u8) })
+        }
+        ProxyOpcode::Mask64 => unsafe { utils::mmio_mask::<u64>(args[0], args[1], args[2]) },
+        ProxyOpcode::Mask32 => {
+            u64::from(unsafe { utils::mmio_mask::<u32>(args[0], args[1] as u32, args[2] as u32) })
+        }
+        ProxyOpcode::Mask16 => {
+            u64::from(unsafe { utils::mmio_mask::<u16>(args[0], args[1] as u16, args[2] as u16) })
+        }
+        ProxyOpcode::Mask8 => {
+            u64::from(unsafe { utils::mmio_mask::<u8>(args[0], args[1] as u8, args[2] as u8) })
+        }
+        ProxyOpcode::WriteRead64 => unsafe { utils::mmio_write_read::<u64>(args[0], args[1]) },
+        ProxyOpcode::WriteRead32 => {
+            u64::from(unsafe { utils::mmio_write_read::<u32>(args[0], args[1] as u32) })
+        }
+        ProxyOpcode::WriteRead16 => {
+            u64::from(unsafe { utils::mmio_write_read::<u16>(args[0], args[1] as u16) })
+        }
+        ProxyOpcode::WriteRead8 => {
+            u64::from(unsafe { utils::mmio_write_read::<u8>(args[0], args[1] as u8) })
+        }
+        ProxyOpcode::Memcpy64 => {
+            unsafe { utils::mmio_copy::<u64>(args[0], args[1], args[2]) };
+            0
+        }
+        ProxyOpcode::Memcpy32 => {
+            unsafe { utils::mmio_copy::<u32>(args[0], args[1], args[2]) };
+            0
+        }
+        ProxyOpcode::Memcpy16 => {
+            unsafe { utils::mmio_copy::<u16>(args[0], args[1], args[2]) };
+            0
+        }
+        ProxyOpcode::Memcpy8 => {
+            unsafe { core::ptr::copy(args[1] as *const u8, args[0] as *mut u8, args[2] as usize) };
+            0
+        }
+        ProxyOpcode::Memset64 => {
+            unsafe { utils::mmio_fill::<u64>(args[0], args[1], args[2]) };
+            0
+        }
+        ProxyOpcode::Memset32 => {
+            unsafe { utils::mmio_fill::<u32>(args[0], args[1] as u32, args[2]) };
+            0
+        }
+        ProxyOpcode::Memset16 => {
+            unsafe { utils::mmio_fill::<u16>(args[0], args[1] as u16, args[2]) };
+            0
+        }
+        ProxyOpcode::Memset8 => {
+            unsafe { core::ptr::write_bytes(args[0] as *mut u8, args[1] as u8, args[2] as usize) };
+            0
+        }
+        ProxyOpcode::IcIalluis => {
+            cache::ic_ialluis();
+            0
+        }
+        ProxyOpcode::IcIallu => {
+            cache::ic_iallu();
+            0
+        }
+        ProxyOpcode::IcIvau => {
+            unsafe { cache::ic_ivau_range(args[0], args[1]) };
+            0
+        }
+        ProxyOpcode::DcIvac => {
+            unsafe { cache::dc_ivac_range(args[0], args[1]) };
+            0
+        }
+        ProxyOpcode::DcIsw => {
+            unsafe { cache::dc_isw(args[0]) };
+            0
+        }
+        ProxyOpcode::DcCsw => {
+            unsafe { cache::dc_csw(args[0]) };
+            0
+        }
+        ProxyOpcode::DcCisw => {
+            unsafe { cache::dc_cisw(args[0]) };
+            0
+        }
+        ProxyOpcode::DcZva => {
+            unsafe { cache::dc_zva_range(args[0], args[1]) };
+            0
+        }
+        ProxyOpcode::DcCvac => {
+            unsafe { cache::dc_cvac_range(args[0], args[1]) };
+            0
+        }
+        ProxyOpcode::DcCvau => {
+            unsafe { cache::dc_cvau_range(args[0], args[1]) };
+            0
+        }
+        ProxyOpcode::DcCivac => {
+            unsafe { cache::dc_civac_range(args[0], args[1]) };
+            0
+        }
+        ProxyOpcode::XzDec => decompress_buffer("xz", decompress::xz_decompress, args),
+        ProxyOpcode::GzDec => decompress_buffer("gzip", decompress::gzip_decompress, args),
+        ProxyOpcode::KbootBoot => kboot_result("boot", unsafe { linux::boot(args[0]) }),
+        ProxyOpcode::KbootSetChosen => {
+            kboot_result("set_chosen", unsafe { linux::set_chosen(args[0], args[1]) })
+        }
+        ProxyOpcode::KbootSetInitrd => {
+            linux::set_initrd(args[0], args[1]);
+            0
+        }
+        ProxyOpcode::KbootPrepareDt => {
+            kboot_result("prepare_dt", unsafe { linux::prepare_dt(args[0]) })
+        }
+        _ => {
+            error!("Unhandled proxy opcode: {:?}", opcode);
+
+            reply.status = ProxyStatus::BadCommand;
+            0
+        }
+    };
+
+    reply
+}
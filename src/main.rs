@@ -5,9 +5,16 @@
 
 extern crate alloc;
 
-use log::info;
+use log::{info, warn};
 
+use crate::boot_args::BootArgs;
+use crate::m1::uart::UART;
+
+mod boot;
+mod boot_args;
+mod cache;
 mod exception_vectors;
+mod linux;
 mod logger;
 mod m1;
 mod m1_hal;
@@ -19,10 +26,69 @@ mod utils;
 
 entry!(main);
 
+/// Console used when the command line doesn't select one with `m1saka.console=`.
+const DEFAULT_CONSOLE_PATH: &str = "/arm-io/uart0";
+
+/// Move the console to the UART selected on the command line, as found in the device tree.
+fn setup_console(boot_args: &BootArgs) {
+    let path = boot_args
+        .get_command_line()
+        .split(' ')
+        .find_map(|argument| argument.strip_prefix("m1saka.console="))
+        .unwrap_or(DEFAULT_CONSOLE_PATH);
+
+    let uart = match boot_args.get_device_tree() {
+        Some(adt) => UART::from_adt(&adt, path),
+        None => None,
+    };
+
+    let uart = match uart {
+        Some(uart) => uart,
+        None => {
+            warn!("Console {} not found, keeping the early one", path);
+
+            return;
+        }
+    };
+
+    info!("Moving the console to {}", path);
+
+    match UART::set_console(uart) {
+        Some(baud_rate) => info!("Console running at {}", baud_rate),
+        None => warn!("Cannot configure console {}", path),
+    }
+}
+
 fn main() {
     logger::init(1_500_000).expect("Logger init failed");
 
     info!("Hello I'm m1saka say m1saka");
 
+    match boot_args::get() {
+        Some(boot_args) => {
+            info!(
+                "Boot arguments revision {}, {} bytes of memory at 0x{:x}, command line \"{}\"",
+                boot_args.revision,
+                boot_args.mem_size_actual,
+                boot_args.phys_base,
+                boot_args.get_command_line()
+            );
+
+            match boot_args.get_device_tree() {
+                Some(adt) => info!(
+                    "Device tree model \"{}\"",
+                    adt.get_root()
+                        .get_property("model")
+                        .map(|property| property.as_str())
+                        .unwrap_or("")
+                ),
+                None => info!("Invalid device tree"),
+            }
+
+            setup_console(boot_args);
+        }
+        None => info!("No boot arguments"),
+    }
+
     m1n1::proxy_handler();
 }
@@ -8,6 +8,7 @@ use cortex_a::barrier::*;
 
 use core::fmt::Write;
 
+use crate::cache;
 use crate::m1::uart::UART;
 
 use alloc::alloc::Layout;
@@ -184,13 +185,11 @@ unsafe fn get_sctlr() -> u64 {
 
 unsafe fn set_sctlr(new_sctlr: u64) {
     asm!("msr sctlr_el2, {sctlr}", sctlr = in(reg) new_sctlr, options(nostack));
-    asm!("ic iallu");
-    dsb(SY);
-    isb(SY);
+    cache::ic_iallu();
 }
 
 pub unsafe fn setup() {
-    let mut uart = UART::INSTANCE;
+    let mut uart = UART::get_console();
 
     // configure level 0
     for (i, entry) in (&mut LVL0_TABLE.entries[..]).iter_mut().enumerate() {
@@ -268,3 +267,13 @@ pub unsafe fn setup() {
 
     writeln!(&mut uart, "MMU on!").ok();
 }
+
+/// Disable the MMU and caches, then clean the data caches so memory holds everything written.
+pub unsafe fn shutdown() {
+    let sctrl_new = get_sctlr() & !((1 << 12) | (1 << 2) | (1 << 0));
+
+    set_sctlr(sctrl_new);
+
+    cache::dcache_clean_invalidate_all();
+    cache::ic_iallu();
+}
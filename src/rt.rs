@@ -1,8 +1,12 @@
 #![allow(clippy::empty_loop)]
 
 use core::fmt::Write;
+use core::ops::Range;
 use core::panic::PanicInfo;
 use core::ptr;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use elf::RelocationError;
 
 use crate::m1::uart::UART;
 
@@ -25,7 +29,7 @@ macro_rules! entry {
 
 #[panic_handler]
 fn panic(panic_info: &PanicInfo<'_>) -> ! {
-    let mut uart = UART::INSTANCE;
+    let mut uart = UART::get_console();
 
     writeln!(&mut uart, "PANIC: {}\r", panic_info).ok();
 
@@ -34,7 +38,7 @@ fn panic(panic_info: &PanicInfo<'_>) -> ! {
 
 #[alloc_error_handler]
 fn allocation_error(_: core::alloc::Layout) -> ! {
-    let mut uart = UART::INSTANCE;
+    let mut uart = UART::get_console();
 
     writeln!(&mut uart, "Memory exhausting").ok();
 
@@ -46,6 +50,10 @@ extern "C" {
     static mut __end_bss__: u8;
     static _stack_bottom: u8;
     static _stack_top: u8;
+    static __init_array_start__: u8;
+    static __init_array_end__: u8;
+    static __fini_array_start__: u8;
+    static __fini_array_end__: u8;
 }
 
 #[link_section = ".text.crt0"]
@@ -61,137 +69,152 @@ pub unsafe extern "C" fn _start() -> ! {
     )
 }
 
+/// Memory used by the running payload, from its code to the top of its stack, heap included.
+pub fn get_image_range() -> Range<u64> {
+    unsafe { _start as usize as u64..&_stack_top as *const u8 as u64 }
+}
+
+/// x0 to x3 as set by the previous stage, iBoot passes its boot arguments in x0.
+static ENTRY_REGISTERS: [AtomicU64; 4] = [
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+];
+
+pub fn get_entry_registers() -> [u64; 4] {
+    let mut registers = [0; 4];
+
+    for (register, value) in registers.iter_mut().zip(ENTRY_REGISTERS.iter()) {
+        *register = value.load(Ordering::Relaxed);
+    }
+
+    registers
+}
+
 #[naked]
 #[no_mangle]
 pub unsafe extern "C" fn trampoline() -> ! {
+    // x19 to x22 are preserved by the calls below and keep the entry registers.
     asm!(
         "
+        mov x19, x0
+        mov x20, x1
+        mov x21, x2
+        mov x22, x3
         adrp x0, _stack_top
         add x0, x0, #:lo12:_stack_top
         mov sp, x0
         adrp x0, _start
-        bl relocate_self
+        adrp x1, __bss_start__
+        add x1, x1, #:lo12:__bss_start__
+        sub x1, x1, x0
+        bl relocate_self_or_halt
 
         adrp x0, __bss_start__
         add x0, x0, #:lo12:__bss_start__
         adrp x1, __bss_end__
         add x1, x1, #:lo12:__bss_end__
         bl clean_bss
+        mov x0, x19
+        mov x1, x20
+        mov x2, x21
+        mov x3, x22
         bl _start_with_stack
         ",
         options(noreturn),
     )
 }
 
-const DT_NULL: isize = 0;
-const DT_RELA: isize = 7;
-const DT_RELAENT: isize = 9;
-const DT_RELACOUNT: isize = 0x6ffffff9;
-const DT_REL: isize = 17;
-const DT_RELENT: isize = 19;
-const DT_RELCOUNT: isize = 0x6ffffffa;
+/// Apply our own relocations, the image being loaded at `aslr_base` with `image_size` bytes
+/// before the bss.
+pub unsafe fn relocate_self(aslr_base: *mut u8, image_size: usize) -> Result<(), RelocationError> {
+    let image = core::slice::from_raw_parts_mut(aslr_base, image_size);
+    let dynamic = *(aslr_base.offset(4) as *const u32);
 
-const R_AARCH64_RELATIVE: usize = 0x403;
-
-#[repr(C)]
-#[derive(Debug)]
-struct ElfDyn {
-    tag: isize,
-    val: usize,
+    elf::relocate(image, 0, aslr_base as u64, u64::from(dynamic))
 }
 
-#[repr(C)]
-struct ElfRel {
-    offset: usize,
-    info: usize,
-}
+#[no_mangle]
+unsafe extern "C" fn relocate_self_or_halt(aslr_base: *mut u8, image_size: usize) {
+    if let Err(error) = relocate_self(aslr_base, image_size) {
+        report_relocation_error(error);
 
-#[repr(C)]
-struct ElfRela {
-    offset: usize,
-    info: usize,
-    addend: isize,
+        loop {}
+    }
 }
 
-#[no_mangle]
-pub unsafe extern "C" fn relocate_self(aslr_base: *mut u8) -> u32 {
-    let mut dynamic =
-        aslr_base.offset(*(aslr_base.offset(4) as *const u32) as isize) as *mut ElfDyn;
-
-    let mut rela_offset = None;
-    let mut rela_entry_size = 0;
-    let mut rela_count = 0;
-
-    let mut rel_offset = None;
-    let mut rel_entry_size = 0;
-    let mut rel_count = 0;
-
-    while (*dynamic).tag != DT_NULL {
-        match (*dynamic).tag {
-            DT_RELA => {
-                rela_offset = Some((*dynamic).val);
-            }
-            DT_RELAENT => {
-                rela_entry_size = (*dynamic).val;
-            }
-            DT_REL => {
-                rel_offset = Some((*dynamic).val);
-            }
-            DT_RELENT => {
-                rel_entry_size = (*dynamic).val;
-            }
-            DT_RELACOUNT => {
-                rela_count = (*dynamic).val;
-            }
-            DT_RELCOUNT => {
-                rel_count = (*dynamic).val;
-            }
-            _ => {}
-        }
-        dynamic = dynamic.offset(1);
+fn write_hex(uart: &UART, value: u32) {
+    uart.write_data(b"0x");
+
+    for shift in (0..8).rev() {
+        let digit = ((value >> (shift * 4)) & 0xF) as u8;
+
+        uart.put_byte(if digit < 10 {
+            b'0' + digit
+        } else {
+            b'a' + digit - 10
+        });
     }
+}
 
-    if let Some(rela_offset) = rela_offset {
-        if rela_entry_size != core::mem::size_of::<ElfRela>() {
-            return 2;
-        }
-        let rela_base = (aslr_base.add(rela_offset)) as *mut ElfRela;
+/// Report a failure to relocate on the console.
+///
+/// Nothing relying on pointers stored in static data can run yet, such as formatting: the error
+/// is written as its kind followed by its value.
+fn report_relocation_error(error: RelocationError) {
+    let (kind, value) = match error {
+        RelocationError::InvalidRelaEntrySize => (1, 0),
+        RelocationError::InvalidRelEntrySize => (2, 0),
+        RelocationError::InvalidRelrEntrySize => (3, 0),
+        RelocationError::InvalidSymbolEntrySize => (4, 0),
+        RelocationError::UnsupportedRelocation(relocation_type) => (5, relocation_type),
+        RelocationError::UndefinedSymbol(index) => (6, index),
+        RelocationError::OutOfBounds => (7, 0),
+    };
+
+    let uart = UART::get_console();
 
-        for i in 0..rela_count {
-            let rela = rela_base.add(i);
+    uart.write_data(b"Relocation failed: ");
+    write_hex(&uart, kind);
+    uart.write_data(b" ");
+    write_hex(&uart, value);
+    uart.write_data(b"\r\n");
+}
 
-            let reloc_type = (*rela).info & 0xffffffff;
+type ArrayFunction = unsafe extern "C" fn();
 
-            if reloc_type == R_AARCH64_RELATIVE {
-                *(aslr_base.add((*rela).offset) as *mut *mut ()) =
-                    aslr_base.offset((*rela).addend) as _;
-            } else {
-                return 4;
-            }
-        }
-    }
+/// Set once the destructors ran, both leaving the proxy and booting another image run them.
+static FINI_ARRAY_DONE: AtomicBool = AtomicBool::new(false);
 
-    if let Some(rel_offset) = rel_offset {
-        if rel_entry_size != core::mem::size_of::<ElfRel>() {
-            return 3;
-        }
+unsafe fn get_function_array(start: &u8, end: &u8) -> &'static [ArrayFunction] {
+    let start = start as *const u8 as *const ArrayFunction;
+    let end = end as *const u8 as *const ArrayFunction;
 
-        let rel_base = (aslr_base.add(rel_offset)) as *mut ElfRel;
+    core::slice::from_raw_parts(start, end.offset_from(start) as usize)
+}
 
-        for i in 0..rel_count {
-            let rel = rel_base.add(i);
+/// Run the constructors of `.init_array`, sorted by priority by the linker script.
+unsafe fn run_init_array() {
+    for constructor in get_function_array(&__init_array_start__, &__init_array_end__) {
+        constructor();
+    }
+}
 
-            let reloc_type = (*rel).info & 0xffffffff;
+/// Run the destructors of `.fini_array` in reverse order, only the first time this is called.
+pub fn run_fini_array() {
+    if FINI_ARRAY_DONE.swap(true, Ordering::Relaxed) {
+        return;
+    }
 
-            if let R_AARCH64_RELATIVE = reloc_type {
-                let ptr = aslr_base.add((*rel).offset) as *mut usize;
-                *ptr += aslr_base as usize;
-            } else {
-                return 4;
-            }
+    unsafe {
+        for destructor in get_function_array(&__fini_array_start__, &__fini_array_end__)
+            .iter()
+            .rev()
+        {
+            destructor();
         }
     }
-    0
 }
 
 #[no_mangle]
@@ -204,7 +227,11 @@ unsafe extern "C" fn clean_bss(start_bss: *mut u8, end_bss: *mut u8) {
 }
 
 #[no_mangle]
-pub unsafe extern "C" fn _start_with_stack() -> ! {
+pub unsafe extern "C" fn _start_with_stack(x0: u64, x1: u64, x2: u64, x3: u64) -> ! {
+    for (register, value) in ENTRY_REGISTERS.iter().zip([x0, x1, x2, x3].iter()) {
+        register.store(*value, Ordering::Relaxed);
+    }
+
     memory::setup();
     exception_vectors::setup();
     mmu::setup();
@@ -214,7 +241,11 @@ pub unsafe extern "C" fn _start_with_stack() -> ! {
         fn main() -> ();
     }
 
+    run_init_array();
+
     main();
 
+    run_fini_array();
+
     loop {}
 }
@@ -1,4 +1,4 @@
-use core::ops::{BitAnd, Not};
+use core::ops::{BitAnd, BitOr, Not};
 use num_traits::Num;
 
 #[inline]
@@ -20,3 +20,103 @@ pub fn get_current_el() -> u32 {
 
     current_el >> 2
 }
+
+#[inline]
+pub fn get_counter() -> u64 {
+    let counter: u64;
+    unsafe {
+        asm!("isb", "mrs {cnt}, cntpct_el0", cnt = out(reg) counter, options(nostack));
+    }
+
+    counter
+}
+
+#[inline]
+pub fn get_counter_frequency() -> u64 {
+    let frequency: u64;
+    unsafe {
+        asm!("mrs {freq}, cntfrq_el0", freq = out(reg) frequency, options(nostack));
+    }
+
+    frequency
+}
+
+pub fn udelay(microseconds: u64) {
+    let start = get_counter();
+    let frequency = get_counter_frequency();
+
+    // Whole seconds and the remainder are scaled apart so host supplied delays can't overflow.
+    let ticks = (microseconds / 1_000_000)
+        .saturating_mul(frequency)
+        .saturating_add(microseconds % 1_000_000 * frequency / 1_000_000);
+
+    while get_counter() - start < ticks {}
+}
+
+#[inline]
+pub unsafe fn mmio_read<T: Copy>(address: u64) -> T {
+    core::ptr::read_volatile(address as *const T)
+}
+
+#[inline]
+pub unsafe fn mmio_write<T: Copy>(address: u64, value: T) {
+    core::ptr::write_volatile(address as *mut T, value)
+}
+
+#[inline]
+pub unsafe fn mmio_set<T: Copy + BitOr<Output = T>>(address: u64, set: T) -> T {
+    let value = mmio_read::<T>(address) | set;
+    mmio_write(address, value);
+
+    value
+}
+
+#[inline]
+pub unsafe fn mmio_clear<T: Copy + BitAnd<Output = T> + Not<Output = T>>(
+    address: u64,
+    clear: T,
+) -> T {
+    let value = mmio_read::<T>(address) & !clear;
+    mmio_write(address, value);
+
+    value
+}
+
+#[inline]
+pub unsafe fn mmio_mask<T: Copy + BitAnd<Output = T> + BitOr<Output = T> + Not<Output = T>>(
+    address: u64,
+    clear: T,
+    set: T,
+) -> T {
+    let value = (mmio_read::<T>(address) & !clear) | set;
+    mmio_write(address, value);
+
+    value
+}
+
+#[inline]
+pub unsafe fn mmio_write_read<T: Copy>(address: u64, value: T) -> T {
+    mmio_write(address, value);
+
+    mmio_read(address)
+}
+
+/// Copy `size` bytes from `source` to `destination` using volatile accesses of `T` width.
+pub unsafe fn mmio_copy<T: Copy>(destination: u64, source: u64, size: u64) {
+    let element_size = core::mem::size_of::<T>() as u64;
+
+    for i in 0..(size / element_size) {
+        let offset = i * element_size;
+
+        mmio_write::<T>(destination + offset, mmio_read::<T>(source + offset));
+    }
+}
+
+/// Fill `size` bytes at `destination` with `value` using volatile accesses of `T` width.
+pub unsafe fn mmio_fill<T: Copy>(destination: u64, value: T, size: u64) {
+    let element_size = core::mem::size_of::<T>() as u64;
+
+    for i in 0..(size / element_size) {
+        mmio_write::<T>(destination + i * element_size, value);
+    }
+}